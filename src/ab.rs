@@ -1,3 +1,17 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc,
+};
+
+use crate::{
+    chess::{retro::DtmTables, syzygy::Tablebases},
+    tt::{Bound, Entry, TranspositionTable},
+};
+
+/// Mixed into the key of min nodes, since a position is scored differently depending on whose
+/// turn it is to maximize.
+const MIN_NODE_KEY: u64 = 0x2d358dccaa6c78a5;
+
 pub trait AlphaBeta {
     type ItemIterator<'a>: Iterator<Item = (Self, Self::Data)> + 'a
     where
@@ -6,10 +20,30 @@ pub trait AlphaBeta {
     type Data: Clone;
 
     fn is_terminal(&self) -> bool;
+    /// The static value of the node. This and every other value in the search is from the
+    /// maximizing side's point of view.
     fn score(&self) -> f32;
     fn children(&self) -> Self::ItemIterator<'_>
     where
         Self: Sized;
+
+    /// A hash identifying the position. Nodes without one are never stored in the
+    /// transposition table.
+    fn key(&self) -> Option<u64> {
+        None
+    }
+
+    /// Drawn no matter what follows, by a rule that doesn't depend on history such as the
+    /// fifty-move rule.
+    fn is_draw(&self) -> bool {
+        false
+    }
+
+    /// The exact value of the node from endgame tables, `ply` moves from the root. Drawn
+    /// positions are worth [`SearchSettings::draw_value`].
+    fn tablebase_value(&self, _settings: &SearchSettings, _ply: u64) -> Option<f32> {
+        None
+    }
 }
 
 pub struct AlphaBetaResult<D> {
@@ -22,6 +56,24 @@ pub struct SearchSettings {
     pub divide: bool,
     pub ab_prune: bool,
     pub depth: u64,
+    /// When set, the search unwinds as soon as the flag is raised. The result of a stopped search
+    /// is meaningless and must be discarded by the caller.
+    pub stop: Option<Arc<AtomicBool>>,
+    pub tt: Option<Arc<TranspositionTable>>,
+    /// Counts every node visited. Threads searching together share one counter.
+    pub nodes: Option<Arc<AtomicU64>>,
+    /// Lazy SMP helper number, 0 for the main thread. Helpers start at a different root move so
+    /// the threads don't all walk the same tree.
+    pub helper: usize,
+    /// Keys of the positions played before the root, oldest first. A position seen twice there
+    /// is a draw the third time.
+    pub history: Vec<u64>,
+    /// How much worse than even a draw is for the side to move at the root.
+    pub contempt: f32,
+    pub tablebases: Option<Arc<Tablebases>>,
+    pub dtm_tables: Option<Arc<DtmTables>>,
+    /// Indices of the root's children worth searching, all of them when unset.
+    pub root_moves: Option<Vec<usize>>,
 }
 
 impl SearchSettings {
@@ -30,18 +82,106 @@ impl SearchSettings {
             divide: true,
             ab_prune: false,
             depth,
+            stop: None,
+            tt: None,
+            nodes: None,
+            helper: 0,
+            history: vec![],
+            contempt: 0.0,
+            tablebases: None,
+            dtm_tables: None,
+            root_moves: None,
         }
     }
+
+    /// A pruned search, as played by the engine.
+    pub fn search(depth: u64) -> Self {
+        Self {
+            divide: false,
+            ab_prune: true,
+            depth,
+            stop: None,
+            tt: None,
+            nodes: None,
+            helper: 0,
+            history: vec![],
+            contempt: 0.0,
+            tablebases: None,
+            dtm_tables: None,
+            root_moves: None,
+        }
+    }
+
+    /// The value of a draw `ply` moves from the root, at a node where the maximizing side is to
+    /// move if `max`. Contempt is for the side to move at the root, whichever side that is.
+    pub fn draw_value(&self, ply: u64, max: bool) -> f32 {
+        let root_max = max == (ply % 2 == 0);
+        if root_max {
+            -self.contempt
+        } else {
+            self.contempt
+        }
+    }
+
+    fn should_stop(&self) -> bool {
+        self.stop
+            .as_ref()
+            .is_some_and(|stop| stop.load(Ordering::Relaxed))
+    }
 }
 
 pub fn alphabeta<T: AlphaBeta>(
     node: &T,
     settings: &SearchSettings,
     depth: u64,
+    alpha: f32,
+    beta: f32,
+    max: bool,
+) -> AlphaBetaResult<T::Data> {
+    search(node, settings, &mut vec![], depth, alpha, beta, max)
+}
+
+/// Whether `key` repeats a position on the path from the root, or one played twice before it.
+fn is_repetition(settings: &SearchSettings, path: &[u64], key: u64) -> bool {
+    path.contains(&key) || settings.history.iter().filter(|&&k| k == key).count() >= 2
+}
+
+/// `path` holds the keys of the positions between the root and `node`.
+fn search<T: AlphaBeta>(
+    node: &T,
+    settings: &SearchSettings,
+    path: &mut Vec<u64>,
+    depth: u64,
     mut alpha: f32,
     mut beta: f32,
     max: bool,
 ) -> AlphaBetaResult<T::Data> {
+    if let Some(nodes) = &settings.nodes {
+        nodes.fetch_add(1, Ordering::Relaxed);
+    }
+    let root = depth == settings.depth;
+    let ply = settings.depth - depth;
+    let position = node.key();
+    // Perft counts every path, so only a real search knows about draws. The root is exempt, as
+    // the search is there to find a move in it.
+    if !settings.divide && !root {
+        let repeated = position.is_some_and(|key| is_repetition(settings, path, key));
+        if repeated || node.is_draw() {
+            return AlphaBetaResult {
+                count: 1,
+                value: settings.draw_value(ply, max),
+                data: vec![],
+            };
+        }
+        if let Some(value) = node.tablebase_value(settings, ply) {
+            return AlphaBetaResult {
+                count: 1,
+                value,
+                data: vec![],
+            };
+        }
+    }
+
     if depth == 0 || node.is_terminal() {
         return AlphaBetaResult {
             count: 1,
@@ -50,6 +190,14 @@ pub fn alphabeta<T: AlphaBeta>(
         };
     }
 
+    if settings.should_stop() {
+        return AlphaBetaResult {
+            count: 0,
+            value: node.score(),
+            data: vec![],
+        };
+    }
+
     if depth == 1 && settings.divide {
         return AlphaBetaResult {
             count: node.children().count().try_into().unwrap(),
@@ -58,6 +206,44 @@ pub fn alphabeta<T: AlphaBeta>(
         };
     }
 
+    let key = settings
+        .tt
+        .as_ref()
+        .and(position)
+        .map(|key| if max { key } else { key ^ MIN_NODE_KEY });
+    let entry = key.and_then(|key| settings.tt.as_ref().unwrap().probe(key));
+    // The root has to come up with a move, which a table hit doesn't provide.
+    if let Some(entry) = entry.filter(|e| !root && e.depth as u64 >= depth) {
+        let cutoff = match entry.bound {
+            Bound::Exact => true,
+            Bound::Lower => entry.score >= beta,
+            Bound::Upper => entry.score <= alpha,
+        };
+        if cutoff {
+            return AlphaBetaResult {
+                count: 1,
+                value: entry.score,
+                data: vec![],
+            };
+        }
+    }
+
+    let mut children: Vec<_> = node.children().enumerate().collect();
+    if let Some(allowed) = settings.root_moves.as_ref().filter(|_| root) {
+        children.retain(|(i, _)| allowed.contains(i));
+    }
+    if let Some(best) = entry.and_then(|e| e.best) {
+        if let Some(pos) = children.iter().position(|(i, _)| *i == best as usize) {
+            let child = children.remove(pos);
+            children.insert(0, child);
+        }
+    }
+    if root && settings.helper > 0 && !children.is_empty() {
+        let len = children.len();
+        children.rotate_left(settings.helper % len);
+    }
+
+    let (alpha_orig, beta_orig) = (alpha, beta);
     let mut value = if max {
         f32::NEG_INFINITY
     } else {
@@ -65,14 +251,19 @@ pub fn alphabeta<T: AlphaBeta>(
     };
     let mut count = 0;
     let mut best = vec![];
+    let mut best_index = None;
 
-    for (ch, data) in node.children() {
+    if let Some(position) = position {
+        path.push(position);
+    }
+    for (index, (ch, data)) in children {
         count += if max {
-            let res = alphabeta(&ch, settings, depth - 1, alpha, beta, false);
+            let res = search(&ch, settings, path, depth - 1, alpha, beta, false);
             if res.value > value {
                 value = res.value;
                 best = res.data.clone();
                 best.push(data);
+                best_index = Some(index);
             }
             alpha = f32::max(alpha, value);
             if value >= beta && settings.ab_prune {
@@ -80,19 +271,23 @@ pub fn alphabeta<T: AlphaBeta>(
             }
             res.count
         } else {
-            let res = alphabeta(&ch, settings, depth - 1, alpha, beta, true);
+            let res = search(&ch, settings, path, depth - 1, alpha, beta, true);
             if res.value < value {
                 value = res.value;
                 best = res.data.clone();
                 best.push(data);
+                best_index = Some(index);
             }
-            beta = f32::min(alpha, value);
+            beta = f32::min(beta, value);
             if value <= alpha && settings.ab_prune {
                 break;
             }
             res.count
         };
     }
+    if position.is_some() {
+        path.pop();
+    }
     if count == 0 {
         return AlphaBetaResult {
             count: 0,
@@ -101,9 +296,173 @@ pub fn alphabeta<T: AlphaBeta>(
         };
     }
 
+    // A stopped search's values are meaningless and must not outlive it.
+    if let Some(key) = key.filter(|_| !settings.should_stop()) {
+        let bound = if value <= alpha_orig {
+            Bound::Upper
+        } else if value >= beta_orig {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        settings.tt.as_ref().unwrap().store(
+            key,
+            Entry {
+                score: value,
+                depth: depth.min(u8::MAX as u64) as u8,
+                bound,
+                best: best_index.map(|i| i as u16),
+            },
+        );
+    }
+
     AlphaBetaResult {
         count: count,
         value: value,
         data: best,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{alphabeta, AlphaBeta, SearchSettings};
+
+    /// A made-up game on a graph: position `n` moves to the positions in `EDGES[n]`. Going from
+    /// 0 to 1 and back repeats the root; 2 is a dead end that is slightly bad for the root.
+    #[derive(Clone, Copy)]
+    struct Toy(usize);
+
+    const EDGES: [&[usize]; 3] = [&[1, 2], &[0], &[]];
+    const SCORES: [f32; 3] = [10.0, 10.0, -1.0];
+
+    impl AlphaBeta for Toy {
+        type ItemIterator<'a> = std::vec::IntoIter<(Toy, usize)>;
+        type Data = usize;
+
+        fn is_terminal(&self) -> bool {
+            EDGES[self.0].is_empty()
+        }
+
+        fn score(&self) -> f32 {
+            SCORES[self.0]
+        }
+
+        fn children(&self) -> Self::ItemIterator<'_> {
+            let children: Vec<_> = EDGES[self.0].iter().map(|&n| (Toy(n), n)).collect();
+            children.into_iter()
+        }
+
+        fn key(&self) -> Option<u64> {
+            Some(self.0 as u64)
+        }
+    }
+
+    /// A small game tree: node `n` has the children in `TREE[n]`, and the leaves score
+    /// `TREE_SCORES[n]`. The root is a max node with a single min node below it.
+    #[derive(Clone, Copy)]
+    struct Tree(usize);
+
+    const TREE: [&[usize]; 6] = [&[1], &[2, 3], &[], &[4, 5], &[], &[]];
+    const TREE_SCORES: [f32; 6] = [0.0, 0.0, 3.0, 0.0, 0.0, 5.0];
+
+    impl AlphaBeta for Tree {
+        type ItemIterator<'a> = std::vec::IntoIter<(Tree, usize)>;
+        type Data = usize;
+
+        fn is_terminal(&self) -> bool {
+            TREE[self.0].is_empty()
+        }
+
+        fn score(&self) -> f32 {
+            TREE_SCORES[self.0]
+        }
+
+        fn children(&self) -> Self::ItemIterator<'_> {
+            let children: Vec<_> = TREE[self.0].iter().map(|&n| (Tree(n), n)).collect();
+            children.into_iter()
+        }
+    }
+
+    fn search(depth: u64, history: &[u64], contempt: f32) -> f32 {
+        let settings = SearchSettings {
+            history: history.to_vec(),
+            contempt,
+            ..SearchSettings::search(depth)
+        };
+        alphabeta(
+            &Toy(0),
+            &settings,
+            depth,
+            f32::NEG_INFINITY,
+            f32::INFINITY,
+            true,
+        )
+        .value
+    }
+
+    #[test]
+    fn test_repetition_in_tree() {
+        // Without draw detection the loop back to the root would score 10.
+        assert_eq!(search(2, &[], 0.0), 0.0);
+        assert_eq!(search(2, &[], 0.5), -0.5);
+        // With enough contempt the dead end beats the draw.
+        assert_eq!(search(2, &[], 3.0), -1.0);
+    }
+
+    #[test]
+    fn test_draw_value() {
+        let settings = SearchSettings {
+            contempt: 0.5,
+            ..SearchSettings::search(4)
+        };
+        // Below a max root a draw is worth less than even at every ply, below a min root more.
+        assert_eq!(settings.draw_value(0, true), -0.5);
+        assert_eq!(settings.draw_value(1, false), -0.5);
+        assert_eq!(settings.draw_value(0, false), 0.5);
+        assert_eq!(settings.draw_value(3, true), 0.5);
+    }
+
+    #[test]
+    fn test_repetition_with_history() {
+        assert_eq!(search(1, &[], 0.0), 10.0);
+        assert_eq!(search(1, &[1], 0.0), 10.0);
+        assert_eq!(search(1, &[1, 2, 1], 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_divide_ignores_repetition() {
+        let settings = SearchSettings::divide(4);
+        let res = alphabeta(
+            &Toy(0),
+            &settings,
+            4,
+            f32::NEG_INFINITY,
+            f32::INFINITY,
+            true,
+        );
+        // 0-1-0-1-0, 0-1-0-2 and 0-2, which counts although it ends early.
+        assert_eq!(res.count, 3);
+    }
+
+    #[test]
+    fn test_min_node_cutoff() {
+        // The min node is worth min(3, max(0, 5)) = 3. Narrowing beta at min nodes to the
+        // parent's alpha instead of the best reply so far closed the window before node 3 was
+        // searched, which then stopped after its first leaf and made the min node worth 0.
+        let minimax = SearchSettings {
+            ab_prune: false,
+            ..SearchSettings::search(3)
+        };
+        for settings in [minimax, SearchSettings::search(3)] {
+            let res = alphabeta(
+                &Tree(0),
+                &settings,
+                3,
+                f32::NEG_INFINITY,
+                f32::INFINITY,
+                true,
+            );
+            assert_eq!(res.value, 3.0);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::sync::{
+    mpsc::{channel, Receiver, Sender},
+    Mutex,
+};
+
+use vampirc_uci::{UciMessage, UciMove};
+
+use super::{
+    board::Board,
+    engine::Engine,
+    io::{OutputSink, Quiet},
+    moves::Move,
+};
+
+/// Prints the search info, unless told to keep quiet, and hands the best move back.
+struct Sink {
+    print_info: bool,
+    bestmove: Mutex<Sender<UciMove>>,
+}
+
+impl OutputSink for Sink {
+    fn send(&self, msg: UciMessage) {
+        match msg {
+            UciMessage::BestMove { best_move, .. } => {
+                if self.print_info {
+                    println!("{msg}");
+                }
+                let _ = self.bestmove.lock().unwrap().send(best_move);
+            }
+            UciMessage::Info(_) if self.print_info => println!("{msg}"),
+            _ => {}
+        }
+    }
+}
+
+/// An engine in this process that is driven with UCI commands, for the command line modes
+/// that need the search but not the UCI conversation around it.
+pub struct Analysis {
+    engine: Engine,
+    bestmove: Receiver<UciMove>,
+}
+
+impl Analysis {
+    /// With `print_info`, the search's `info` and `bestmove` lines go to stdout.
+    pub fn new(print_info: bool, threads: usize) -> Self {
+        let (send, bestmove) = channel();
+        let sink = Sink {
+            print_info,
+            bestmove: Mutex::new(send),
+        };
+        let engine = Engine::new(Box::new(sink), Box::new(Quiet));
+        engine.handle_uci_line("uci");
+        engine.handle_uci_line(&format!("setoption name Threads value {threads}"));
+        Self { engine, bestmove }
+    }
+
+    /// Searches the position reached by playing `moves` from `fen` with the limits of `go`,
+    /// e.g. `go depth 6`. Returns `None` if the side to move has no legal move.
+    pub fn search(&self, fen: &str, moves: &[Move], go: &str) -> Option<UciMove> {
+        let mut position = format!("position fen {}", Board::from_fen(fen).ok()?.to_fen());
+        if !moves.is_empty() {
+            position.push_str(" moves");
+            for mv in moves {
+                position.push_str(&format!(" {}", UciMove::from(*mv)));
+            }
+        }
+        self.engine.handle_uci_line(&position);
+        self.engine.board().legal_moves().next()?;
+        self.engine.handle_uci_line(go);
+        self.bestmove.recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use vampirc_uci::UciMove;
+
+    use crate::chess::board::Board;
+
+    use super::Analysis;
+
+    #[test]
+    fn test_search() {
+        let analysis = Analysis::new(false, 1);
+        let fen = "4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1";
+        let board = Board::from_fen(fen).unwrap();
+        let mv = analysis.search(fen, &[], "go depth 2").unwrap();
+        assert!(board.legal_moves().any(|m| UciMove::from(m) == mv));
+
+        // The moves are played before searching, so the reply is one of Black's.
+        let rook_up = board
+            .legal_moves()
+            .find(|&mv| UciMove::from(mv).to_string() == "d1d2")
+            .unwrap();
+        let mv = analysis.search(fen, &[rook_up], "go depth 1").unwrap();
+        let after = board.apply_move(&rook_up).unwrap();
+        assert!(after.legal_moves().any(|m| UciMove::from(m) == mv));
+
+        let stalemate = "7k/5Q2/6K1/8/8/8/8/8 b - - 0 1";
+        assert_eq!(analysis.search(stalemate, &[], "go depth 1"), None);
+    }
+}
@@ -49,6 +49,11 @@ impl BitBoard {
     pub fn from_square(sq: Square) -> BitBoard {
         BitBoard(1u64 << sq.0)
     }
+
+    /// Upside down: the first rank becomes the eighth.
+    pub fn flip_ranks(&self) -> BitBoard {
+        BitBoard(self.0.swap_bytes())
+    }
 }
 
 impl IntoIterator for BitBoard {
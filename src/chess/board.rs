@@ -9,8 +9,10 @@ use super::{
     bitboard::BitBoard,
     moves::Move,
     piece::{Piece, ALL_PIECES, NR_PIECE_TYPES},
+    retro::{Dtm, DTM_WIN},
     side::Side,
     square::{File, Rank, Square, ALL_FILES, ALL_RANKS},
+    syzygy::{Wdl, TB_WIN},
 };
 
 #[derive(Debug, Default, Clone, Copy)]
@@ -69,6 +71,24 @@ impl CastleRights {
     }
 }
 
+/// Reasons a position can't be played from, even though its FEN parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidPosition {
+    KingCount(Side),
+    PawnOnBackRank,
+    OpponentInCheck,
+}
+
+impl Display for InvalidPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidPosition::KingCount(side) => write!(f, "{side:?} must have exactly one king"),
+            InvalidPosition::PawnOnBackRank => write!(f, "pawn on the first or last rank"),
+            InvalidPosition::OpponentInCheck => write!(f, "side not to move is in check"),
+        }
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Default, Clone)]
 pub struct Board {
@@ -87,17 +107,34 @@ impl Board {
         self.sides[side]
     }
 
-    pub fn adv_ply(&mut self, half: bool) {
-        //TODO
+    /// Passes the turn. Irreversible moves, i.e. pawn moves and captures, reset the fifty-move
+    /// clock.
+    pub fn adv_ply(&mut self, irreversible: bool) {
         if self.to_move == Side::Black {
             self.fullmoves += 1;
         }
-        if half {
+        if irreversible {
+            self.halfmove_clock = 0;
+        } else {
             self.halfmove_clock += 1;
         }
         self.to_move = self.to_move.other();
     }
 
+    pub fn halfmove_clock(&self) -> u64 {
+        self.halfmove_clock
+    }
+
+    pub fn fullmoves(&self) -> u64 {
+        self.fullmoves
+    }
+
+    /// Drawn by the fifty-move rule, unless the last move delivered mate.
+    pub fn is_fifty_move_draw(&self) -> bool {
+        self.halfmove_clock >= 100
+            && (!self.is_in_check(self.to_move) || self.legal_moves().next().is_some())
+    }
+
     #[allow(dead_code)]
     pub fn pieces(&self, piece: Piece) -> BitBoard {
         self.pieces[piece]
@@ -225,6 +262,93 @@ impl Board {
     pub fn set_enpassant(&mut self, enpassant: BitBoard) {
         self.enpassant = enpassant;
     }
+
+    pub fn set_to_move(&mut self, side: Side) {
+        self.to_move = side;
+    }
+
+    /// The same position with the colours swapped: the ranks are flipped, white's pieces become
+    /// black's and the other side is to move.
+    pub fn mirrored(&self) -> Self {
+        Self {
+            pieces: self.pieces.map(|bb| bb.flip_ranks()),
+            sides: [
+                self.sides[Side::Black].flip_ranks(),
+                self.sides[Side::White].flip_ranks(),
+            ],
+            castle_rights: [
+                self.castle_rights[Side::Black],
+                self.castle_rights[Side::White],
+            ],
+            to_move: self.to_move.other(),
+            enpassant: self.enpassant.flip_ranks(),
+            ..self.clone()
+        }
+    }
+
+    /// Checks that the position is one moves can be generated and searched from.
+    pub fn validate(&self) -> Result<(), InvalidPosition> {
+        for side in [Side::White, Side::Black] {
+            if (self.pieces(Piece::King) & self.color_pieces(side))
+                .into_iter()
+                .count()
+                != 1
+            {
+                return Err(InvalidPosition::KingCount(side));
+            }
+        }
+        if self
+            .pieces(Piece::Pawn)
+            .into_iter()
+            .any(|sq| sq.rank() == Rank::FIRST || sq.rank() == Rank::LAST)
+        {
+            return Err(InvalidPosition::PawnOnBackRank);
+        }
+        if self.is_in_check(self.to_move.other()) {
+            return Err(InvalidPosition::OpponentInCheck);
+        }
+        Ok(())
+    }
+
+    /// Drops castling rights and en passant squares that the pieces on the board contradict, as
+    /// GUIs and hand-written FENs often get these wrong.
+    pub fn sanitize(&mut self) {
+        for side in [Side::White, Side::Black] {
+            let rank = match side {
+                Side::White => Rank::FIRST,
+                Side::Black => Rank::LAST,
+            };
+            let has = |piece: Piece, file: File| {
+                self.piece(Square::from_rank_and_file(rank, file)) == Some((piece, side))
+            };
+            let king = has(Piece::King, File::E);
+            let kingside = king && has(Piece::Rook, File::H);
+            let queenside = king && has(Piece::Rook, File::A);
+            if !kingside {
+                self.castle_rights[side].remove_kingside();
+            }
+            if !queenside {
+                self.castle_rights[side].remove_queenside();
+            }
+        }
+
+        if let Some(sq) = self.enpassant.to_square() {
+            // The square a pawn just skipped over, with that pawn right in front of it.
+            let (ep_rank, pawn_rank, from_rank) = match self.to_move {
+                Side::White => (Rank::new(6), Rank::new(5), Rank::new(7)),
+                Side::Black => (Rank::new(3), Rank::new(4), Rank::new(2)),
+            };
+            let pawn = Square::from_rank_and_file(pawn_rank, sq.file());
+            let from = Square::from_rank_and_file(from_rank, sq.file());
+            let valid = sq.rank() == ep_rank
+                && self.piece(sq).is_none()
+                && self.piece(from).is_none()
+                && self.piece(pawn) == Some((Piece::Pawn, self.to_move.other()));
+            if !valid {
+                self.enpassant = BitBoard::default();
+            }
+        }
+    }
 }
 
 impl From<BoardState> for Board {
@@ -277,27 +401,44 @@ fn utf8_char(piece: Piece, side: Side) -> char {
     c.chars().nth(side.into()).unwrap()
 }
 
-impl Display for Board {
+/// Draws a board from one side's point of view, with that side's pieces at the bottom.
+pub struct BoardView<'a> {
+    board: &'a Board,
+    side: Side,
+}
+
+impl Board {
+    pub fn view_from(&self, side: Side) -> BoardView<'_> {
+        BoardView { board: self, side }
+    }
+}
+
+impl Display for BoardView<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let board = self.board;
         writeln!(
             f,
             "kr: white={}, black={}{}",
-            self.castle_rights(Side::White),
-            self.castle_rights(Side::Black),
-            self.enpassant
+            board.castle_rights(Side::White),
+            board.castle_rights(Side::Black),
+            board
+                .enpassant
                 .to_square()
                 .map_or("".to_owned(), |x| format!(" ; enpassant: {x}"))
         )?;
-        for rank in (Rank::FIRST..=Rank::LAST).rev() {
-            for file in File::A..=File::H {
+        let (mut ranks, mut files) = (ALL_RANKS, ALL_FILES);
+        match self.side {
+            Side::White => ranks.reverse(),
+            Side::Black => files.reverse(),
+        }
+        for (i, rank) in ranks.into_iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{} ", rank.0)?;
+            for file in files {
                 let sq = Square::from_rank_and_file(rank, file);
-                if file == File::A && rank != Rank::LAST {
-                    writeln!(f)?;
-                }
-                if file == File::A {
-                    write!(f, "{} ", rank.0)?;
-                }
-                let p = self.piece(sq);
+                let p = board.piece(sq);
                 if let Some((piece, side)) = p {
                     let s = format!(" {}  ", utf8_char(piece, side)).black();
                     write!(
@@ -325,20 +466,27 @@ impl Display for Board {
         }
         writeln!(f)?;
         write!(f, "   ")?;
-        for file in File::A..=File::H {
+        for file in files {
             write!(f, "{file}   ")?;
         }
         writeln!(f)
     }
 }
 
+impl Display for Board {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.view_from(Side::White).fmt(f)
+    }
+}
+
+/// White maximizes, so scores and tablebase values are from white's point of view.
 impl AlphaBeta for Board {
     fn is_terminal(&self) -> bool {
         false
     }
 
     fn score(&self) -> f32 {
-        self.evaluate_position()
+        self.evaluate()
     }
 
     fn children(&self) -> Self::ItemIterator<'_> {
@@ -346,6 +494,46 @@ impl AlphaBeta for Board {
             .map(|m| (apply(self, m), MoveData { mv: m }))
     }
 
+    fn key(&self) -> Option<u64> {
+        Some(self.hash())
+    }
+
+    fn is_draw(&self) -> bool {
+        self.is_fifty_move_draw()
+    }
+
+    /// Generated tables are probed everywhere and score by the distance to mate. Syzygy tables
+    /// are probed right after captures and pawn moves, as they don't know about the fifty-move
+    /// counter.
+    fn tablebase_value(&self, settings: &SearchSettings, ply: u64) -> Option<f32> {
+        let draw = settings.draw_value(ply, self.to_move() == Side::White);
+        let ply = ply as f32;
+        let value = match settings
+            .dtm_tables
+            .as_ref()
+            .and_then(|tables| tables.probe(self))
+        {
+            Some(Dtm::Win(plies)) => DTM_WIN - ply - plies as f32,
+            Some(Dtm::Loss(plies)) => -DTM_WIN + ply + plies as f32,
+            Some(Dtm::Draw) => return Some(draw),
+            None => {
+                let tablebases = settings.tablebases.as_ref()?;
+                if self.halfmove_clock() != 0 {
+                    return None;
+                }
+                match tablebases.probe_wdl(self)? {
+                    Wdl::Win => TB_WIN - ply,
+                    Wdl::Loss => -TB_WIN + ply,
+                    _ => return Some(draw),
+                }
+            }
+        };
+        Some(match self.to_move() {
+            Side::White => value,
+            Side::Black => -value,
+        })
+    }
+
     type ItemIterator<'a> = impl Iterator<Item = (Board, Self::Data)> + 'a;
 
     type Data = MoveData;
@@ -361,14 +549,16 @@ fn apply(b: &Board, m: Move) -> Board {
 }
 
 impl Board {
-    pub fn alphabeta(&self, settings: &SearchSettings, max: bool) -> AlphaBetaResult<MoveData> {
+    /// Searches for the side to move. The value is from white's point of view, like every
+    /// score.
+    pub fn alphabeta(&self, settings: &SearchSettings) -> AlphaBetaResult<MoveData> {
         crate::ab::alphabeta(
             self,
             settings,
             settings.depth,
             f32::NEG_INFINITY,
             f32::INFINITY,
-            max,
+            self.to_move() == Side::White,
         )
     }
 }
@@ -377,6 +567,83 @@ impl Board {
 mod test {
     // write a test that ensures the to_fen function works correctly
     use super::*;
+    #[test]
+    fn test_validate() {
+        assert_eq!(Board::from_fen("startpos").unwrap().validate(), Ok(()));
+        assert_eq!(
+            Board::from_fen("8/8/8/8/8/8/8/4K3 w - - 0 1")
+                .unwrap()
+                .validate(),
+            Err(InvalidPosition::KingCount(Side::Black))
+        );
+        assert_eq!(
+            Board::from_fen("4k3/8/8/8/8/8/8/4K2P w - - 0 1")
+                .unwrap()
+                .validate(),
+            Err(InvalidPosition::PawnOnBackRank)
+        );
+        assert_eq!(
+            Board::from_fen("4k3/8/8/8/8/8/8/4R1K1 w - - 0 1")
+                .unwrap()
+                .validate(),
+            Err(InvalidPosition::OpponentInCheck)
+        );
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let mut b = Board::from_fen("r3k3/8/8/3pP3/8/8/8/4K2R w KQkq d6 0 1").unwrap();
+        b.sanitize();
+        assert_eq!(b.to_fen(), "r3k3/8/8/3pP3/8/8/8/4K2R w Kq d6 0 1");
+
+        let mut b = Board::from_fen("4k3/8/8/4P3/8/8/8/4K3 w - d6 0 1").unwrap();
+        b.sanitize();
+        assert_eq!(b.to_fen(), "4k3/8/8/4P3/8/8/8/4K3 w - - 0 1");
+    }
+
+    #[test]
+    fn test_halfmove_clock() {
+        let play = |board: Board, mv: Move| board.apply_move(&mv).unwrap();
+        let sq = |s: &str| {
+            let mut chars = s.chars();
+            let file = File::try_from(chars.next().unwrap()).unwrap();
+            let rank = Rank::new(chars.next().unwrap().to_digit(10).unwrap() as u8);
+            Square::from_rank_and_file(rank, file)
+        };
+        let b = Board::from_fen("4k3/8/8/3p4/8/8/3P4/R3K3 w - - 7 30").unwrap();
+        let b = play(b, Move::new(sq("a1"), sq("a5"), None));
+        assert_eq!(b.halfmove_clock(), 8);
+        let b = play(b, Move::new(sq("e8"), sq("e7"), None));
+        assert_eq!(b.halfmove_clock(), 9);
+        let b = play(b, Move::new(sq("d2"), sq("d4"), None));
+        assert_eq!(b.halfmove_clock(), 0);
+        let b = play(b, Move::new(sq("e7"), sq("e6"), None));
+        let b = play(b, Move::new(sq("a5"), sq("d5"), None));
+        assert_eq!(b.to_fen(), "8/8/4k3/3R4/3P4/8/8/4K3 b - - 0 32");
+    }
+
+    #[test]
+    fn test_fifty_move_draw() {
+        let draw = |fen: &str| Board::from_fen(fen).unwrap().is_fifty_move_draw();
+        assert!(!draw("4k3/8/8/8/8/8/8/R3K3 b - - 99 80"));
+        assert!(draw("4k3/8/8/8/8/8/8/R3K3 b - - 100 80"));
+        // Mate on the hundredth half move still counts.
+        assert!(!draw("R3k3/8/4K3/8/8/8/8/8 b - - 100 80"));
+    }
+
+    #[test]
+    fn test_mirrored() {
+        let mirrored = |fen: &str| Board::from_fen(fen).unwrap().mirrored().to_fen();
+        assert_eq!(
+            mirrored("r3k2r/8/8/3pP3/8/8/1Q6/4K3 w kq d6 0 40"),
+            "4k3/1q6/8/8/3Pp3/8/8/R3K2R b KQ d3 0 40"
+        );
+        assert_eq!(
+            mirrored("startpos"),
+            Board::from_fen("startpos").unwrap().to_fen().replace(" w ", " b ")
+        );
+    }
+
     #[test]
     fn test_to_fen_start() {
         let b = Board::from_fen("startpos").unwrap();
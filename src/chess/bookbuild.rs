@@ -0,0 +1,298 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    fmt::Display,
+    fs,
+    io::{self, BufRead, BufReader},
+};
+
+use super::{
+    pgn::{PgnError, PgnGame, PgnReader},
+    polyglot::{encode_move, Book, BookEntry},
+    side::Side,
+};
+
+/// Which moves of which games make it into a book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildOptions {
+    /// Moves after this many plies of a game are not counted.
+    pub max_ply: usize,
+    /// Moves played in fewer games are left out.
+    pub min_games: u32,
+    /// Moves that scored less for the side playing them are left out, in percent.
+    pub min_score: u32,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        Self {
+            max_ply: 16,
+            min_games: 3,
+            min_score: 0,
+        }
+    }
+}
+
+/// How the games went after a move, for the side that played it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MoveStats {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl MoveStats {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    pub fn score(&self) -> f32 {
+        (self.wins as f32 + self.draws as f32 / 2.) / self.games() as f32
+    }
+
+    /// Two points for a win and one for a draw, as the weights of most books are.
+    fn points(&self) -> u64 {
+        2 * self.wins as u64 + self.draws as u64
+    }
+}
+
+/// Collects the opening moves of games into a Polyglot book.
+#[derive(Debug, Clone, Default)]
+pub struct BookBuilder {
+    options: BuildOptions,
+    /// Keyed by position and move, both as Polyglot writes them.
+    stats: HashMap<(u64, u16), MoveStats>,
+    /// Games counted so far.
+    pub games: usize,
+    /// Games without a result, which say nothing about their moves.
+    pub unfinished: usize,
+}
+
+impl BookBuilder {
+    pub fn new(options: BuildOptions) -> Self {
+        Self {
+            options,
+            ..Self::default()
+        }
+    }
+
+    pub fn add_game(&mut self, game: &PgnGame) {
+        let winner = match game.result.as_str() {
+            "1-0" => Some(Side::White),
+            "0-1" => Some(Side::Black),
+            "1/2-1/2" => None,
+            _ => {
+                self.unfinished += 1;
+                return;
+            }
+        };
+        self.games += 1;
+        let mut board = game.start.clone();
+        for pgn_move in game.mainline.moves.iter().take(self.options.max_ply) {
+            let key = (board.polyglot_key(), encode_move(&pgn_move.mv, &board));
+            let stats = self.stats.entry(key).or_default();
+            match winner {
+                Some(side) if side == board.to_move() => stats.wins += 1,
+                Some(_) => stats.losses += 1,
+                None => stats.draws += 1,
+            }
+            board = board.apply_move(&pgn_move.mv).unwrap();
+        }
+    }
+
+    /// Adds every game of a PGN file. Games that can't be read are skipped and returned.
+    pub fn add_pgn(&mut self, input: impl BufRead) -> Vec<PgnError> {
+        let mut errors = vec![];
+        for game in PgnReader::new(input) {
+            match game {
+                Ok(game) => self.add_game(&game),
+                Err(err) => errors.push(err),
+            }
+        }
+        errors
+    }
+
+    pub fn stats(&self, key: u64, mv: u16) -> Option<MoveStats> {
+        self.stats.get(&(key, mv)).copied()
+    }
+
+    /// The moves that pass the filters, weighted by their points. The weights are scaled down
+    /// together when the biggest doesn't fit, so no move ends up at zero.
+    pub fn build(&self) -> Book {
+        let kept: Vec<(&(u64, u16), &MoveStats)> = self
+            .stats
+            .iter()
+            .filter(|(_, stats)| {
+                stats.games() >= self.options.min_games
+                    && 100. * stats.score() >= self.options.min_score as f32
+            })
+            .collect();
+        let most = kept.iter().map(|(_, stats)| stats.points()).max();
+        let scale = most.unwrap_or(0).div_ceil(u16::MAX as u64).max(1);
+        let mut entries: Vec<BookEntry> = kept
+            .into_iter()
+            .map(|(&(key, mv), stats)| BookEntry {
+                key,
+                mv,
+                weight: (stats.points() / scale).max(1) as u16,
+                learn: 0,
+            })
+            .collect();
+        // Books list the moves of a position heaviest first.
+        entries.sort_by_key(|entry| (entry.key, Reverse(entry.weight), entry.mv));
+        Book::new(entries)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BuildSummary {
+    pub games: usize,
+    pub unfinished: usize,
+    pub errors: usize,
+    pub entries: usize,
+}
+
+impl Display for BuildSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} games, {} without a result, {} unreadable, {} book entries",
+            self.games, self.unfinished, self.errors, self.entries
+        )
+    }
+}
+
+/// Builds a book from PGN files and writes it to `out`, printing the games that can't be read.
+pub fn build_book_file(
+    out: &str,
+    pgns: &[String],
+    options: BuildOptions,
+) -> io::Result<BuildSummary> {
+    let mut builder = BookBuilder::new(options);
+    let mut errors = 0;
+    for path in pgns {
+        for err in builder.add_pgn(BufReader::new(fs::File::open(path)?)) {
+            println!("{path}: {err}");
+            errors += 1;
+        }
+    }
+    let book = builder.build();
+    let mut file = io::BufWriter::new(fs::File::create(out)?);
+    book.write(&mut file)?;
+    Ok(BuildSummary {
+        games: builder.games,
+        unfinished: builder.unfinished,
+        errors,
+        entries: book.len(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::chess::{
+        board::Board,
+        polyglot::{encode_move, Book},
+    };
+
+    use super::{BookBuilder, BuildOptions, MoveStats};
+
+    const GAMES: &str = r#"
+[Result "1-0"]
+1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 1-0
+
+[Result "1/2-1/2"]
+1. e4 e5 2. Nf3 Nf6 1/2-1/2
+
+[Result "0-1"]
+1. e4 c5 2. Nf3 d6 0-1
+
+[Result "1-0"]
+1. d4 d5 2. c4 1-0
+
+[Result "*"]
+1. e4 e5 *
+
+[Result "1-0"]
+1. e4 e5 2. Qh5 Nf6 3. Qxf7# 1-0
+"#;
+
+    fn play(moves: &[&str]) -> Board {
+        let mut board = Board::from_fen("startpos").unwrap();
+        for san in moves {
+            let mv = board.parse_san(san).unwrap();
+            board = board.apply_move(&mv).unwrap();
+        }
+        board
+    }
+
+    fn stats(builder: &BookBuilder, moves: &[&str], san: &str) -> Option<MoveStats> {
+        let board = play(moves);
+        let mv = encode_move(&board.parse_san(san).unwrap(), &board);
+        builder.stats(board.polyglot_key(), mv)
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let mut builder = BookBuilder::new(BuildOptions {
+            max_ply: 3,
+            ..BuildOptions::default()
+        });
+        assert!(builder.add_pgn(GAMES.as_bytes()).is_empty());
+        assert_eq!(builder.games, 5);
+        assert_eq!(builder.unfinished, 1);
+
+        let e4 = stats(&builder, &[], "e4").unwrap();
+        assert_eq!((e4.wins, e4.draws, e4.losses), (2, 1, 1));
+        assert_eq!(e4.score(), 0.625);
+        // For black, white's win is a loss.
+        let e5 = stats(&builder, &["e4"], "e5").unwrap();
+        assert_eq!((e5.wins, e5.draws, e5.losses), (0, 1, 2));
+        assert_eq!(stats(&builder, &["e4", "e5"], "Nf3").unwrap().games(), 2);
+        // Past the third ply nothing is counted.
+        assert_eq!(stats(&builder, &["e4", "e5", "Nf3"], "Nc6"), None);
+    }
+
+    #[test]
+    fn test_build() {
+        let mut builder = BookBuilder::new(BuildOptions {
+            max_ply: 8,
+            min_games: 2,
+            min_score: 50,
+        });
+        builder.add_pgn(GAMES.as_bytes());
+        let book = builder.build();
+        let start = play(&[]);
+        // d4 was played once, and e5 scored too little.
+        let moves = book.moves(&start);
+        assert_eq!(moves.len(), 1);
+        assert_eq!(start.san(&moves[0].0), "e4");
+        assert_eq!(moves[0].1, 5);
+        assert!(book.moves(&play(&["e4"])).is_empty());
+
+        let mut bytes = vec![];
+        book.write(&mut bytes).unwrap();
+        let read = Book::from_bytes(&bytes);
+        assert_eq!(read.entries(), book.entries());
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(read.pick(&start, false, &mut rng).is_some());
+    }
+
+    #[test]
+    fn test_scaling() {
+        let mut builder = BookBuilder::new(BuildOptions {
+            min_games: 1,
+            ..BuildOptions::default()
+        });
+        let win = "[Result \"1-0\"]\n1. e4 1-0\n\n".repeat(33000);
+        let draw = "[Result \"1/2-1/2\"]\n1. d4 1/2-1/2\n\n";
+        builder.add_pgn(win.as_bytes());
+        builder.add_pgn(draw.as_bytes());
+        let book = builder.build();
+        let start = play(&[]);
+        let mut weights: Vec<u16> = book.moves(&start).iter().map(|&(_, w)| w).collect();
+        weights.sort();
+        assert_eq!(weights, [1, 33000]);
+    }
+}
@@ -0,0 +1,133 @@
+use std::fmt::Write;
+
+use vampirc_uci::UciMove;
+
+use super::{
+    bench,
+    board::Board,
+    perft::{self, PerftStats},
+};
+
+/// Handles the commands that aren't UCI but are handy when poking at the engine by hand:
+/// `perft N`, `divide N`, `stats N`, `bench [N]`, `d`, `eval` and `moves`. Returns the text to
+/// print, or `None` if the line is not one of them.
+pub fn run_command(board: &Board, line: &str) -> Option<String> {
+    let mut tokens = line.split_whitespace();
+    let command = tokens.next()?;
+    let depth = tokens.next().map(|d| d.parse::<u64>());
+    let mut out = String::new();
+    match (command, depth) {
+        ("perft", Some(Ok(depth))) => {
+            let nodes = perft::perft(board, depth);
+            writeln!(out, "Nodes searched: {nodes}").unwrap();
+        }
+        ("divide", Some(Ok(depth))) => {
+            let counts = divide(board, depth);
+            for (mv, count) in &counts {
+                writeln!(out, "{mv}: {count}").unwrap();
+            }
+            let total: u64 = counts.iter().map(|(_, count)| count).sum();
+            writeln!(out, "\nNodes searched: {total}").unwrap();
+        }
+        ("stats", Some(Ok(depth))) => {
+            writeln!(out, "{}", PerftStats::header()).unwrap();
+            for depth in 1..=depth {
+                writeln!(out, "{}", perft::perft_stats(board, depth).row(depth)).unwrap();
+            }
+        }
+        ("bench", None) => write!(out, "{}", bench::bench(bench::DEFAULT_DEPTH)).unwrap(),
+        ("bench", Some(Ok(depth))) => write!(out, "{}", bench::bench(depth)).unwrap(),
+        ("bench", _) => writeln!(out, "usage: bench [depth]").unwrap(),
+        ("perft" | "divide" | "stats", _) => writeln!(out, "usage: {command} <depth>").unwrap(),
+        ("d", None) => {
+            write!(out, "{board}").unwrap();
+            writeln!(out, "Fen: {}", board.to_fen()).unwrap();
+            writeln!(out, "Key: {:016x}", board.hash()).unwrap();
+        }
+        ("eval", None) => writeln!(out, "Eval: {}", board.evaluate()).unwrap(),
+        ("moves", None) => {
+            let mut moves: Vec<String> = board
+                .legal_moves()
+                .map(|mv| UciMove::from(mv).to_string())
+                .collect();
+            moves.sort();
+            writeln!(out, "{} ({})", moves.join(" "), moves.len()).unwrap();
+        }
+        _ => return None,
+    }
+    Some(out)
+}
+
+/// Perft of every legal move, in UCI notation and sorted the way other engines print them.
+pub fn divide(board: &Board, depth: u64) -> Vec<(String, u64)> {
+    let mut counts: Vec<_> = perft::divide(board, depth)
+        .into_iter()
+        .map(|(mv, count)| (UciMove::from(mv).to_string(), count))
+        .collect();
+    counts.sort();
+    counts
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chess::board::Board;
+
+    use super::run_command;
+
+    #[test]
+    fn test_perft_divide() {
+        let board = Board::from_fen("startpos").unwrap();
+        assert_eq!(
+            run_command(&board, "perft 3").unwrap(),
+            "Nodes searched: 8902\n"
+        );
+
+        let out = run_command(&board, "divide 2").unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines.len(), 22);
+        assert_eq!(lines[0], "a2a3: 20");
+        assert_eq!(lines[19], "h2h4: 20");
+        assert_eq!(lines[21], "Nodes searched: 400");
+
+        assert_eq!(
+            run_command(&board, "perft").unwrap(),
+            "usage: perft <depth>\n"
+        );
+        assert_eq!(
+            run_command(&board, "divide x").unwrap(),
+            "usage: divide <depth>\n"
+        );
+
+        let out = run_command(&board, "stats 3").unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].contains("captures"));
+        assert!(lines[3].starts_with("    3         8902         34"));
+    }
+
+    #[test]
+    fn test_inspect() {
+        let board = Board::from_fen("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1").unwrap();
+        let out = run_command(&board, "d").unwrap();
+        assert!(out.contains("Fen: 4k3/8/8/8/8/8/4P3/4K3 w - - 0 1\n"));
+        assert!(out.contains(&format!("Key: {:016x}", board.hash())));
+        assert_eq!(
+            run_command(&board, "moves").unwrap(),
+            "e1d1 e1d2 e1f1 e1f2 e2e3 e2e4 (6)\n"
+        );
+        assert!(run_command(&board, "eval").unwrap().starts_with("Eval: "));
+    }
+
+    #[test]
+    fn test_not_a_command() {
+        let board = Board::from_fen("startpos").unwrap();
+        assert_eq!(run_command(&board, "isready"), None);
+        assert_eq!(run_command(&board, "position startpos"), None);
+        assert_eq!(run_command(&board, ""), None);
+        assert_eq!(run_command(&board, "d 3"), None);
+        assert_eq!(
+            run_command(&board, "bench x").unwrap(),
+            "usage: bench [depth]\n"
+        );
+    }
+}
@@ -0,0 +1,559 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use super::{
+    board::Board,
+    piece::{Piece, ALL_PIECES},
+    side::Side,
+};
+
+/// What a won endgame scores at least, in pawns. It stays well below the tablebase scores.
+pub const KNOWN_WIN: f32 = 10.0;
+
+/// How many of each piece each side has, four bits apiece. Positions with the same material
+/// share a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MaterialKey(u64);
+
+impl MaterialKey {
+    fn shift(piece: Piece, side: Side) -> u64 {
+        (side as u64 * ALL_PIECES.len() as u64 + piece as u64) * 4
+    }
+
+    pub fn count(self, piece: Piece, side: Side) -> u32 {
+        ((self.0 >> Self::shift(piece, side)) & 15) as u32
+    }
+
+    /// Anything besides the king and pawns.
+    fn pieces(self, side: Side) -> u32 {
+        [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen]
+            .into_iter()
+            .map(|piece| self.count(piece, side))
+            .sum()
+    }
+
+    /// Material written like `KBNK`, with the pieces of `strong` first.
+    fn parse(code: &str, strong: Side) -> Self {
+        let mut key = 0;
+        let mut side = strong.other();
+        for c in code.chars() {
+            if c == 'K' {
+                side = side.other();
+            }
+            let piece = match c {
+                'P' => Piece::Pawn,
+                'N' => Piece::Knight,
+                'B' => Piece::Bishop,
+                'R' => Piece::Rook,
+                'Q' => Piece::Queen,
+                _ => continue,
+            };
+            key += 1 << Self::shift(piece, side);
+        }
+        Self(key)
+    }
+}
+
+impl Board {
+    pub fn material_key(&self) -> MaterialKey {
+        let mut key = 0;
+        for side in [Side::White, Side::Black] {
+            for piece in ALL_PIECES.into_iter().filter(|&piece| piece != Piece::King) {
+                let count = (self.pieces(piece) & self.color_pieces(side))
+                    .into_iter()
+                    .count();
+                key |= (count.min(15) as u64) << MaterialKey::shift(piece, side);
+            }
+        }
+        MaterialKey(key)
+    }
+
+    /// The score the search uses: [`Board::evaluate_position`], unless the material is an
+    /// endgame known better. Like every score, it is from white's point of view.
+    pub fn evaluate(&self) -> f32 {
+        match verdict(self) {
+            Some(Verdict::Score(score)) => score,
+            Some(Verdict::Scale(factor)) => factor * self.evaluate_position(),
+            None => self.evaluate_position(),
+        }
+    }
+}
+
+enum Verdict {
+    /// Replaces the evaluation.
+    Score(f32),
+    /// Multiplies the evaluation.
+    Scale(f32),
+}
+
+/// Scores a position from the point of view of the side that has the material to win.
+type Evaluator = fn(&Board, Side) -> f32;
+
+/// Evaluators by the material they know, for either side being the strong one.
+fn endgames() -> &'static HashMap<MaterialKey, (Evaluator, Side)> {
+    static ENDGAMES: OnceLock<HashMap<MaterialKey, (Evaluator, Side)>> = OnceLock::new();
+    ENDGAMES.get_or_init(|| {
+        let evaluators: [(&str, Evaluator); 8] = [
+            ("KPK", kpk),
+            ("KBNK", kbnk),
+            ("KQK", mop_up),
+            ("KRK", mop_up),
+            ("KK", draw),
+            ("KNK", draw),
+            ("KBK", draw),
+            ("KNNK", draw),
+        ];
+        let mut endgames = HashMap::new();
+        for (code, evaluator) in evaluators {
+            for strong in [Side::White, Side::Black] {
+                endgames.insert(MaterialKey::parse(code, strong), (evaluator, strong));
+            }
+        }
+        endgames
+    })
+}
+
+fn verdict(board: &Board) -> Option<Verdict> {
+    let key = board.material_key();
+    let white = |strong: Side, score: f32| match strong {
+        Side::White => score,
+        Side::Black => -score,
+    };
+    if let Some(&(evaluator, strong)) = endgames().get(&key) {
+        return Some(Verdict::Score(white(strong, evaluator(board, strong))));
+    }
+    for strong in [Side::White, Side::Black] {
+        let weak = strong.other();
+        let bare = key.pieces(weak) == 0 && key.count(Piece::Pawn, weak) == 0;
+        let heavy = key.count(Piece::Queen, strong) + key.count(Piece::Rook, strong) > 0;
+        if bare && heavy {
+            return Some(Verdict::Score(white(strong, mop_up(board, strong))));
+        }
+        if bare && wrong_rook_pawns(board, key, strong) {
+            return Some(Verdict::Score(0.0));
+        }
+    }
+    let bishops_only = [Side::White, Side::Black]
+        .into_iter()
+        .all(|side| key.pieces(side) == 1 && key.count(Piece::Bishop, side) == 1);
+    if bishops_only {
+        let mut colors = board
+            .pieces(Piece::Bishop)
+            .into_iter()
+            .map(|sq| is_light(sq.0));
+        if colors.next() != colors.next() {
+            return Some(Verdict::Scale(0.5));
+        }
+    }
+    None
+}
+
+fn file(sq: u8) -> i32 {
+    (sq % 8) as i32
+}
+
+fn rank(sq: u8) -> i32 {
+    (sq / 8) as i32
+}
+
+fn distance(a: u8, b: u8) -> i32 {
+    (file(a) - file(b)).abs().max((rank(a) - rank(b)).abs())
+}
+
+fn is_light(sq: u8) -> bool {
+    (file(sq) + rank(sq)) % 2 == 1
+}
+
+/// How far a square is from the edge of the board, 0 to 3.
+fn centrality(sq: u8) -> i32 {
+    file(sq).min(7 - file(sq)).min(rank(sq)).min(7 - rank(sq))
+}
+
+/// The square of the one `piece` of `side`.
+fn square(board: &Board, piece: Piece, side: Side) -> u8 {
+    (board.pieces(piece) & board.color_pieces(side))
+        .into_iter()
+        .next()
+        .unwrap()
+        .0
+}
+
+fn draw(_board: &Board, _strong: Side) -> f32 {
+    0.0
+}
+
+/// Bringing the kings together helps every mate against a bare king.
+fn closeness(board: &Board, strong: Side) -> f32 {
+    let kings = distance(
+        square(board, Piece::King, strong),
+        square(board, Piece::King, strong.other()),
+    );
+    0.1 * (7 - kings) as f32
+}
+
+/// A bare king against a queen or rook is mated on the edge. The position's own score is
+/// white's, so it is turned around when black is the strong side.
+fn mop_up(board: &Board, strong: Side) -> f32 {
+    let material = match strong {
+        Side::White => board.evaluate_position(),
+        Side::Black => -board.evaluate_position(),
+    };
+    let edge = 3 - centrality(square(board, Piece::King, strong.other()));
+    KNOWN_WIN + material + 0.2 * edge as f32 + closeness(board, strong)
+}
+
+/// Bishop and knight only mate in a corner the bishop covers.
+fn kbnk(board: &Board, strong: Side) -> f32 {
+    let weak_king = square(board, Piece::King, strong.other());
+    let corners: [u8; 2] = match is_light(square(board, Piece::Bishop, strong)) {
+        true => [7, 56],
+        false => [0, 63],
+    };
+    let corner = corners
+        .iter()
+        .map(|&corner| {
+            (file(weak_king) - file(corner)).abs() + (rank(weak_king) - rank(corner)).abs()
+        })
+        .min()
+        .unwrap();
+    KNOWN_WIN + 0.1 * (14 - corner) as f32 + closeness(board, strong)
+}
+
+fn kpk(board: &Board, strong: Side) -> f32 {
+    // Seen from the strong side, with its pawn going up the board.
+    let relative = |sq: u8| match strong {
+        Side::White => sq,
+        Side::Black => sq ^ 56,
+    };
+    let pawn = relative(square(board, Piece::Pawn, strong));
+    let strong_king = relative(square(board, Piece::King, strong));
+    let weak_king = relative(square(board, Piece::King, strong.other()));
+    let to_move = board.to_move() == strong;
+    if !kpk_bitbase().probe(strong_king, pawn, weak_king, to_move) {
+        return 0.0;
+    }
+    KNOWN_WIN + 1.0 + 0.1 * rank(pawn) as f32
+}
+
+/// Pawns only on a rook file, promoting on a square the bishop doesn't cover, can't win once
+/// the defending king reaches the corner.
+fn wrong_rook_pawns(board: &Board, key: MaterialKey, strong: Side) -> bool {
+    if key.pieces(strong) != 1 || key.count(Piece::Bishop, strong) != 1 {
+        return false;
+    }
+    let pawns = board.pieces(Piece::Pawn) & board.color_pieces(strong);
+    let Some(first) = pawns.into_iter().next() else {
+        return false;
+    };
+    if pawns.into_iter().any(|sq| file(sq.0) != file(first.0)) {
+        return false;
+    }
+    if file(first.0) != 0 && file(first.0) != 7 {
+        return false;
+    }
+    let promotion = match strong {
+        Side::White => 56 + file(first.0) as u8,
+        Side::Black => file(first.0) as u8,
+    };
+    let bishop = square(board, Piece::Bishop, strong);
+    let weak_king = square(board, Piece::King, strong.other());
+    is_light(bishop) != is_light(promotion) && distance(weak_king, promotion) <= 1
+}
+
+/// Whether the side with a king and pawn wins against a bare king, for every position of those
+/// pieces with the pawn on the a to d files, white's point of view.
+pub struct KpkBitbase {
+    wins: Vec<u64>,
+}
+
+/// Pawns on the a to d files and the second to seventh ranks.
+const KPK_SIZE: usize = 2 * 64 * 64 * 4 * 6;
+
+const INVALID: u8 = 0;
+const UNKNOWN: u8 = 1;
+const DRAW: u8 = 2;
+const WIN: u8 = 4;
+
+const KING_ATTACKS: [u64; 64] = {
+    let mut attacks = [0u64; 64];
+    let mut sq = 0;
+    while sq < 64 {
+        let mut to = 0;
+        while to < 64 {
+            let files = (sq % 8) as i32 - (to % 8) as i32;
+            let ranks = (sq / 8) as i32 - (to / 8) as i32;
+            if sq != to && files.abs() <= 1 && ranks.abs() <= 1 {
+                attacks[sq] |= 1 << to;
+            }
+            to += 1;
+        }
+        sq += 1;
+    }
+    attacks
+};
+
+fn kpk_index(white_to_move: bool, white_king: u8, black_king: u8, pawn: u8) -> usize {
+    (!white_to_move) as usize
+        | (black_king as usize) << 1
+        | (white_king as usize) << 7
+        | (file(pawn) as usize) << 13
+        | ((6 - rank(pawn)) as usize) << 15
+}
+
+fn pawn_attacks(pawn: u8) -> u64 {
+    let mut attacks = 0;
+    if file(pawn) > 0 {
+        attacks |= 1 << (pawn + 7);
+    }
+    if file(pawn) < 7 {
+        attacks |= 1 << (pawn + 9);
+    }
+    attacks
+}
+
+/// What the rules alone decide about a position: illegal ones, immediate safe promotions,
+/// stalemates and the pawn falling.
+fn kpk_initial(white_to_move: bool, white_king: u8, black_king: u8, pawn: u8) -> u8 {
+    let bit = |sq: u8| 1u64 << sq;
+    let queening = pawn + 8;
+    if distance(white_king, black_king) <= 1
+        || white_king == pawn
+        || black_king == pawn
+        || (white_to_move && pawn_attacks(pawn) & bit(black_king) != 0)
+    {
+        return INVALID;
+    }
+    if white_to_move
+        && rank(pawn) == 6
+        && white_king != queening
+        && (distance(black_king, queening) > 1 || distance(white_king, queening) == 1)
+    {
+        return WIN;
+    }
+    let escapes = KING_ATTACKS[black_king as usize]
+        & !(KING_ATTACKS[white_king as usize] | pawn_attacks(pawn));
+    let takes = KING_ATTACKS[black_king as usize] & bit(pawn) & !KING_ATTACKS[white_king as usize];
+    if !white_to_move && (escapes == 0 || takes != 0) {
+        return DRAW;
+    }
+    UNKNOWN
+}
+
+/// Wins if some white move wins, draws if every black move draws.
+fn kpk_classify(db: &[u8], white_to_move: bool, white_king: u8, black_king: u8, pawn: u8) -> u8 {
+    let mut results = INVALID;
+    let king = if white_to_move {
+        white_king
+    } else {
+        black_king
+    };
+    let mut moves = KING_ATTACKS[king as usize];
+    while moves != 0 {
+        let to = moves.trailing_zeros() as u8;
+        moves &= moves - 1;
+        results |= match white_to_move {
+            true => db[kpk_index(false, to, black_king, pawn)],
+            false => db[kpk_index(true, white_king, to, pawn)],
+        };
+    }
+    if white_to_move && rank(pawn) < 6 {
+        results |= db[kpk_index(false, white_king, black_king, pawn + 8)];
+        let passes = pawn + 8 != white_king && pawn + 8 != black_king;
+        if rank(pawn) == 1 && passes {
+            results |= db[kpk_index(false, white_king, black_king, pawn + 16)];
+        }
+    }
+    let (good, bad) = if white_to_move {
+        (WIN, DRAW)
+    } else {
+        (DRAW, WIN)
+    };
+    if results & good != 0 {
+        good
+    } else if results & UNKNOWN != 0 {
+        UNKNOWN
+    } else {
+        bad
+    }
+}
+
+fn kpk_decode(idx: usize) -> (bool, u8, u8, u8) {
+    let pawn = ((idx >> 13) & 3) + 8 * (6 - (idx >> 15));
+    (
+        idx & 1 == 0,
+        ((idx >> 7) & 63) as u8,
+        ((idx >> 1) & 63) as u8,
+        pawn as u8,
+    )
+}
+
+impl KpkBitbase {
+    fn generate() -> Self {
+        let mut db: Vec<u8> = (0..KPK_SIZE)
+            .map(|idx| {
+                let (white_to_move, white_king, black_king, pawn) = kpk_decode(idx);
+                kpk_initial(white_to_move, white_king, black_king, pawn)
+            })
+            .collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for idx in 0..KPK_SIZE {
+                if db[idx] != UNKNOWN {
+                    continue;
+                }
+                let (white_to_move, white_king, black_king, pawn) = kpk_decode(idx);
+                db[idx] = kpk_classify(&db, white_to_move, white_king, black_king, pawn);
+                changed |= db[idx] != UNKNOWN;
+            }
+        }
+        let mut wins = vec![0u64; KPK_SIZE / 64];
+        for (idx, &result) in db.iter().enumerate() {
+            if result == WIN {
+                wins[idx / 64] |= 1 << (idx % 64);
+            }
+        }
+        Self { wins }
+    }
+
+    /// Squares are seen from the side with the pawn, which moves up the board.
+    pub fn probe(&self, strong_king: u8, pawn: u8, weak_king: u8, strong_to_move: bool) -> bool {
+        let (strong_king, pawn, weak_king) = match file(pawn) > 3 {
+            true => (strong_king ^ 7, pawn ^ 7, weak_king ^ 7),
+            false => (strong_king, pawn, weak_king),
+        };
+        let idx = kpk_index(strong_to_move, strong_king, weak_king, pawn);
+        self.wins[idx / 64] & (1 << (idx % 64)) != 0
+    }
+}
+
+/// Generated the first time it's needed, which the engine makes happen at startup.
+pub fn kpk_bitbase() -> &'static KpkBitbase {
+    static KPK: OnceLock<KpkBitbase> = OnceLock::new();
+    KPK.get_or_init(KpkBitbase::generate)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        ab::SearchSettings,
+        chess::{
+            board::Board,
+            piece::Piece,
+            retro::{Dtm, DtmTables},
+            side::Side,
+            square::Square,
+        },
+    };
+
+    use super::{kpk_bitbase, MaterialKey, KNOWN_WIN};
+
+    fn eval(fen: &str) -> f32 {
+        Board::from_fen(fen).unwrap().evaluate()
+    }
+
+    #[test]
+    fn test_material_key() {
+        let key = Board::from_fen("startpos").unwrap().material_key();
+        assert_eq!(key.count(Piece::Pawn, Side::Black), 8);
+        assert_eq!(key.count(Piece::Queen, Side::White), 1);
+        assert_eq!(key.count(Piece::King, Side::White), 0);
+        let kbnk = Board::from_fen("8/8/8/3k4/8/8/8/KBN5 w - - 0 1").unwrap();
+        assert_eq!(kbnk.material_key(), MaterialKey::parse("KBNK", Side::White));
+        assert_eq!(kbnk.material_key(), MaterialKey::parse("KNBK", Side::White));
+        assert_ne!(kbnk.material_key(), MaterialKey::parse("KBNK", Side::Black));
+    }
+
+    #[test]
+    fn test_kpk_bitbase() {
+        let mut tables = DtmTables::default();
+        tables.generate(&"KPK".parse().unwrap());
+        let bitbase = kpk_bitbase();
+        let mut wins = 0;
+        for white_king in 0..64 {
+            for black_king in 0..64 {
+                for pawn in 8..56 {
+                    for to_move in [Side::White, Side::Black] {
+                        let mut board = Board::default();
+                        let squares = [white_king, black_king, pawn];
+                        if squares[1..].contains(&white_king) || black_king == pawn {
+                            continue;
+                        }
+                        board.set_square(Square(white_king), Piece::King, Side::White);
+                        board.set_square(Square(black_king), Piece::King, Side::Black);
+                        board.set_square(Square(pawn), Piece::Pawn, Side::White);
+                        for side in [Side::White, Side::Black] {
+                            board.castle_rights_mut(side).remove_kingside();
+                            board.castle_rights_mut(side).remove_queenside();
+                        }
+                        board.set_to_move(to_move);
+                        if board.validate().is_err() {
+                            continue;
+                        }
+                        let expected = matches!(
+                            (to_move, tables.probe(&board).unwrap()),
+                            (Side::White, Dtm::Win(_)) | (Side::Black, Dtm::Loss(_))
+                        );
+                        let white_to_move = to_move == Side::White;
+                        let won = bitbase.probe(white_king, pawn, black_king, white_to_move);
+                        assert_eq!(won, expected, "{}", board.to_fen());
+                        wins += won as usize;
+                    }
+                }
+            }
+        }
+        assert!(wins > 100_000);
+    }
+
+    #[test]
+    fn test_kpk() {
+        // The defender holds the opposition, or doesn't.
+        assert_eq!(eval("8/8/8/8/4k3/8/4P3/4K3 w - - 0 1"), 0.0);
+        assert_eq!(eval("8/8/8/8/8/4k3/4P3/4K3 b - - 0 1"), 0.0);
+        assert!(eval("8/8/8/4k3/8/4K3/4P3/8 b - - 0 1") > KNOWN_WIN);
+        // The same with colours reversed.
+        assert!(eval("8/4p3/4k3/8/4K3/8/8/8 w - - 0 1") < -KNOWN_WIN);
+        assert_eq!(eval("k7/8/8/8/8/8/P7/K7 w - - 0 1"), 0.0);
+    }
+
+    #[test]
+    fn test_mates() {
+        // Against a bare king, the edge and the right corner score better.
+        let edge = eval("8/8/8/8/8/8/8/K3k2R w - - 0 1");
+        let center = eval("8/8/8/4k3/8/8/8/K6R w - - 0 1");
+        assert!(center > KNOWN_WIN && edge > center);
+        assert!(eval("8/8/8/8/8/8/8/k3K2r w - - 0 1") < -KNOWN_WIN);
+        assert!(eval("8/8/8/8/8/8/8/k2QKQ2 w - - 0 1") > KNOWN_WIN);
+
+        let right = eval("k7/8/2K5/8/8/8/8/1B4N1 w - - 0 1");
+        let wrong = eval("7k/8/5K2/8/8/8/8/1B4N1 w - - 0 1");
+        assert!(wrong > KNOWN_WIN && right > wrong);
+    }
+
+    #[test]
+    fn test_search_black() {
+        // Taking the rook leaves black a won queen ending, which shows as white losing.
+        let board = Board::from_fen("7k/8/8/8/8/8/1q6/R5K1 b - - 0 1").unwrap();
+        let res = board.alphabeta(&SearchSettings::search(2));
+        assert!(res.value < -KNOWN_WIN, "{}", res.value);
+        assert_eq!(board.san(&res.data.last().unwrap().mv), "Qxa1+");
+    }
+
+    #[test]
+    fn test_draws() {
+        assert_eq!(eval("8/8/8/3k4/8/8/8/KN6 w - - 0 1"), 0.0);
+        assert_eq!(eval("8/8/8/3k4/8/8/8/KNN5 b - - 0 1"), 0.0);
+        assert_eq!(eval("8/8/8/3kb3/8/8/8/K7 w - - 0 1"), 0.0);
+        // The a8 corner is light and the bishop dark.
+        assert_eq!(eval("k7/8/8/8/8/8/P7/K1B5 w - - 0 1"), 0.0);
+        assert!(eval("8/k7/8/8/8/8/P7/KB6 w - - 0 1") != 0.0);
+        assert!(eval("4k3/8/8/8/8/8/P7/K1B5 w - - 0 1") != 0.0);
+        assert_eq!(eval("8/p7/8/8/8/8/6b1/K1k5 w - - 0 1"), 0.0);
+
+        let fen = "4k3/8/8/3b4/8/2B5/PP6/4K3 w - - 0 1";
+        let board = Board::from_fen(fen).unwrap();
+        assert_eq!(board.evaluate(), 0.5 * board.evaluate_position());
+        let fen = "4k3/8/8/4b3/8/2B5/PP6/4K3 w - - 0 1";
+        let board = Board::from_fen(fen).unwrap();
+        assert_eq!(board.evaluate(), board.evaluate_position());
+    }
+}
@@ -1,572 +1,1189 @@
 use std::{
-    sync::Arc,
-    time::{Duration, Instant},
+    fmt::{Arguments, Display},
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
-use tokio::{
-    select, spawn,
-    sync::{
-        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
-        Mutex,
-    },
-};
-use vampirc_uci::{UciFen, UciMessage, UciMove, UciSearchControl, UciTimeControl};
-
-use crate::ab::SearchSettings;
+use vampirc_uci::{parse_one, UciFen, UciInfoAttribute, UciMessage, UciMove, UciOptionConfig};
 
-use super::{board::Board, side::Side};
+use crate::tt::TranspositionTable;
 
-#[derive(Debug, Clone, Copy, PartialEq, Default)]
-struct EngineResult {
-    best_move: Option<UciMove>,
-    ponder: Option<UciMove>,
-    stats: Stats,
-    out_of_time: bool,
-}
-
-#[derive(Debug, Clone, Default)]
-enum EngineState {
-    Going(ThinkState),
-    Pondering(ThinkState),
-    #[default]
-    Stopped,
-}
-
-impl EngineState {
-    /// Returns `true` if the engine state is [`Stopped`].
-    ///
-    /// [`Stopped`]: EngineState::Stopped
-    #[must_use]
-    fn is_stopped(&self) -> bool {
-        matches!(self, Self::Stopped)
-    }
+use super::{
+    board::{Board, InvalidPosition},
+    endgame,
+    io::{Logger, OutputSink, Stderr, Stdout},
+    legal::IllegalMove,
+    moves::Move,
+    polyglot::Book,
+    retro::DtmTables,
+    syzygy::Tablebases,
+    timeman::{Clock, SystemClock},
+    worker::{SearchJob, SearchState, SearchWorker},
+};
 
-    /// Returns `true` if the engine state is [`Pondering`].
-    ///
-    /// [`Pondering`]: EngineState::Pondering
-    #[must_use]
-    fn is_pondering(&self) -> bool {
-        matches!(self, Self::Pondering(..))
+/// Parses a line of UCI input. `vampirc_uci` rejects `go ponder`, so the `ponder` token is
+/// stripped before parsing and reported separately.
+pub fn parse_uci_line(line: &str) -> (UciMessage, bool) {
+    let mut tokens = line.split_whitespace();
+    if tokens.next() == Some("go") && tokens.any(|t| t == "ponder") {
+        let line = line
+            .split_whitespace()
+            .filter(|&t| t != "ponder")
+            .collect::<Vec<_>>()
+            .join(" ");
+        return (parse_one(&line), true);
     }
+    (parse_one(line), false)
 }
 
+/// Settings the GUI can change with `setoption`.
 #[derive(Debug, Clone)]
-struct ThinkState {
-    start_time: Instant,
-    time_control: Option<UciTimeControl>,
-    // TODO
-    _search_control: Option<UciSearchControl>,
-    best_result: EngineResultState,
-    our_side: Side,
+struct EngineOptions {
+    move_overhead: Duration,
+    threads: usize,
+    /// Transposition table size in megabytes.
+    hash: usize,
+    /// In centipawns.
+    contempt: i64,
+    /// Play from the book while it has a move, without searching.
+    own_book: bool,
+    /// `None` for no book.
+    book_file: Option<String>,
+    /// Always play the book's most heavily weighted move instead of picking by weight.
+    best_book_move: bool,
+    /// Directories with Syzygy tables, `None` for none.
+    syzygy_path: Option<String>,
+    /// A directory of tables made by `tbgen`, `None` for none.
+    dtm_path: Option<String>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Default)]
-struct Stats {
-    confidence: f32,
-    depth: u64,
-}
+const MAX_THREADS: usize = 256;
+const MAX_HASH: usize = 65536;
+const MAX_CONTEMPT: i64 = 100;
+/// What GUIs show for an empty string option.
+const EMPTY: &str = "<empty>";
 
-impl ThinkState {
-    fn new(
-        time_control: Option<UciTimeControl>,
-        search_control: Option<UciSearchControl>,
-        our_side: Side,
-    ) -> Self {
+impl Default for EngineOptions {
+    fn default() -> Self {
         Self {
-            start_time: Instant::now(),
-            time_control,
-            _search_control: search_control,
-            best_result: EngineResultState::Calculating,
-            our_side,
+            move_overhead: Duration::from_millis(30),
+            threads: 1,
+            hash: 16,
+            contempt: 0,
+            own_book: false,
+            book_file: None,
+            best_book_move: false,
+            syzygy_path: None,
+            dtm_path: None,
         }
     }
+}
 
-    fn adj_controls_for_ponder(&mut self) {
-        let time_since = self.start_time.elapsed();
-        if let Some(UciTimeControl::TimeLeft {
-            white_time,
-            black_time,
-            ..
-        }) = &mut self.time_control
-        {
-            let time = match self.our_side {
-                Side::White => white_time,
-                Side::Black => black_time,
-            };
-            if let Some(time) = time {
-                *time = time
-                    .checked_sub(&vampirc_uci::Duration::from_std(time_since).unwrap())
-                    .unwrap_or(vampirc_uci::Duration::milliseconds(100));
+impl EngineOptions {
+    fn announce() -> Vec<UciOptionConfig> {
+        let defaults = Self::default();
+        vec![
+            UciOptionConfig::Check {
+                name: "Ponder".into(),
+                default: Some(false),
+            },
+            UciOptionConfig::Spin {
+                name: "Move Overhead".into(),
+                default: Some(defaults.move_overhead.as_millis() as i64),
+                min: Some(0),
+                max: Some(5000),
+            },
+            UciOptionConfig::Spin {
+                name: "Threads".into(),
+                default: Some(defaults.threads as i64),
+                min: Some(1),
+                max: Some(MAX_THREADS as i64),
+            },
+            UciOptionConfig::Spin {
+                name: "Hash".into(),
+                default: Some(defaults.hash as i64),
+                min: Some(1),
+                max: Some(MAX_HASH as i64),
+            },
+            UciOptionConfig::Spin {
+                name: "Contempt".into(),
+                default: Some(defaults.contempt),
+                min: Some(-MAX_CONTEMPT),
+                max: Some(MAX_CONTEMPT),
+            },
+            UciOptionConfig::Check {
+                name: "OwnBook".into(),
+                default: Some(defaults.own_book),
+            },
+            UciOptionConfig::String {
+                name: "BookFile".into(),
+                default: Some(EMPTY.into()),
+            },
+            UciOptionConfig::Check {
+                name: "BestBookMove".into(),
+                default: Some(defaults.best_book_move),
+            },
+            UciOptionConfig::String {
+                name: "SyzygyPath".into(),
+                default: Some(EMPTY.into()),
+            },
+            UciOptionConfig::String {
+                name: "DtmPath".into(),
+                default: Some(EMPTY.into()),
+            },
+        ]
+    }
+
+    fn set(&mut self, name: &str, value: Option<&str>) -> bool {
+        let number = value.and_then(|v| v.trim().parse::<u64>().ok());
+        let check = value.and_then(|v| v.trim().parse::<bool>().ok());
+        match name.to_lowercase().as_str() {
+            "move overhead" => match number {
+                Some(ms) => self.move_overhead = Duration::from_millis(ms.min(5000)),
+                None => return false,
+            },
+            "threads" => match number {
+                Some(n) => self.threads = (n as usize).clamp(1, MAX_THREADS),
+                None => return false,
+            },
+            "hash" => match number {
+                Some(mb) => self.hash = (mb as usize).clamp(1, MAX_HASH),
+                None => return false,
+            },
+            "contempt" => match value.and_then(|v| v.trim().parse::<i64>().ok()) {
+                Some(cp) => self.contempt = cp.clamp(-MAX_CONTEMPT, MAX_CONTEMPT),
+                None => return false,
+            },
+            "ownbook" => match check {
+                Some(on) => self.own_book = on,
+                None => return false,
+            },
+            "bookfile" => {
+                let path = value.map(str::trim).unwrap_or_default();
+                self.book_file = match path {
+                    "" | EMPTY => None,
+                    path => Some(path.to_owned()),
+                };
+            }
+            "bestbookmove" => match check {
+                Some(on) => self.best_book_move = on,
+                None => return false,
+            },
+            "syzygypath" => {
+                let path = value.map(str::trim).unwrap_or_default();
+                self.syzygy_path = match path {
+                    "" | EMPTY => None,
+                    path => Some(path.to_owned()),
+                };
+            }
+            "dtmpath" => {
+                let path = value.map(str::trim).unwrap_or_default();
+                self.dtm_path = match path {
+                    "" | EMPTY => None,
+                    path => Some(path.to_owned()),
+                };
             }
+            // Only tells us whether the GUI intends to send `go ponder`.
+            "ponder" => {}
+            _ => return false,
         }
-        self.start_time = Instant::now();
+        true
     }
 }
 
-#[derive(Debug, Default, Clone, Copy)]
-enum EngineResultState {
-    #[default]
-    Calculating,
-    Ready(EngineResult),
-    Communicated(EngineResult),
+/// Why a `position` command could not be followed to the end.
+#[derive(Debug)]
+enum PositionError {
+    BadFen(String),
+    Invalid(InvalidPosition),
+    /// Not a move on the board at all, such as a promotion to a king.
+    BadMove(UciMove),
+    IllegalMove(IllegalMove),
 }
 
-#[derive(Default)]
-struct EngineInternals {
-    state: EngineState,
-    board: Board,
-    is_init: bool,
+impl Display for PositionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PositionError::BadFen(fen) => write!(f, "could not parse fen '{fen}'"),
+            PositionError::Invalid(err) => write!(f, "invalid position: {err}"),
+            PositionError::BadMove(mv) => write!(f, "could not parse move {mv}"),
+            PositionError::IllegalMove(err) => err.fmt(f),
+        }
+    }
 }
 
-pub struct Engine {
-    internals: Mutex<EngineInternals>,
-    main_task: Mutex<Option<tokio::task::JoinHandle<()>>>,
-    messages_recv: Mutex<UnboundedReceiver<UciMessage>>,
-    messages_send: UnboundedSender<UciMessage>,
+/// Everything that lives between `uci` and `quit`.
+struct Session {
+    board: Board,
+    /// Hashes of the positions that led to `board`, for repetition detection.
+    history: Vec<u64>,
+    options: EngineOptions,
+    /// Kept from one search to the next, until `ucinewgame`.
+    tt: Arc<TranspositionTable>,
+    /// Loaded from `BookFile`, whether or not `OwnBook` is on.
+    book: Option<Book>,
+    /// Found in `SyzygyPath`.
+    tablebases: Option<Arc<Tablebases>>,
+    /// Loaded from `DtmPath`.
+    dtm_tables: Option<Arc<DtmTables>>,
+    worker: SearchWorker,
 }
 
-impl Default for Engine {
-    fn default() -> Self {
-        let (send, recv) = unbounded_channel();
+impl Session {
+    fn new(output: Arc<dyn OutputSink>, logger: Arc<dyn Logger>, clock: Arc<dyn Clock>) -> Self {
+        let options = EngineOptions::default();
+        // Built now rather than in the middle of the first search that reaches it.
+        endgame::kpk_bitbase();
         Self {
-            internals: Default::default(),
-            messages_recv: Mutex::new(recv),
-            messages_send: send,
-            main_task: Default::default(),
+            board: Board::from_fen("startpos").unwrap(),
+            history: vec![],
+            tt: Arc::new(TranspositionTable::new(options.hash)),
+            options,
+            book: None,
+            tablebases: None,
+            dtm_tables: None,
+            worker: SearchWorker::spawn(output, logger, clock),
         }
     }
-}
-
-impl EngineInternals {
-    pub fn reset(&mut self) {
-        *self = Self::default();
-        self.is_init = true;
-    }
 
-    pub fn set_position(&mut self, startpos: bool, fen: Option<UciFen>, moves: &[UciMove]) {
+    /// Sets up the position, checking every move for legality. On error the board is left in
+    /// a playable state: the start position if the fen is unusable, otherwise the position
+    /// before the first illegal move.
+    fn set_position(
+        &mut self,
+        startpos: bool,
+        fen: Option<UciFen>,
+        moves: &[UciMove],
+    ) -> Result<(), PositionError> {
         let start_fen =
             vampirc_uci::UciFen::from("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
         let fen = if startpos {
-            start_fen
+            start_fen.clone()
         } else {
-            fen.unwrap_or(start_fen)
+            fen.unwrap_or_else(|| start_fen.clone())
         };
-        self.board = Board::from_fen(fen.as_str()).unwrap();
+        self.board = Board::from_fen(start_fen.as_str()).unwrap();
+        self.history.clear();
+        let mut board = Board::from_fen(fen.as_str())
+            .map_err(|_| PositionError::BadFen(fen.as_str().to_owned()))?;
+        board.sanitize();
+        board.validate().map_err(PositionError::Invalid)?;
+        self.board = board;
         for mv in moves {
-            self.board = self.board.clone().apply_move(&mv.into()).unwrap();
-        }
-    }
-
-    fn get_move_immediately(&self) -> EngineResult {
-        for mv in self.board.legal_moves() {
-            return EngineResult {
-                best_move: Some(mv.into()),
-                out_of_time: true,
-                ..Default::default()
-            };
-        }
-        EngineResult {
-            best_move: None,
-            ponder: None,
-            stats: Stats {
-                confidence: f32::INFINITY,
-                depth: 0,
-            },
-            out_of_time: true,
+            let mv = Move::try_from(mv).map_err(|_| PositionError::BadMove(*mv))?;
+            let board = self
+                .board
+                .clone()
+                .apply_legal_move(&mv)
+                .map_err(PositionError::IllegalMove)?;
+            self.history.push(self.board.hash());
+            self.board = board;
+            // Nothing before an irreversible move can come back.
+            if self.board.halfmove_clock() == 0 {
+                self.history.clear();
+            }
         }
+        Ok(())
     }
 }
 
-struct EngineTimes {
-    min: Duration,
-    max: Duration,
-}
-
-impl EngineTimes {
-    fn inf() -> Self {
-        Self {
-            min: Duration::from_secs(0),
-            max: Duration::from_secs(100000000),
-        }
-    }
+/// The UCI front end. Commands are handled as they arrive, on the caller's thread; searching
+/// happens on a separate thread, so `isready` and `stop` are answered even mid-search.
+pub struct Engine {
+    /// `None` until the GUI sends `uci`, and again after `quit`.
+    session: Mutex<Option<Session>>,
+    output: Arc<dyn OutputSink>,
+    logger: Arc<dyn Logger>,
+    clock: Arc<dyn Clock>,
 }
 
-fn calc_time_left(
-    white_time: Option<Duration>,
-    black_time: Option<Duration>,
-    white_increment: Option<Duration>,
-    black_increment: Option<Duration>,
-    _moves_to_go: Option<u8>,
-    our_side: Side,
-) -> EngineTimes {
-    eprintln!(
-        "calc_time_left({:?}, {:?}, {:?}, {:?}, {:?}, {:?})",
-        white_time, black_time, white_increment, black_increment, _moves_to_go, our_side
-    );
-    let (time, _inc) = match our_side {
-        Side::White => (white_time, white_increment),
-        Side::Black => (black_time, black_increment),
-    };
-    if let Some(time) = time {
-        return EngineTimes {
-            min: time / 20,
-            max: time / 10,
-        };
+/// Talks UCI over stdout and logs to stderr.
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new(Box::new(Stdout), Box::new(Stderr))
     }
-    // TODO: take all the inputs into account
-    //todo!()
-    EngineTimes::inf()
 }
 
 impl Engine {
-    async fn get_times(self: &Arc<Self>, state: &ThinkState) -> EngineTimes {
-        match &state.time_control {
-            Some(tc) => match tc {
-                UciTimeControl::Ponder => EngineTimes::inf(),
-                UciTimeControl::Infinite => EngineTimes::inf(),
-                UciTimeControl::TimeLeft {
-                    white_time,
-                    black_time,
-                    white_increment,
-                    black_increment,
-                    moves_to_go,
-                } => calc_time_left(
-                    white_time.map(|x| x.to_std().unwrap_or_default()),
-                    black_time.map(|x| x.to_std().unwrap_or_default()),
-                    white_increment.map(|x| x.to_std().unwrap_or_default()),
-                    black_increment.map(|x| x.to_std().unwrap_or_default()),
-                    *moves_to_go,
-                    state.our_side,
-                ),
-                UciTimeControl::MoveTime(x) => EngineTimes {
-                    min: x.to_std().unwrap_or_default(),
-                    max: x.to_std().unwrap_or_default(),
-                },
-            },
-            None => EngineTimes {
-                min: Duration::from_millis(5000),
-                max: Duration::from_secs(10),
-            },
-        }
+    /// Creates an engine that writes its UCI responses to `output` and its diagnostics to
+    /// `logger`. Engines share nothing, so several can run side by side in one process.
+    pub fn new(output: Box<dyn OutputSink>, logger: Box<dyn Logger>) -> Self {
+        Self::with_clock(output, logger, Arc::new(SystemClock))
     }
 
-    async fn find_moves(self: &Arc<Self>, state: &ThinkState, past_min_time: bool) -> EngineResult {
-        let depth = match state.best_result {
-            EngineResultState::Calculating => 1,
-            EngineResultState::Ready(last) => last.stats.depth + 2,
-            EngineResultState::Communicated(_) => panic!("Engine is not in a state to calculate"),
-        };
-        eprintln!("find_moves {} {}", past_min_time, depth);
-        let board = self.internals.lock().await.board.clone();
-        let settings = SearchSettings {
-            depth,
-            divide: false,
-            ab_prune: true,
-        };
-        let mut res = { tokio::task::spawn_blocking(move || board.alphabeta(&settings, true)) }
-            .await
-            .unwrap();
-        //eprintln!("got data: {:#?}", res.data);
-        let best = res.data.pop();
-        let response = res.data.pop();
-        let confidence = (depth as f32) - 7.;
-        EngineResult {
-            best_move: best.map(|x| x.mv.into()),
-            stats: Stats { confidence, depth },
-            ponder: response.map(|x| x.mv.into()),
-            ..Default::default()
+    /// Like [`Engine::new`], but searches take their time from `clock`.
+    pub fn with_clock(
+        output: Box<dyn OutputSink>,
+        logger: Box<dyn Logger>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self {
+            session: Mutex::new(None),
+            output: output.into(),
+            logger: logger.into(),
+            clock,
         }
     }
 
-    async fn get_last_result(self: &Arc<Self>, state: &ThinkState) -> EngineResult {
-        match state.best_result {
-            EngineResultState::Calculating => self.internals.lock().await.get_move_immediately(),
-            EngineResultState::Ready(last) => last,
-            EngineResultState::Communicated(last) => last,
+    fn init_uci(&self, session: &mut Option<Session>) {
+        self.send_uci_message(UciMessage::Id {
+            name: Some("Rust Chess".into()),
+            author: None,
+        });
+        self.send_uci_message(UciMessage::Id {
+            name: None,
+            author: Some("Daniel Bittman".into()),
+        });
+        for option in EngineOptions::announce() {
+            self.send_uci_message(UciMessage::Option(option));
         }
+        // Replacing the session ends any search left over from before, without a bestmove.
+        *session = None;
+        *session = Some(Session::new(
+            self.output.clone(),
+            self.logger.clone(),
+            self.clock.clone(),
+        ));
+        self.send_uci_message(UciMessage::UciOk);
     }
 
-    #[async_recursion::async_recursion]
-    async fn calculate(self: &Arc<Self>) -> EngineResult {
-        let last_state = match &self.internals.lock().await.state {
-            EngineState::Going(state) => state.clone(),
-            EngineState::Pondering(state) => state.clone(),
-            _ => {
-                panic!("Engine is not in a state to calculate")
-            }
-        };
-
-        let times = self.get_times(&last_state).await;
-
-        let elapsed = last_state.start_time.elapsed();
-        let past_min_time = elapsed >= times.min;
-        let is_pondering = self.internals.lock().await.state.is_pondering()
-            || last_state
-                .time_control
-                .as_ref()
-                .map(|tc| matches!(tc, UciTimeControl::Ponder))
-                .unwrap_or_default();
+    pub fn send_uci_message(&self, uci: UciMessage) {
+        self.output.send(uci);
+    }
 
-        let is_infinite = last_state
-            .time_control
-            .as_ref()
-            .map(|tc| matches!(tc, UciTimeControl::Infinite))
-            .unwrap_or_default();
+    fn log(&self, args: Arguments<'_>) {
+        self.logger.log(args);
+    }
 
-        let past_max_time = elapsed >= times.max && !is_pondering && !is_infinite;
+    pub fn send_info_string(&self, s: String) {
+        self.send_uci_message(UciMessage::Info(vec![UciInfoAttribute::String(s)]));
+    }
 
-        let remaining = if is_pondering || is_infinite {
-            Duration::from_secs(100000000)
-        } else {
-            match (times.max - elapsed).checked_div(2) {
-                Some(x) => x,
-                None => return self.calculate().await,
-            }
-        };
+    pub fn is_init(&self) -> bool {
+        self.session.lock().unwrap().is_some()
+    }
 
-        eprintln!(
-            "calculate {} {} {} {}",
-            past_min_time,
-            past_max_time,
-            elapsed.as_millis(),
-            remaining.as_millis()
-        );
-        if past_max_time {
-            return self.get_last_result(&last_state).await;
+    /// The position the next `go` would search. That is the start position until the GUI
+    /// says otherwise.
+    pub fn board(&self) -> Board {
+        match &*self.session.lock().unwrap() {
+            Some(session) => session.board.clone(),
+            None => Board::from_fen("startpos").unwrap(),
         }
-        match tokio::time::timeout(remaining, self.find_moves(&last_state, past_min_time)).await {
-            Ok(result) => result,
-            Err(_) => {
-                let mut result = self.get_last_result(&last_state).await;
-                result.out_of_time = true;
-                result
-            }
+    }
+
+    pub fn search_state(&self) -> SearchState {
+        match &*self.session.lock().unwrap() {
+            Some(session) => session.worker.state(),
+            None => SearchState::Idle,
         }
     }
 
-    fn send_bestmove(self: &Arc<Self>, mv: EngineResult) {
-        self.send_uci_message(UciMessage::BestMove {
-            best_move: mv.best_move.unwrap(),
-            ponder: mv.ponder,
-        });
+    /// Parses and handles one line of UCI input, including `go ponder`.
+    pub fn handle_uci_line(&self, line: &str) {
+        let (uci, ponder) = parse_uci_line(line);
+        self.handle_command(uci, ponder);
     }
 
-    async fn handle_message(self: &Arc<Self>, msg: UciMessage) {
-        eprintln!("got message: {:?}", msg);
-        match msg {
+    pub fn handle_uci_message(&self, uci: UciMessage) {
+        self.handle_command(uci, false);
+    }
+
+    fn handle_command(&self, uci: UciMessage, ponder: bool) {
+        self.log(format_args!("uci message: {} (ponder: {})", uci, ponder));
+        let mut guard = self.session.lock().unwrap();
+        if uci == UciMessage::Uci {
+            self.init_uci(&mut guard);
+            return;
+        }
+        let Some(session) = guard.as_mut() else {
+            self.log(format_args!("UCI message while not in UCI mode {}", uci));
+            return;
+        };
+        match uci {
+            //UciMessage::Debug(_) => todo!(),
+            UciMessage::IsReady => {
+                self.send_uci_message(UciMessage::ReadyOk);
+            }
+            //UciMessage::Register { later, name, code } => todo!(),
             UciMessage::Position {
                 startpos,
                 fen,
                 moves,
             } => {
-                self.internals
-                    .lock()
-                    .await
-                    .set_position(startpos, fen, &moves);
-                self.internals.lock().await.state = EngineState::Stopped;
-            }
-            UciMessage::Go {
-                time_control,
-                search_control,
-            } => {
-                let side = self.internals.lock().await.board.to_move();
-                if let Some(UciTimeControl::Ponder) = time_control.as_ref() {
-                    self.internals.lock().await.state =
-                        EngineState::Pondering(ThinkState::new(time_control, search_control, side));
-                } else {
-                    self.internals.lock().await.state =
-                        EngineState::Going(ThinkState::new(time_control, search_control, side));
+                if let Err(err) = session.set_position(startpos, fen, &moves) {
+                    let fen = session.board.to_fen();
+                    self.send_info_string(format!("{err}; using position {fen}"));
                 }
-                // TODO: put something, anything, into the engine result.
             }
-            UciMessage::Stop => {
-                let mut internal = self.internals.lock().await;
-                match &mut internal.state {
-                    EngineState::Going(state) => match state.best_result {
-                        EngineResultState::Ready(res) => {
-                            self.send_bestmove(res);
+            UciMessage::SetOption { name, value } => {
+                if !session.options.set(&name, value.as_deref()) {
+                    self.log(format_args!("unknown or bad option {name} = {value:?}"));
+                }
+                if session.tt.megabytes() != session.options.hash {
+                    session.tt = Arc::new(TranspositionTable::new(session.options.hash));
+                }
+                if name.eq_ignore_ascii_case("bookfile") {
+                    session.book = None;
+                    if let Some(path) = &session.options.book_file {
+                        match Book::open(path) {
+                            Ok(book) => session.book = Some(book),
+                            Err(err) => {
+                                self.send_info_string(format!("could not read book {path}: {err}"))
+                            }
                         }
-                        _ => {}
-                    },
-                    EngineState::Pondering(state) => match state.best_result {
-                        EngineResultState::Ready(res) => {
-                            self.send_bestmove(res);
+                    }
+                }
+                if name.eq_ignore_ascii_case("syzygypath") {
+                    session.tablebases = None;
+                    if let Some(path) = &session.options.syzygy_path {
+                        let tablebases = Tablebases::new(path);
+                        self.send_info_string(format!(
+                            "found {} tablebases with up to {} pieces",
+                            tablebases.count(),
+                            tablebases.max_pieces()
+                        ));
+                        session.tablebases = Some(Arc::new(tablebases)).filter(|tb| tb.count() > 0);
+                    }
+                }
+                if name.eq_ignore_ascii_case("dtmpath") {
+                    session.dtm_tables = None;
+                    if let Some(path) = &session.options.dtm_path {
+                        match DtmTables::load(path) {
+                            Ok(tables) => {
+                                self.send_info_string(format!(
+                                    "loaded {} generated tables",
+                                    tables.len()
+                                ));
+                                session.dtm_tables = Some(Arc::new(tables));
+                            }
+                            Err(err) => self.send_info_string(format!(
+                                "could not read tables in {path}: {err}"
+                            )),
                         }
-                        _ => {}
-                    },
-                    _ => {}
+                    }
                 }
-                internal.state = EngineState::Stopped;
             }
-            UciMessage::PonderHit => {
-                let mut internal = self.internals.lock().await;
-                match &internal.state {
-                    EngineState::Pondering(state) => {
-                        internal.state = EngineState::Going(state.clone());
+            UciMessage::UciNewGame => session.tt.clear(),
+            UciMessage::Stop => session.worker.stop(),
+            UciMessage::PonderHit => session.worker.ponderhit(),
+            UciMessage::Quit => {
+                // Dropping the worker ends the search without a bestmove.
+                *guard = None;
+            }
+            UciMessage::Go {
+                time_control,
+                search_control,
+            } => {
+                // A bare `go ponder` carries no clock, so the default controls apply on ponderhit.
+                let (time_control, ponder) = match time_control {
+                    Some(vampirc_uci::UciTimeControl::Ponder) => (None, true),
+                    time_control => (time_control, ponder),
+                };
+                let infinite = time_control == Some(vampirc_uci::UciTimeControl::Infinite);
+                if let Some(book) = session.book.as_ref().filter(|_| session.options.own_book) {
+                    let best = session.options.best_book_move;
+                    let book_move = book.pick(&session.board, best, &mut rand::thread_rng());
+                    if let Some(mv) = book_move.filter(|_| !ponder && !infinite) {
+                        self.send_uci_message(UciMessage::BestMove {
+                            best_move: mv.into(),
+                            ponder: None,
+                        });
+                        return;
                     }
-                    _ => {}
                 }
+                session.worker.go(SearchJob {
+                    board: session.board.clone(),
+                    started: self.clock.now(),
+                    time_control,
+                    search_control,
+                    ponder,
+                    move_overhead: session.options.move_overhead,
+                    threads: session.options.threads,
+                    tt: session.tt.clone(),
+                    history: session.history.clone(),
+                    contempt: session.options.contempt as f32 / 100.0,
+                    tablebases: session.tablebases.clone(),
+                    dtm_tables: session.dtm_tables.clone(),
+                });
             }
-            UciMessage::UciNewGame => {
-                self.internals.lock().await.state = EngineState::Stopped;
+            //UciMessage::Id { name, author } => todo!(),
+            //UciMessage::UciOk => todo!(),
+            //UciMessage::ReadyOk => todo!(),
+            //UciMessage::BestMove { best_move, ponder } => todo!(),
+            //UciMessage::CopyProtection(_) => todo!(),
+            //UciMessage::Registration(_) => todo!(),
+            //UciMessage::Option(_) => todo!(),
+            //UciMessage::Info(_) => todo!(),
+            //UciMessage::Unknown(_, _) => todo!(),
+            _ => {
+                self.log(format_args!("unknown UCI message {}", uci));
             }
-            _ => {}
         }
     }
+}
 
-    async fn record_bestmove(self: &Arc<Self>, result: EngineResult) {
-        let mut internal = self.internals.lock().await;
-        match &mut internal.state {
-            EngineState::Going(state) => {
-                if let EngineResultState::Communicated(x) = state.best_result {
-                    if x == result {
-                        return;
-                    }
-                }
-                state.best_result = EngineResultState::Ready(result);
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::{
+        io::{AsyncBufReadExt, BufReader},
+        sync::mpsc::{unbounded_channel, UnboundedReceiver},
+        time::Instant,
+    };
+    use vampirc_uci::{UciInfoAttribute, UciMessage, UciMove, UciSquare};
+
+    use crate::chess::{
+        board::Board,
+        io::{Quiet, WriterSink},
+        moves::Move,
+        polyglot::{encode_move, Book, BookEntry},
+        retro::{generate_table_files, Dtm, DtmTables},
+        timeman::{ManualClock, SystemClock},
+        worker::SearchState,
+    };
+
+    use super::{parse_uci_line, Engine, Session};
+
+    /// Drives an engine in-process and captures everything it says. The engine's clock only
+    /// moves when the test advances it.
+    struct TestEngine {
+        engine: Arc<Engine>,
+        clock: Arc<ManualClock>,
+        output: UnboundedReceiver<UciMessage>,
+        sent: Vec<UciMessage>,
+    }
+
+    impl TestEngine {
+        fn new() -> Self {
+            let (send, output) = unbounded_channel();
+            let clock = Arc::new(ManualClock::default());
+            Self {
+                engine: Arc::new(Engine::with_clock(
+                    Box::new(send),
+                    Box::new(Quiet),
+                    clock.clone(),
+                )),
+                clock,
+                output,
+                sent: vec![],
             }
-            EngineState::Pondering(state) => {
-                if let EngineResultState::Communicated(x) = state.best_result {
-                    if x == result {
-                        return;
-                    }
-                }
-                state.best_result = EngineResultState::Ready(result);
+        }
+
+        fn send(&self, msg: UciMessage) {
+            self.engine.handle_uci_message(msg);
+        }
+
+        /// Feeds lines of UCI text, parsed the same way as stdin.
+        fn script(&self, lines: &[&str]) {
+            for line in lines {
+                self.engine.handle_uci_line(line);
             }
-            _ => {}
         }
-    }
 
-    async fn should_send_bestmove(&self) -> Option<EngineResult> {
-        let mut internal = self.internals.lock().await;
-        eprintln!("should_send_bestmove: {:?}", internal.state);
-        match &mut internal.state {
-            EngineState::Going(state) => match state.best_result {
-                EngineResultState::Ready(x) => {
-                    if !x.out_of_time && x.stats.confidence < 0.0 {
-                        return None;
+        /// Waits for the next message matching `pred`. Messages passed over are kept.
+        async fn expect(
+            &mut self,
+            timeout: Duration,
+            pred: impl Fn(&UciMessage) -> bool,
+        ) -> UciMessage {
+            let output = &mut self.output;
+            let sent = &mut self.sent;
+            let res = tokio::time::timeout(timeout, async {
+                loop {
+                    let msg = output.recv().await.expect("engine output closed");
+                    sent.push(msg.clone());
+                    if pred(&msg) {
+                        return msg;
                     }
-                    state.best_result = EngineResultState::Communicated(x.clone());
-                    return Some(x);
                 }
-                _ => return None,
-            },
-            _ => None,
+            })
+            .await;
+            res.unwrap_or_else(|_| panic!("timed out, engine sent {:?}", self.sent))
         }
-    }
 
-    pub async fn main_task_engine(self: &Arc<Self>) {
-        loop {
-            let state = self.internals.lock().await.state.clone();
-            //eprintln!("top of loop: {:?}", state);
-            if !state.is_stopped() {
-                let self2 = self.clone();
-                let calc = spawn(async move { self2.calculate().await });
-                let mut messages_recv = self.messages_recv.lock().await;
-                let msg = messages_recv.recv();
-                select! {
-                    calc = calc => {
-                        self.record_bestmove(calc.unwrap()).await;
-                        if let Some(mv) = self.should_send_bestmove().await {
-                            self.send_bestmove(mv);
-                            let mut internal = self.internals.lock().await;
-                            if let Some(ourmv) = mv.best_move && let Some(ponder) = mv.ponder && let EngineState::Going(mut state) = internal.state.clone() && false {
-                                let board = internal.board.clone();
-                                let ourmv: crate::chess::moves::Move = (&ourmv).into();
-                                let ponder: crate::chess::moves::Move = (&ponder).into();
-                                let board = board.apply_move(&ourmv).unwrap();
-                                let board = board.apply_move(&ponder).unwrap();
-                                internal.board = board;
-                                state.adj_controls_for_ponder();
-                                state.best_result = EngineResultState::Calculating;
-                                internal.state = EngineState::Pondering(state);
-                            } else {
-                                internal.state = EngineState::Stopped;
-                            }
-                        }
-                    },
-                    msg = msg => {
-                        self.handle_message(msg.unwrap()).await;
-                    }
-                }
-            } else {
-                let mut messages_recv = self.messages_recv.lock().await;
-                let msg = messages_recv.recv();
-                self.handle_message(msg.await.unwrap()).await;
+        async fn expect_bestmove(&mut self, timeout: Duration) -> UciMessage {
+            self.expect(timeout, |msg| matches!(msg, UciMessage::BestMove { .. }))
+                .await
+        }
+
+        /// Returns what the engine has said and the test hasn't looked at yet.
+        fn drain(&mut self) -> Vec<UciMessage> {
+            let mut msgs = vec![];
+            while let Ok(msg) = self.output.try_recv() {
+                self.sent.push(msg.clone());
+                msgs.push(msg);
             }
+            msgs
         }
-    }
 
-    pub async fn init_uci(self: &Arc<Self>) {
-        self.send_uci_message(UciMessage::Id {
-            name: Some("Rust Chess".into()),
-            author: None,
-        });
-        self.send_uci_message(UciMessage::Id {
-            name: None,
-            author: Some("Daniel Bittman".into()),
-        });
-        *self.main_task.lock().await = None;
-        {
-            self.internals.lock().await.reset();
+        fn advance(&self, ms: u64) {
+            self.clock.advance(Duration::from_millis(ms));
         }
-        let self2 = self.clone();
-        self.main_task
-            .lock()
-            .await
-            .replace(spawn(async move { self2.main_task_engine().await }));
 
-        self.send_uci_message(UciMessage::UciOk);
+        /// Waits until the search thread has dealt with everything sent so far, by queueing a
+        /// search behind it. That search stops any running one, sets up a position of its own,
+        /// and its bestmove is left out of `sent`. Its only piece that can move is the king on
+        /// a1, so its bestmove can't be mistaken for a late one from the test's searches.
+        async fn sync(&mut self) {
+            self.script(&["position fen k7/8/8/8/8/8/8/K7 w - - 0 1", "go depth 1"]);
+            self.expect(WAIT, |msg| {
+                matches!(msg, UciMessage::BestMove { best_move, .. }
+                    if best_move.from == UciSquare::from('a', 1))
+            })
+            .await;
+            self.sent.pop();
+        }
+
+        async fn wait_for_state(&self, state: SearchState) {
+            let start = Instant::now();
+            while self.engine.search_state() != state {
+                assert!(start.elapsed() < WAIT, "never reached {state:?}");
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }
+
+        fn count(&self, pred: impl Fn(&UciMessage) -> bool) -> usize {
+            self.sent.iter().filter(|msg| pred(msg)).count()
+        }
+
+        fn bestmoves(&self) -> usize {
+            self.count(|msg| matches!(msg, UciMessage::BestMove { .. }))
+        }
+
+        fn info_strings(&self) -> usize {
+            self.count(|msg| {
+                matches!(msg, UciMessage::Info(attrs)
+                    if matches!(attrs.first(), Some(UciInfoAttribute::String(_))))
+            })
+        }
     }
 
-    pub fn send_uci_message(&self, uci: UciMessage) {
-        println!("{}", uci.to_string());
+    fn assert_legal(board: &Board, msg: &UciMessage) {
+        let UciMessage::BestMove { best_move, .. } = msg else {
+            panic!("not a bestmove: {msg}");
+        };
+        let mv = Move::try_from(best_move).unwrap();
+        assert!(board.legal_moves().any(|m| m == mv), "illegal {msg}");
     }
 
-    pub async fn is_init(&self) -> bool {
-        self.internals.lock().await.is_init
+    fn startpos() -> Board {
+        Board::from_fen("startpos").unwrap()
     }
 
-    pub async fn handle_uci_message(self: &Arc<Self>, uci: UciMessage) {
-        eprintln!("uci message: {}", uci.to_string());
-        if !self.is_init().await {
-            if uci != UciMessage::Uci {
-                eprintln!("UCI message while not in UCI mode {}", uci.to_string());
-                return;
-            }
-        }
-        match &uci {
-            UciMessage::Uci => {
-                self.init_uci().await;
+    /// The position after `position startpos moves e2e4 e7e5`.
+    fn ponder_board() -> Board {
+        Board::from_fen("rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2").unwrap()
+    }
+
+    /// Only runs out if the engine hangs. Searches don't time out on their own in these tests.
+    const WAIT: Duration = Duration::from_secs(10);
+
+    #[tokio::test]
+    async fn test_uci_handshake() {
+        let mut t = TestEngine::new();
+        t.script(&["isready", "position startpos", "go movetime 10"]);
+        assert!(t.drain().is_empty());
+
+        t.send(UciMessage::Uci);
+        t.expect(WAIT, |msg| *msg == UciMessage::UciOk).await;
+        assert!(matches!(
+            t.sent[0],
+            UciMessage::Id {
+                name: Some(_),
+                author: None
             }
-            //UciMessage::Debug(_) => todo!(),
-            UciMessage::IsReady => {
-                self.send_uci_message(UciMessage::ReadyOk);
+        ));
+        assert!(matches!(
+            t.sent[1],
+            UciMessage::Id {
+                name: None,
+                author: Some(_)
             }
-            //UciMessage::Register { later, name, code } => todo!(),
-            UciMessage::Position { .. } => {}
-            UciMessage::SetOption { .. } => {}
-            UciMessage::UciNewGame => {}
-            UciMessage::Stop => {}
-            UciMessage::PonderHit => {}
-            UciMessage::Quit => {
-                self.main_task.lock().await.take().unwrap().abort();
+        ));
+        assert!(t.sent[2..t.sent.len() - 1]
+            .iter()
+            .all(|msg| matches!(msg, UciMessage::Option(_))));
+        t.send(UciMessage::IsReady);
+        t.expect(WAIT, |msg| *msg == UciMessage::ReadyOk).await;
+    }
+
+    #[tokio::test]
+    async fn test_isready_during_search() {
+        let mut t = TestEngine::new();
+        t.script(&["uci", "position startpos", "go infinite"]);
+        t.expect(WAIT, |msg| matches!(msg, UciMessage::Info(_)))
+            .await;
+        t.send(UciMessage::IsReady);
+        t.expect(WAIT, |msg| *msg == UciMessage::ReadyOk).await;
+        // An infinite search only ends on stop, however long it runs.
+        t.advance(3_600_000);
+        t.drain();
+        assert_eq!(t.engine.search_state(), SearchState::Searching);
+        assert_eq!(t.bestmoves(), 0);
+
+        t.send(UciMessage::Stop);
+        let mv = t.expect_bestmove(WAIT).await;
+        assert_legal(&startpos(), &mv);
+        t.sync().await;
+        assert_eq!(t.bestmoves(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_search_states() {
+        let mut t = TestEngine::new();
+        t.script(&["uci", "position startpos moves e2e4 e7e5"]);
+        assert_eq!(t.engine.search_state(), SearchState::Idle);
+
+        t.script(&["go ponder infinite"]);
+        assert_eq!(t.engine.search_state(), SearchState::Pondering);
+        t.script(&["ponderhit"]);
+        t.wait_for_state(SearchState::Searching).await;
+        t.script(&["stop"]);
+        t.expect_bestmove(WAIT).await;
+        assert_eq!(t.engine.search_state(), SearchState::Idle);
+
+        // A go during a search ends it like a stop would, and each go gets its bestmove.
+        t.script(&["go infinite", "go infinite"]);
+        t.expect_bestmove(WAIT).await;
+        t.wait_for_state(SearchState::Searching).await;
+        t.script(&["stop"]);
+        t.expect_bestmove(WAIT).await;
+        t.wait_for_state(SearchState::Idle).await;
+        t.sync().await;
+        assert_eq!(t.bestmoves(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_threads() {
+        let mut t = TestEngine::new();
+        t.script(&[
+            "uci",
+            "setoption name Threads value 4",
+            "position startpos moves e2e4 e7e5",
+            "go depth 3",
+        ]);
+        let mv = t.expect_bestmove(WAIT).await;
+        assert_legal(&ponder_board(), &mv);
+
+        let infos: Vec<_> = t
+            .sent
+            .iter()
+            .filter_map(|msg| match msg {
+                UciMessage::Info(attrs) => Some(attrs),
+                _ => None,
+            })
+            .collect();
+        let depths: Vec<u8> = infos
+            .iter()
+            .flat_map(|attrs| attrs.iter())
+            .filter_map(|attr| match attr {
+                UciInfoAttribute::Depth(d) => Some(*d),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(depths, [1, 3]);
+        // Node counts cover all threads, so they only ever go up.
+        let nodes: Vec<u64> = infos
+            .iter()
+            .flat_map(|attrs| attrs.iter())
+            .filter_map(|attr| match attr {
+                UciInfoAttribute::Nodes(n) => Some(*n),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(nodes.len(), 2);
+        assert!(nodes[0] > 0 && nodes[0] <= nodes[1]);
+    }
+
+    #[test]
+    fn test_position_history() {
+        let (send, _output) = unbounded_channel();
+        let mut session = Session::new(Arc::new(send), Arc::new(Quiet), Arc::new(SystemClock));
+        let mut set = |line: &str| {
+            let (
+                UciMessage::Position {
+                    startpos,
+                    fen,
+                    moves,
+                },
+                _,
+            ) = parse_uci_line(line)
+            else {
+                panic!("not a position: {line}");
+            };
+            session.set_position(startpos, fen, &moves).unwrap();
+            session.history.clone()
+        };
+
+        let start = startpos().hash();
+        let history = set("position startpos moves g1f3 g8f6 f3g1 f6g8 g1f3");
+        assert_eq!(history.len(), 5);
+        assert_eq!(history[0], start);
+        assert_eq!(history[4], start);
+
+        // The pawn move makes everything before it unreachable.
+        let history = set("position startpos moves g1f3 g8f6 e2e4 b8c6");
+        assert_eq!(history.len(), 1);
+        let after_e4 = "rnbqkb1r/pppppppp/5n2/8/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq e3 0 2";
+        assert_eq!(history[0], Board::from_fen(after_e4).unwrap().hash());
+    }
+
+    #[tokio::test]
+    async fn test_go_movetime() {
+        let mut t = TestEngine::new();
+        t.script(&[
+            "uci",
+            "position startpos moves e2e4 e7e5",
+            "go movetime 1000",
+        ]);
+        // No time has passed, so the search can't be out of it.
+        t.expect(WAIT, |msg| matches!(msg, UciMessage::Info(_)))
+            .await;
+        assert_eq!(t.engine.search_state(), SearchState::Searching);
+        t.advance(1000);
+        let mv = t.expect_bestmove(WAIT).await;
+        assert_legal(&ponder_board(), &mv);
+        t.sync().await;
+        assert_eq!(t.bestmoves(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_go_clock() {
+        let mut t = TestEngine::new();
+        t.script(&[
+            "uci",
+            "position startpos moves e2e4",
+            "go wtime 100 btime 1000 winc 0 binc 0",
+        ]);
+        // Black has a second left and budgets a small part of it.
+        t.advance(200);
+        let mv = t.expect_bestmove(WAIT).await;
+        let board = startpos()
+            .apply_move(
+                &Move::try_from(&vampirc_uci::UciMove::from_to(
+                    UciSquare::from('e', 2),
+                    UciSquare::from('e', 4),
+                ))
+                .unwrap(),
+            )
+            .unwrap();
+        assert_legal(&board, &mv);
+    }
+
+    #[tokio::test]
+    async fn test_stop_yields_one_bestmove() {
+        let mut t = TestEngine::new();
+        t.script(&["uci", "position startpos"]);
+
+        // Nothing to stop.
+        t.send(UciMessage::Stop);
+        t.sync().await;
+        assert_eq!(t.bestmoves(), 0);
+
+        // Stopped before the search had a chance to produce anything.
+        t.script(&["position startpos", "go movetime 100000", "stop"]);
+        let mv = t.expect_bestmove(WAIT).await;
+        assert_legal(&startpos(), &mv);
+
+        // Stopped twice.
+        t.script(&["go infinite"]);
+        t.expect(WAIT, |msg| matches!(msg, UciMessage::Info(_)))
+            .await;
+        t.script(&["stop", "stop"]);
+        let mv = t.expect_bestmove(WAIT).await;
+        assert_legal(&startpos(), &mv);
+
+        // Stopped after the bestmove was already sent.
+        t.script(&["go movetime 50"]);
+        t.advance(50);
+        t.expect_bestmove(WAIT).await;
+        t.script(&["stop"]);
+        t.sync().await;
+        assert_eq!(t.bestmoves(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_ponderhit() {
+        let mut t = TestEngine::new();
+        t.script(&[
+            "uci",
+            "position startpos moves e2e4 e7e5",
+            "go ponder wtime 2000 btime 2000",
+        ]);
+        // Pondering has no time limit, however long the opponent takes.
+        t.advance(1900);
+        t.drain();
+        assert_eq!(t.engine.search_state(), SearchState::Pondering);
+        assert_eq!(t.bestmoves(), 0, "bestmove sent while pondering");
+
+        // The time spent pondering came off our clock. With the 100ms that are left, 20ms is
+        // more than the search may take; with the full two seconds it would not be.
+        t.script(&["ponderhit"]);
+        t.advance(20);
+        let mv = t.expect_bestmove(WAIT).await;
+        assert_legal(&ponder_board(), &mv);
+        t.sync().await;
+        assert_eq!(t.bestmoves(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_ponder_stop() {
+        let mut t = TestEngine::new();
+        t.script(&[
+            "uci",
+            "position startpos moves e2e4 e7e5",
+            "go ponder wtime 2000 btime 2000",
+        ]);
+        t.expect(WAIT, |msg| matches!(msg, UciMessage::Info(_)))
+            .await;
+        t.script(&["stop"]);
+        let mv = t.expect_bestmove(WAIT).await;
+        assert_legal(&ponder_board(), &mv);
+
+        // The discarded ponder search must not interfere with the next one.
+        t.script(&["position startpos moves e2e4 e7e5 g1f3", "go movetime 200"]);
+        t.advance(200);
+        t.expect_bestmove(WAIT).await;
+        t.sync().await;
+        assert_eq!(t.bestmoves(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_ucinewgame() {
+        let mut t = TestEngine::new();
+        t.script(&["uci", "ucinewgame", "isready"]);
+        t.expect(WAIT, |msg| *msg == UciMessage::ReadyOk).await;
+        t.script(&["position startpos moves e2e4 e7e5", "go movetime 100"]);
+        t.advance(100);
+        t.expect_bestmove(WAIT).await;
+
+        t.script(&["ucinewgame", "isready"]);
+        t.expect(WAIT, |msg| *msg == UciMessage::ReadyOk).await;
+        t.script(&["position startpos", "go movetime 100"]);
+        t.advance(100);
+        let mv = t.expect_bestmove(WAIT).await;
+        assert_legal(&startpos(), &mv);
+    }
+
+    #[tokio::test]
+    async fn test_quit() {
+        let mut t = TestEngine::new();
+        t.script(&["uci", "position startpos", "go infinite"]);
+        t.expect(WAIT, |msg| matches!(msg, UciMessage::Info(_)))
+            .await;
+        // Quitting waits for the search thread, so it has said all it ever will.
+        t.send(UciMessage::Quit);
+        t.drain();
+        t.script(&["stop", "isready"]);
+        assert!(t.drain().is_empty());
+        assert_eq!(t.bestmoves(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_illegal_position() {
+        let mut t = TestEngine::new();
+        t.script(&[
+            "uci",
+            "position startpos moves e2e4 e7e5 e1e3 d7d5",
+            "go movetime 100",
+        ]);
+        t.advance(100);
+        let mv = t.expect_bestmove(WAIT).await;
+        assert_eq!(t.info_strings(), 1);
+        assert_legal(&ponder_board(), &mv);
+
+        // No kings at all: fall back to the start position.
+        t.script(&["position fen 8/8/8/8/8/8/8/8 w - - 0 1", "go movetime 100"]);
+        t.advance(100);
+        let mv = t.expect_bestmove(WAIT).await;
+        assert_eq!(t.info_strings(), 2);
+        assert_legal(&startpos(), &mv);
+
+        // Squares that don't exist on the board.
+        t.send(UciMessage::Position {
+            startpos: true,
+            fen: None,
+            moves: vec![UciMove::from_to(
+                UciSquare::from('e', 2),
+                UciSquare::from('e', 9),
+            )],
+        });
+        t.script(&["go movetime 100"]);
+        t.advance(100);
+        let mv = t.expect_bestmove(WAIT).await;
+        assert_eq!(t.info_strings(), 3);
+        assert_legal(&startpos(), &mv);
+    }
+
+    #[tokio::test]
+    async fn test_engines_side_by_side() {
+        let mut a = TestEngine::new();
+        let mut b = TestEngine::new();
+        a.script(&["uci", "position startpos", "go movetime 100"]);
+        b.script(&[
+            "uci",
+            "position startpos moves e2e4 e7e5",
+            "go movetime 100",
+        ]);
+        a.advance(100);
+        b.advance(100);
+        let mv = a.expect_bestmove(WAIT).await;
+        assert_legal(&startpos(), &mv);
+        let mv = b.expect_bestmove(WAIT).await;
+        assert_legal(&ponder_board(), &mv);
+    }
+
+    #[tokio::test]
+    async fn test_own_book() {
+        let board = startpos();
+        let d4 = board.parse_san("d4").unwrap();
+        let book = Book::new(vec![BookEntry {
+            key: board.polyglot_key(),
+            mv: encode_move(&d4, &board),
+            weight: 1,
+            learn: 0,
+        }]);
+        let path = std::env::temp_dir().join(format!("book-{}.bin", std::process::id()));
+        book.write(&mut std::fs::File::create(&path).unwrap())
+            .unwrap();
+
+        let mut t = TestEngine::new();
+        let path = path.to_str().unwrap();
+        t.script(&[
+            "uci",
+            &format!("setoption name BookFile value {path}"),
+            "position startpos",
+            "go movetime 5000",
+        ]);
+        // The book is only used with OwnBook on.
+        t.drain();
+        assert_eq!(t.bestmoves(), 0);
+        t.script(&["stop"]);
+        t.expect_bestmove(WAIT).await;
+
+        // Answered right away, without a search.
+        t.script(&["setoption name OwnBook value true", "go movetime 5000"]);
+        let msg = t.drain().pop().unwrap();
+        assert_eq!(
+            msg,
+            UciMessage::BestMove {
+                best_move: d4.into(),
+                ponder: None
             }
-            UciMessage::Go { .. } => {}
-            //UciMessage::Id { name, author } => todo!(),
-            //UciMessage::UciOk => todo!(),
-            //UciMessage::ReadyOk => todo!(),
-            //UciMessage::BestMove { best_move, ponder } => todo!(),
-            //UciMessage::CopyProtection(_) => todo!(),
-            //UciMessage::Registration(_) => todo!(),
-            //UciMessage::Option(_) => todo!(),
-            //UciMessage::Info(_) => todo!(),
-            //UciMessage::Unknown(_, _) => todo!(),
-            _ => {
-                eprintln!("unknown UCI message {}", uci.to_string());
-                return;
+        );
+        // Out of book the engine searches as usual.
+        t.script(&["position startpos moves d2d4", "go movetime 100"]);
+        t.advance(100);
+        let mv = t.expect_bestmove(WAIT).await;
+        assert_ne!(msg, mv);
+        std::fs::remove_file(path).unwrap();
+
+        t.script(&["setoption name BookFile value /no/such/book.bin"]);
+        t.drain();
+        assert_eq!(t.info_strings(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_syzygy_path() {
+        // Tables are only read when a position needs them, so an empty one still counts.
+        let dir = std::env::temp_dir().join(format!("syzygy-engine-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("KQvK.rtbw"), "").unwrap();
+
+        let mut t = TestEngine::new();
+        t.script(&[
+            "uci",
+            &format!("setoption name SyzygyPath value {}", dir.to_str().unwrap()),
+        ]);
+        let msg = t
+            .expect(WAIT, |msg| matches!(msg, UciMessage::Info(_)))
+            .await;
+        assert_eq!(
+            msg,
+            UciMessage::Info(vec![UciInfoAttribute::String(
+                "found 1 tablebases with up to 3 pieces".into()
+            )])
+        );
+        // A table that can't be read is no help, but doesn't stop the search either.
+        let board = Board::from_fen("8/8/4k3/8/3Q4/8/8/K7 w - - 0 1").unwrap();
+        t.script(&["position fen 8/8/4k3/8/3Q4/8/8/K7 w - - 0 1", "go depth 2"]);
+        let msg = t.expect_bestmove(WAIT).await;
+        assert_legal(&board, &msg);
+        std::fs::remove_dir_all(dir).unwrap();
+
+        t.script(&["setoption name SyzygyPath value <empty>", "go depth 1"]);
+        t.expect_bestmove(WAIT).await;
+        assert_eq!(t.info_strings(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_dtm_path() {
+        let dir = std::env::temp_dir().join(format!("dtm-engine-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let kqk = "KQK".parse().unwrap();
+        generate_table_files(&[kqk], dir.to_str().unwrap()).unwrap();
+        let tables = DtmTables::load(dir.to_str().unwrap()).unwrap();
+
+        let mut t = TestEngine::new();
+        t.script(&[
+            "uci",
+            &format!("setoption name DtmPath value {}", dir.to_str().unwrap()),
+        ]);
+        let msg = t
+            .expect(WAIT, |msg| matches!(msg, UciMessage::Info(_)))
+            .await;
+        assert_eq!(
+            msg,
+            UciMessage::Info(vec![UciInfoAttribute::String(
+                "loaded 1 generated tables".into()
+            )])
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // One ply is enough to head for the quickest mate.
+        let board = Board::from_fen("8/8/8/4k3/8/8/8/K6Q w - - 0 1").unwrap();
+        let dtm = |mv: &Move| tables.probe(&board.clone().apply_move(mv).unwrap());
+        let quickest = board
+            .legal_moves()
+            .filter_map(|mv| match dtm(&mv) {
+                Some(Dtm::Loss(plies)) => Some(plies),
+                _ => None,
+            })
+            .min()
+            .unwrap();
+        t.script(&["position fen 8/8/8/4k3/8/8/8/K6Q w - - 0 1", "go depth 1"]);
+        let UciMessage::BestMove { best_move, .. } = t.expect_bestmove(WAIT).await else {
+            unreachable!();
+        };
+        let mv = Move::try_from(&best_move).unwrap();
+        assert_eq!(dtm(&mv), Some(Dtm::Loss(quickest)));
+
+        t.script(&["setoption name DtmPath value /no/such/dir"]);
+        t.drain();
+        assert_eq!(t.info_strings(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_writer_sink() {
+        let (ours, theirs) = tokio::io::duplex(4096);
+        let engine = Arc::new(Engine::new(
+            Box::new(WriterSink::new(ours)),
+            Box::new(Quiet),
+        ));
+        engine.handle_uci_line("uci");
+        engine.handle_uci_line("isready");
+
+        let mut lines = BufReader::new(theirs).lines();
+        let mut got = vec![];
+        while let Some(line) = lines.next_line().await.unwrap() {
+            got.push(line);
+            if got.last().unwrap() == "readyok" {
+                break;
             }
         }
-        self.messages_send.send(uci).unwrap();
+        assert_eq!(got.first().unwrap(), "id name Rust Chess");
+        assert!(got.contains(&"uciok".to_owned()));
     }
 }
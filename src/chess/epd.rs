@@ -0,0 +1,312 @@
+use std::fmt::Display;
+
+use super::{board::Board, moves::Move, san::SanError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EpdError {
+    /// Fewer than the four fields of a position.
+    Fields,
+    Fen(String),
+    /// A string operand without its closing quote.
+    Unterminated,
+    /// A `bm`, `am` or `pv` move that isn't legal in the position.
+    Move(String, SanError),
+}
+
+impl Display for EpdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EpdError::Fields => write!(f, "missing position fields"),
+            EpdError::Fen(fen) => write!(f, "bad position {fen}"),
+            EpdError::Unterminated => write!(f, "unterminated string"),
+            EpdError::Move(mv, error) => write!(f, "{mv}: {error}"),
+        }
+    }
+}
+
+/// An opcode and its operands, like `bm Nf3 e4;` or `id "WAC.001";`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Operation {
+    pub opcode: String,
+    pub operands: Vec<String>,
+}
+
+/// A record of an EPD file: a position and what is known about it.
+#[derive(Clone)]
+pub struct Epd {
+    /// The move counters come from `hmvc` and `fmvn`, or from a full FEN as perft files have
+    /// them. Without either they are 0 and 1.
+    pub board: Board,
+    pub operations: Vec<Operation>,
+}
+
+/// Splits the operations after the position into operands, keeping quoted strings together.
+fn split_operations(text: &str) -> Result<Vec<Vec<String>>, EpdError> {
+    let mut operations = vec![];
+    let mut operands: Vec<String> = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => operations.push(std::mem::take(&mut operands)),
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => s.push(c),
+                        None => return Err(EpdError::Unterminated),
+                    }
+                }
+                operands.push(s);
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut s = c.to_string();
+                while let Some(c) = chars.next_if(|&c| !c.is_whitespace() && c != ';') {
+                    s.push(c);
+                }
+                operands.push(s);
+            }
+        }
+    }
+    operations.push(operands);
+    Ok(operations)
+}
+
+impl Epd {
+    pub fn parse(line: &str) -> Result<Self, EpdError> {
+        let mut rest = line.trim();
+        let mut fields = vec![];
+        for _ in 0..4 {
+            let (field, tail) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            if field.is_empty() {
+                return Err(EpdError::Fields);
+            }
+            fields.push(field);
+            rest = tail.trim_start();
+        }
+
+        let mut operations: Vec<Operation> = vec![];
+        let mut clocks = None;
+        for (i, mut operands) in split_operations(rest)?.into_iter().enumerate() {
+            if operands.is_empty() {
+                continue;
+            }
+            // Perft files have the counters of a full FEN in front of the first operation.
+            let numbers = operands
+                .iter()
+                .all(|o| o.chars().all(|c| c.is_ascii_digit()));
+            if i == 0 && operands.len() == 2 && numbers {
+                clocks = Some((operands[0].clone(), operands[1].clone()));
+                continue;
+            }
+            let opcode = operands.remove(0);
+            operations.push(Operation { opcode, operands });
+        }
+
+        let find = |opcode: &str| {
+            operations
+                .iter()
+                .find(|op| op.opcode == opcode)
+                .and_then(|op| op.operands.first().cloned())
+        };
+        let (halfmoves, fullmoves) = clocks.unwrap_or_else(|| {
+            (
+                find("hmvc").unwrap_or_else(|| "0".to_owned()),
+                find("fmvn").unwrap_or_else(|| "1".to_owned()),
+            )
+        });
+        let fen = format!("{} {halfmoves} {fullmoves}", fields.join(" "));
+        let board = Board::from_fen(&fen).map_err(|_| EpdError::Fen(fen.clone()))?;
+        Ok(Self { board, operations })
+    }
+
+    pub fn operands(&self, opcode: &str) -> Option<&[String]> {
+        self.operations
+            .iter()
+            .find(|op| op.opcode == opcode)
+            .map(|op| op.operands.as_slice())
+    }
+
+    /// The first operand, for opcodes like `id` and `c0` that take a string.
+    pub fn string(&self, opcode: &str) -> Option<&str> {
+        self.operands(opcode)?.first().map(String::as_str)
+    }
+
+    /// The first operand as a number, for opcodes like `ce`, `acd` and `D5`.
+    pub fn integer(&self, opcode: &str) -> Option<i64> {
+        self.string(opcode)?.parse().ok()
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.string("id")
+    }
+
+    /// Replaces the operands of `opcode`, or adds it at the end.
+    pub fn set(&mut self, opcode: &str, operands: &[&str]) {
+        let operands = operands.iter().map(|&o| o.to_owned()).collect();
+        match self.operations.iter_mut().find(|op| op.opcode == opcode) {
+            Some(op) => op.operands = operands,
+            None => self.operations.push(Operation {
+                opcode: opcode.to_owned(),
+                operands,
+            }),
+        }
+    }
+
+    fn moves(&self, opcode: &str) -> Result<Vec<Move>, EpdError> {
+        let operands = self.operands(opcode).unwrap_or_default();
+        operands
+            .iter()
+            .map(|san| {
+                self.board
+                    .parse_san(san)
+                    .map_err(|error| EpdError::Move(san.clone(), error))
+            })
+            .collect()
+    }
+
+    /// The moves of `bm`, empty if there is none.
+    pub fn best_moves(&self) -> Result<Vec<Move>, EpdError> {
+        self.moves("bm")
+    }
+
+    /// The moves of `am`, empty if there is none.
+    pub fn avoid_moves(&self) -> Result<Vec<Move>, EpdError> {
+        self.moves("am")
+    }
+
+    /// The principal variation of `pv`, each move played after the one before.
+    pub fn pv(&self) -> Result<Vec<Move>, EpdError> {
+        let mut board = self.board.clone();
+        let mut pv = vec![];
+        for san in self.operands("pv").unwrap_or_default() {
+            let mv = board
+                .parse_san(san)
+                .map_err(|error| EpdError::Move(san.clone(), error))?;
+            board = board.apply_move(&mv).unwrap();
+            pv.push(mv);
+        }
+        Ok(pv)
+    }
+}
+
+/// Operands are quoted when they have to be, and always for the string opcodes `id` and
+/// `c0` to `c9`.
+impl Display for Epd {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fen = self.board.to_fen();
+        let position: Vec<&str> = fen.split(' ').take(4).collect();
+        write!(f, "{}", position.join(" "))?;
+        for op in &self.operations {
+            let string = op.opcode == "id"
+                || (op.opcode.len() == 2
+                    && op.opcode.starts_with('c')
+                    && op.opcode.ends_with(|c: char| c.is_ascii_digit()));
+            write!(f, " {}", op.opcode)?;
+            for operand in &op.operands {
+                let quote = string
+                    || operand.is_empty()
+                    || operand.contains(|c: char| c.is_whitespace() || c == ';');
+                match quote {
+                    true => write!(f, " \"{operand}\"")?,
+                    false => write!(f, " {operand}")?,
+                }
+            }
+            write!(f, ";")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Epd, EpdError};
+    use crate::chess::san::SanError;
+
+    const WAC1: &str = "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - -";
+
+    #[test]
+    fn test_parse() {
+        let line = format!(
+            "{WAC1} bm Qg6; am Qxg7+ Rae1; id \"WAC.001\"; c0 \"mate; in 3\"; ce +32000; \
+             acd 12; pv Qg6 fxg6; hmvc 4; fmvn 30;"
+        );
+        let epd = Epd::parse(&line).unwrap();
+        assert_eq!(epd.id(), Some("WAC.001"));
+        assert_eq!(epd.string("c0"), Some("mate; in 3"));
+        assert_eq!(epd.integer("ce"), Some(32000));
+        assert_eq!(epd.integer("acd"), Some(12));
+        assert_eq!(epd.operands("am").unwrap(), ["Qxg7+", "Rae1"]);
+        assert_eq!(epd.operands("nothing"), None);
+        assert!(epd.board.to_fen().ends_with(" w - - 4 30"));
+
+        let board = &epd.board;
+        let bm = epd.best_moves().unwrap();
+        assert_eq!(bm.len(), 1);
+        assert_eq!(board.san(&bm[0]), "Qg6");
+        let am = epd.avoid_moves().unwrap();
+        assert_eq!(
+            am.iter().map(|mv| board.san(mv)).collect::<Vec<_>>(),
+            ["Qxg7+", "Rae1"]
+        );
+        let pv = epd.pv().unwrap();
+        assert_eq!(pv.len(), 2);
+        assert_eq!(
+            board.clone().apply_move(&pv[0]).unwrap().san(&pv[1]),
+            "fxg6"
+        );
+
+        let none = Epd::parse(WAC1).unwrap();
+        assert!(none.operations.is_empty());
+        assert_eq!(none.best_moves(), Ok(vec![]));
+        assert!(none.board.to_fen().ends_with(" 0 1"));
+    }
+
+    #[test]
+    fn test_perft_format() {
+        let line = "4k3/8/8/8/8/8/8/4K2R w K - 3 9 ;D1 15 ;D2 66";
+        let epd = Epd::parse(line).unwrap();
+        assert_eq!(epd.board.to_fen(), "4k3/8/8/8/8/8/8/4K2R w K - 3 9");
+        assert_eq!(epd.integer("D1"), Some(15));
+        assert_eq!(epd.integer("D2"), Some(66));
+        assert_eq!(epd.operations.len(), 2);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(Epd::parse("").err(), Some(EpdError::Fields));
+        assert_eq!(Epd::parse("8/8/8 w -").err(), Some(EpdError::Fields));
+        assert!(matches!(
+            Epd::parse("8/8/8 w - - bm e4;").err(),
+            Some(EpdError::Fen(_))
+        ));
+        let unterminated = format!("{WAC1} id \"WAC.001;");
+        assert_eq!(
+            Epd::parse(&unterminated).err(),
+            Some(EpdError::Unterminated)
+        );
+
+        let epd = Epd::parse(&format!("{WAC1} bm Qa3; am Nd7;")).unwrap();
+        assert_eq!(
+            epd.best_moves(),
+            Err(EpdError::Move("Qa3".to_owned(), SanError::NoSuchMove))
+        );
+        assert_eq!(
+            epd.avoid_moves(),
+            Err(EpdError::Move("Nd7".to_owned(), SanError::Ambiguous))
+        );
+    }
+
+    #[test]
+    fn test_write() {
+        let line = format!("{WAC1} bm Qg6; id \"WAC.001\"; c0 \"mate; in 3\"; ce 32000;");
+        let mut epd = Epd::parse(&line).unwrap();
+        assert_eq!(epd.to_string(), line);
+        epd.set("acd", &["12"]);
+        epd.set("id", &["WAC 1"]);
+        let written = epd.to_string();
+        assert!(written.ends_with(" id \"WAC 1\"; c0 \"mate; in 3\"; ce 32000; acd 12;"));
+        assert_eq!(Epd::parse(&written).unwrap().operations, epd.operations);
+    }
+}
@@ -0,0 +1,83 @@
+use std::fmt::Arguments;
+
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::mpsc::{unbounded_channel, UnboundedSender},
+};
+use vampirc_uci::UciMessage;
+
+/// Receives the UCI messages an engine emits.
+pub trait OutputSink: Send + Sync {
+    fn send(&self, msg: UciMessage);
+}
+
+/// Receives an engine's diagnostics, which are not part of the UCI conversation.
+pub trait Logger: Send + Sync {
+    fn log(&self, args: Arguments<'_>);
+}
+
+/// Prints messages to stdout, which is what a GUI talking to the process expects.
+pub struct Stdout;
+
+impl OutputSink for Stdout {
+    fn send(&self, msg: UciMessage) {
+        println!("{}", msg);
+    }
+}
+
+/// Hands messages to whoever holds the receiving end, e.g. a test or another task.
+impl OutputSink for UnboundedSender<UciMessage> {
+    fn send(&self, msg: UciMessage) {
+        // The receiver going away just means nobody is listening anymore.
+        let _ = UnboundedSender::send(self, msg);
+    }
+}
+
+/// Writes messages as lines to a socket, pipe or anything else that is `AsyncWrite`. Writing
+/// happens on a separate task so the engine never blocks on a slow reader.
+pub struct WriterSink {
+    lines: UnboundedSender<String>,
+}
+
+impl WriterSink {
+    /// Must be called from within a tokio runtime.
+    pub fn new<W: AsyncWrite + Unpin + Send + 'static>(mut writer: W) -> Self {
+        let (lines, mut recv) = unbounded_channel::<String>();
+        tokio::spawn(async move {
+            while let Some(line) = recv.recv().await {
+                if writer.write_all(line.as_bytes()).await.is_err() || writer.flush().await.is_err()
+                {
+                    break;
+                }
+            }
+        });
+        Self { lines }
+    }
+}
+
+impl OutputSink for WriterSink {
+    fn send(&self, msg: UciMessage) {
+        let _ = self.lines.send(format!("{}\n", msg));
+    }
+}
+
+pub struct Stderr;
+
+impl Logger for Stderr {
+    fn log(&self, args: Arguments<'_>) {
+        eprintln!("{}", args);
+    }
+}
+
+/// Throws diagnostics away.
+pub struct Quiet;
+
+impl Logger for Quiet {
+    fn log(&self, _args: Arguments<'_>) {}
+}
+
+impl Logger for UnboundedSender<String> {
+    fn log(&self, args: Arguments<'_>) {
+        let _ = self.send(args.to_string());
+    }
+}
@@ -1,3 +1,7 @@
+use std::fmt::Display;
+
+use vampirc_uci::UciMove;
+
 use super::{
     board::Board,
     direction::{Direction, ALL_DIRS},
@@ -7,12 +11,31 @@ use super::{
     square::{File, Rank, Square},
 };
 
+/// A move that can't be played in the position it was tried in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IllegalMove(pub Move);
+
+impl Display for IllegalMove {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "illegal move {}", UciMove::from(self.0))
+    }
+}
+
 impl Board {
     pub fn legal_moves(&self) -> impl Iterator<Item = Move> + '_ {
         self.moves(self.to_move())
             .filter(|m| self.move_legal(m, self.to_move()))
     }
 
+    /// Applies `mv` only if it is one of the legal moves in this position.
+    pub fn apply_legal_move(self, mv: &Move) -> Result<Self, IllegalMove> {
+        if self.legal_moves().any(|m| m == *mv) {
+            self.apply_move(mv).map_err(|_| IllegalMove(*mv))
+        } else {
+            Err(IllegalMove(*mv))
+        }
+    }
+
     pub fn is_pinned_by_us(&self, sq: Square, us: Side) -> bool {
         let their_king_sq = (self.pieces(Piece::King) & self.color_pieces(us.other()))
             .to_square()
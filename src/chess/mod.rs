@@ -1,11 +1,30 @@
+pub mod analysis;
+pub mod bench;
 pub mod bitboard;
 pub mod board;
+pub mod bookbuild;
+pub mod debug;
 pub mod direction;
+pub mod endgame;
 pub mod engine;
+pub mod epd;
 pub mod eval;
+pub mod io;
 pub mod legal;
 pub mod moves;
+pub mod perft;
+pub mod pgn;
 pub mod piece;
 pub mod piecemoves;
+pub mod play;
+pub mod polyglot;
+pub mod retro;
+pub mod san;
 pub mod side;
 pub mod square;
+pub mod suite;
+pub mod syzygy;
+pub mod timeman;
+pub mod unmove;
+pub mod worker;
+pub mod zobrist;
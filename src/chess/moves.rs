@@ -1,6 +1,6 @@
 use std::fmt::Display;
 
-use vampirc_uci::{UciMove, UciSquare};
+use vampirc_uci::{UciMove, UciPiece, UciSquare};
 
 use super::{
     bitboard::BitBoard,
@@ -182,7 +182,7 @@ impl Board {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Move {
     start: Square,
     dest: Square,
@@ -236,19 +236,30 @@ impl Display for Move {
     }
 }
 
-impl From<&UciMove> for Move {
-    fn from(value: &UciMove) -> Self {
-        Self {
-            start: Square::from_rank_and_file(
-                value.from.rank.try_into().unwrap(),
-                value.from.file.try_into().unwrap(),
-            ),
-            dest: Square::from_rank_and_file(
-                value.to.rank.try_into().unwrap(),
-                value.to.file.try_into().unwrap(),
-            ),
-            promo: value.promotion.map(|p| p.into()),
-        }
+impl TryFrom<&UciSquare> for Square {
+    type Error = ();
+
+    fn try_from(value: &UciSquare) -> Result<Self, Self::Error> {
+        Ok(Square::from_rank_and_file(
+            value.rank.try_into()?,
+            value.file.try_into()?,
+        ))
+    }
+}
+
+impl TryFrom<&UciMove> for Move {
+    type Error = ();
+
+    fn try_from(value: &UciMove) -> Result<Self, Self::Error> {
+        let promo = match value.promotion {
+            Some(UciPiece::Pawn) | Some(UciPiece::King) => return Err(()),
+            promo => promo.map(|p| p.into()),
+        };
+        Ok(Self {
+            start: (&value.from).try_into()?,
+            dest: (&value.to).try_into()?,
+            promo,
+        })
     }
 }
 
@@ -0,0 +1,554 @@
+use std::{
+    fmt::Display,
+    fs,
+    io::{BufRead, BufReader},
+    ops::AddAssign,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use fen::FenError;
+
+use super::{
+    bitboard::BitBoard,
+    board::Board,
+    epd::Epd,
+    moves::Move,
+    piece::Piece,
+    piecemoves::get_piece_moves,
+    side::Side,
+    square::{File, Square},
+};
+
+/// Counts the leaves of the legal move tree, the standard check of a move generator. The last
+/// ply is counted without playing its moves.
+pub fn perft(board: &Board, depth: u64) -> u64 {
+    match depth {
+        0 => 1,
+        1 => board.legal_moves().count() as u64,
+        _ => board
+            .legal_moves()
+            .map(|mv| perft(&board.clone().apply_move(&mv).unwrap(), depth - 1))
+            .sum(),
+    }
+}
+
+#[derive(Default)]
+struct Slot {
+    /// The key xor'ed with the count, so a slot torn by two threads reads as a miss.
+    key: AtomicU64,
+    count: AtomicU64,
+}
+
+/// Subtree counts by position and depth. Perft trees are full of transpositions, so deep runs
+/// get a lot faster with one. Threads can share a table.
+pub struct PerftTable {
+    slots: Box<[Slot]>,
+}
+
+impl PerftTable {
+    pub fn new(megabytes: usize) -> Self {
+        let len = (megabytes.max(1) << 20) / std::mem::size_of::<Slot>();
+        Self {
+            slots: (0..len).map(|_| Slot::default()).collect(),
+        }
+    }
+
+    fn key(hash: u64, depth: u64) -> u64 {
+        hash ^ (depth + 1).wrapping_mul(0x9e3779b97f4a7c15)
+    }
+
+    fn slot(&self, key: u64) -> &Slot {
+        &self.slots[(key % self.slots.len() as u64) as usize]
+    }
+
+    pub fn get(&self, hash: u64, depth: u64) -> Option<u64> {
+        let key = Self::key(hash, depth);
+        let slot = self.slot(key);
+        let count = slot.count.load(Ordering::Relaxed);
+        (count != 0 && slot.key.load(Ordering::Relaxed) ^ count == key).then_some(count)
+    }
+
+    pub fn insert(&self, hash: u64, depth: u64, count: u64) {
+        let key = Self::key(hash, depth);
+        let slot = self.slot(key);
+        slot.count.store(count, Ordering::Relaxed);
+        slot.key.store(key ^ count, Ordering::Relaxed);
+    }
+}
+
+/// Like [`perft`], but looks subtrees up in `table` before counting them.
+pub fn perft_hashed(board: &Board, depth: u64, table: &PerftTable) -> u64 {
+    if depth <= 1 {
+        return perft(board, depth);
+    }
+    let hash = board.hash();
+    if let Some(count) = table.get(hash, depth) {
+        return count;
+    }
+    let count = board
+        .legal_moves()
+        .map(|mv| perft_hashed(&board.clone().apply_move(&mv).unwrap(), depth - 1, table))
+        .sum();
+    table.insert(hash, depth, count);
+    count
+}
+
+fn count(board: &Board, depth: u64, table: Option<&PerftTable>) -> u64 {
+    match table {
+        Some(table) => perft_hashed(board, depth, table),
+        None => perft(board, depth),
+    }
+}
+
+/// Perft with the root moves shared out between up to `threads` threads.
+pub fn perft_parallel(
+    board: &Board,
+    depth: u64,
+    threads: usize,
+    table: Option<&PerftTable>,
+) -> u64 {
+    if depth <= 1 || threads <= 1 {
+        return count(board, depth, table);
+    }
+    let moves: Vec<Move> = board.legal_moves().collect();
+    let next = AtomicUsize::new(0);
+    let total = AtomicU64::new(0);
+    thread::scope(|scope| {
+        for _ in 0..threads.min(moves.len()) {
+            scope.spawn(|| {
+                while let Some(mv) = moves.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let child = board.clone().apply_move(mv).unwrap();
+                    total.fetch_add(count(&child, depth - 1, table), Ordering::Relaxed);
+                }
+            });
+        }
+    });
+    total.into_inner()
+}
+
+/// The perft count below every legal move.
+pub fn divide(board: &Board, depth: u64) -> Vec<(Move, u64)> {
+    board
+        .legal_moves()
+        .map(|mv| {
+            let child = board.clone().apply_move(&mv).unwrap();
+            (mv, perft(&child, depth.saturating_sub(1)))
+        })
+        .collect()
+}
+
+/// Perft broken down by what the moves of the last ply do, as in the reference tables on the
+/// chess programming wiki. When a count is off, the column that differs points at the rule the
+/// move generator gets wrong.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PerftStats {
+    pub nodes: u64,
+    pub captures: u64,
+    pub enpassant: u64,
+    pub castles: u64,
+    pub promotions: u64,
+    pub checks: u64,
+    /// Checks given only by pieces other than the one that moved. A double check by the moved
+    /// piece and the one it uncovered isn't counted here, same as in the reference tables.
+    pub discovered_checks: u64,
+    pub double_checks: u64,
+    pub checkmates: u64,
+}
+
+impl AddAssign for PerftStats {
+    fn add_assign(&mut self, other: Self) {
+        self.nodes += other.nodes;
+        self.captures += other.captures;
+        self.enpassant += other.enpassant;
+        self.castles += other.castles;
+        self.promotions += other.promotions;
+        self.checks += other.checks;
+        self.discovered_checks += other.discovered_checks;
+        self.double_checks += other.double_checks;
+        self.checkmates += other.checkmates;
+    }
+}
+
+impl PerftStats {
+    pub fn header() -> String {
+        format!(
+            "{:>5} {:>12} {:>10} {:>9} {:>9} {:>9} {:>10} {:>9} {:>8} {:>9}",
+            "depth",
+            "nodes",
+            "captures",
+            "e.p.",
+            "castles",
+            "promos",
+            "checks",
+            "disc chk",
+            "dbl chk",
+            "mates"
+        )
+    }
+
+    /// A row of the table under [`PerftStats::header`].
+    pub fn row(&self, depth: u64) -> String {
+        format!(
+            "{depth:>5} {:>12} {:>10} {:>9} {:>9} {:>9} {:>10} {:>9} {:>8} {:>9}",
+            self.nodes,
+            self.captures,
+            self.enpassant,
+            self.castles,
+            self.promotions,
+            self.checks,
+            self.discovered_checks,
+            self.double_checks,
+            self.checkmates
+        )
+    }
+}
+
+/// The squares of the pieces giving check to `side`.
+fn checkers(board: &Board, side: Side) -> Vec<Square> {
+    let them = side.other();
+    let Some(king) = (board.pieces(Piece::King) & board.color_pieces(side)).to_square() else {
+        return vec![];
+    };
+    board
+        .color_pieces(them)
+        .into_iter()
+        .filter(|&sq| {
+            let (piece, _) = board.piece(sq).unwrap();
+            get_piece_moves(
+                piece,
+                them,
+                sq,
+                BitBoard::default(),
+                board.color_pieces(side),
+                board.color_pieces(them),
+            )
+            .get(king)
+        })
+        .collect()
+}
+
+fn classify(board: &Board, mv: &Move) -> PerftStats {
+    let (piece, _) = board.piece(mv.start()).unwrap();
+    let enpassant = piece == Piece::Pawn && board.enpassant().get(mv.dest());
+    let castle = mv.is_castling(board);
+    let child = board.clone().apply_move(mv).unwrap();
+    let mut stats = PerftStats {
+        nodes: 1,
+        captures: (enpassant || board.piece(mv.dest()).is_some()) as u64,
+        enpassant: enpassant as u64,
+        castles: castle as u64,
+        promotions: mv.promo().is_some() as u64,
+        ..Default::default()
+    };
+    if child.is_in_check(child.to_move()) {
+        let checkers = checkers(&child, child.to_move());
+        // The rook is the piece that moved when castling gives check.
+        let rook = match mv.dest().file() {
+            File::G => File::F,
+            _ => File::D,
+        };
+        let moved = |sq: Square| {
+            sq == mv.dest() || (castle && sq == Square::from_rank_and_file(mv.dest().rank(), rook))
+        };
+        stats.checks = 1;
+        stats.discovered_checks = !checkers.iter().any(|&sq| moved(sq)) as u64;
+        stats.double_checks = (checkers.len() > 1) as u64;
+        stats.checkmates = child.legal_moves().next().is_none() as u64;
+    }
+    stats
+}
+
+/// Like [`perft`], but classifies every move of the last ply.
+pub fn perft_stats(board: &Board, depth: u64) -> PerftStats {
+    let mut stats = PerftStats::default();
+    match depth {
+        0 => stats.nodes = 1,
+        1 => {
+            for mv in board.legal_moves() {
+                stats += classify(board, &mv);
+            }
+        }
+        _ => {
+            for mv in board.legal_moves() {
+                stats += perft_stats(&board.clone().apply_move(&mv).unwrap(), depth - 1);
+            }
+        }
+    }
+    stats
+}
+
+/// A position with known perft counts, written as an EPD line with `;D<depth> <count>`
+/// operations, e.g. `<fen> ;D1 20 ;D2 400`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PerftCase {
+    pub fen: String,
+    /// Depth and expected count, in the order they were listed.
+    pub counts: Vec<(u64, u64)>,
+}
+
+impl PerftCase {
+    /// The `D1`, `D2`... counts of a record, `None` if it has none.
+    pub fn from_epd(epd: &Epd) -> Option<Self> {
+        let counts: Vec<_> = epd
+            .operations
+            .iter()
+            .filter_map(|op| {
+                let depth = op.opcode.strip_prefix('D')?.parse().ok()?;
+                let count = op.operands.first()?.parse().ok()?;
+                Some((depth, count))
+            })
+            .collect();
+        (!counts.is_empty()).then(|| Self {
+            fen: epd.board.to_fen(),
+            counts,
+        })
+    }
+
+    /// Returns `None` for blank lines, comments and lines without any counts.
+    pub fn parse(line: &str) -> Option<Self> {
+        Self::from_epd(&Epd::parse(line).ok()?)
+    }
+
+    /// Runs every count up to `max_depth`.
+    pub fn run(
+        &self,
+        max_depth: u64,
+        threads: usize,
+        table: Option<&PerftTable>,
+    ) -> Result<Vec<PerftOutcome>, FenError> {
+        let board = Board::from_fen(&self.fen)?;
+        Ok(self
+            .counts
+            .iter()
+            .filter(|(depth, _)| *depth <= max_depth)
+            .map(|&(depth, expected)| {
+                let start = Instant::now();
+                let nodes = perft_parallel(&board, depth, threads, table);
+                PerftOutcome {
+                    depth,
+                    expected,
+                    nodes,
+                    time: start.elapsed(),
+                }
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PerftOutcome {
+    pub depth: u64,
+    pub expected: u64,
+    pub nodes: u64,
+    pub time: Duration,
+}
+
+impl PerftOutcome {
+    pub fn passed(&self) -> bool {
+        self.nodes == self.expected
+    }
+
+    pub fn nps(&self) -> u64 {
+        (self.nodes as f64 / self.time.as_secs_f64().max(1e-6)) as u64
+    }
+}
+
+impl Display for PerftOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.passed() {
+            write!(f, "ok   D{} {}", self.depth, self.nodes)?;
+        } else {
+            write!(
+                f,
+                "FAIL D{} {} (expected {})",
+                self.depth, self.nodes, self.expected
+            )?;
+        }
+        write!(f, " in {:.3}s, {} nps", self.time.as_secs_f64(), self.nps())
+    }
+}
+
+/// Checks every position of an EPD file up to `max_depth`, printing a line per count. Returns
+/// whether they all passed.
+pub fn run_epd_file(path: &str, max_depth: u64, threads: usize) -> std::io::Result<bool> {
+    let table = PerftTable::new(64);
+    let (mut passed, mut failed) = (0, 0);
+    let start = Instant::now();
+    let mut nodes = 0;
+    for line in BufReader::new(fs::File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() || line.trim().starts_with('#') {
+            continue;
+        }
+        let case = match Epd::parse(&line) {
+            Ok(epd) => match PerftCase::from_epd(&epd) {
+                Some(case) => case,
+                None => continue,
+            },
+            Err(err) => {
+                println!("{line}\n  FAIL {err}");
+                failed += 1;
+                continue;
+            }
+        };
+        println!("{}", case.fen);
+        let outcomes = match case.run(max_depth, threads, Some(&table)) {
+            Ok(outcomes) => outcomes,
+            Err(err) => {
+                println!("  FAIL bad fen: {err:?}");
+                failed += 1;
+                continue;
+            }
+        };
+        for outcome in outcomes {
+            println!("  {outcome}");
+            nodes += outcome.nodes;
+            if outcome.passed() {
+                passed += 1;
+            } else {
+                failed += 1;
+            }
+        }
+    }
+    let time = start.elapsed();
+    println!(
+        "{passed} passed, {failed} failed, {nodes} nodes in {:.3}s ({} nps)",
+        time.as_secs_f64(),
+        (nodes as f64 / time.as_secs_f64().max(1e-6)) as u64
+    );
+    Ok(failed == 0)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chess::board::Board;
+
+    use super::{
+        perft, perft_hashed, perft_parallel, perft_stats, PerftCase, PerftStats, PerftTable,
+    };
+
+    const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+
+    #[test]
+    fn test_perft() {
+        let board = Board::from_fen("startpos").unwrap();
+        let counts: Vec<u64> = (0..=4).map(|d| perft(&board, d)).collect();
+        assert_eq!(counts, [1, 20, 400, 8902, 197281]);
+        assert_eq!(perft(&Board::from_fen(KIWIPETE).unwrap(), 3), 97862);
+    }
+
+    #[test]
+    fn test_hashed_and_parallel() {
+        let table = PerftTable::new(16);
+        let board = Board::from_fen(KIWIPETE).unwrap();
+        assert_eq!(perft_hashed(&board, 3, &table), 97862);
+        // Now mostly from the table.
+        assert_eq!(perft_hashed(&board, 3, &table), 97862);
+        assert_eq!(perft_parallel(&board, 3, 4, None), 97862);
+        assert_eq!(perft_parallel(&board, 3, 4, Some(&table)), 97862);
+
+        let board = Board::from_fen("startpos").unwrap();
+        assert_eq!(perft_parallel(&board, 4, 3, Some(&table)), 197281);
+    }
+
+    #[test]
+    fn test_epd_case() {
+        let case = PerftCase::parse("4k3/8/8/8/8/8/8/4K2R w K - ;D1 15 ;D2 66 ;D3 1197").unwrap();
+        assert_eq!(case.fen, "4k3/8/8/8/8/8/8/4K2R w K - 0 1");
+        assert_eq!(case.counts, [(1, 15), (2, 66), (3, 1197)]);
+        assert_eq!(PerftCase::parse(""), None);
+        assert_eq!(PerftCase::parse("# comment"), None);
+
+        let outcomes = case.run(2, 2, None).unwrap();
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes.iter().all(|o| o.passed()));
+        assert!(outcomes[1].to_string().starts_with("ok   D2 66 in "));
+
+        let wrong = PerftCase::parse("4k3/8/8/8/8/8/8/4K2R w K - 0 1 ;D2 67").unwrap();
+        let outcome = wrong.run(5, 1, None).unwrap()[0];
+        assert!(!outcome.passed());
+        assert!(outcome.to_string().starts_with("FAIL D2 66 (expected 67)"));
+    }
+
+    /// Nodes, captures, e.p., castles, promotions, checks, discovered checks, double checks and
+    /// checkmates by depth, from the chess programming wiki.
+    fn check_stats(fen: &str, table: &[[u64; 9]]) {
+        let board = Board::from_fen(fen).unwrap();
+        for (depth, row) in (1..).zip(table) {
+            let expected = PerftStats {
+                nodes: row[0],
+                captures: row[1],
+                enpassant: row[2],
+                castles: row[3],
+                promotions: row[4],
+                checks: row[5],
+                discovered_checks: row[6],
+                double_checks: row[7],
+                checkmates: row[8],
+            };
+            assert_eq!(perft_stats(&board, depth), expected, "{fen} depth {depth}");
+        }
+    }
+
+    #[test]
+    fn test_stats_startpos() {
+        check_stats(
+            "startpos",
+            &[
+                [20, 0, 0, 0, 0, 0, 0, 0, 0],
+                [400, 0, 0, 0, 0, 0, 0, 0, 0],
+                [8902, 34, 0, 0, 0, 12, 0, 0, 0],
+                [197281, 1576, 0, 0, 0, 469, 0, 0, 8],
+            ],
+        );
+    }
+
+    #[test]
+    fn test_stats_kiwipete() {
+        check_stats(
+            KIWIPETE,
+            &[
+                [48, 8, 0, 2, 0, 0, 0, 0, 0],
+                [2039, 351, 1, 91, 0, 3, 0, 0, 0],
+                [97862, 17102, 45, 3162, 0, 993, 0, 0, 1],
+            ],
+        );
+    }
+
+    #[test]
+    fn test_stats_endgame() {
+        check_stats(
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            &[
+                [14, 1, 0, 0, 0, 2, 0, 0, 0],
+                [191, 14, 0, 0, 0, 10, 0, 0, 0],
+                [2812, 209, 2, 0, 0, 267, 3, 0, 0],
+                [43238, 3348, 123, 0, 0, 1680, 106, 0, 17],
+                [674624, 52051, 1165, 0, 0, 52950, 1292, 3, 0],
+            ],
+        );
+    }
+
+    #[test]
+    fn test_stats_promotions() {
+        check_stats(
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            &[
+                [6, 0, 0, 0, 0, 0, 0, 0, 0],
+                [264, 87, 0, 6, 48, 10, 0, 0, 0],
+                [9467, 1021, 4, 0, 120, 38, 2, 0, 22],
+            ],
+        );
+    }
+
+    #[test]
+    fn test_stats_table() {
+        let stats = perft_stats(&Board::from_fen("startpos").unwrap(), 3);
+        let header = PerftStats::header();
+        let row = stats.row(3);
+        assert_eq!(header.len(), row.len());
+        assert!(row.starts_with("    3         8902         34"));
+    }
+}
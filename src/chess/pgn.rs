@@ -0,0 +1,892 @@
+use std::{
+    fmt::Display,
+    io::{self, BufRead},
+    time::Duration,
+};
+
+use super::{board::Board, moves::Move, san::SanError, side::Side};
+
+/// The tags every game has, in the order they are written.
+pub const SEVEN_TAG_ROSTER: [&str; 7] =
+    ["Event", "Site", "Date", "Round", "White", "Black", "Result"];
+
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+/// A move of a game together with everything that was written around it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgnMove {
+    pub mv: Move,
+    /// As written in the game, without annotations.
+    pub san: String,
+    /// Numeric annotation glyphs, `$1` or `!` for a good move and so on.
+    pub nags: Vec<u8>,
+    /// Comments after the move, with any `[%cmd value]` taken out into `commands`.
+    pub comments: Vec<String>,
+    pub commands: Vec<(String, String)>,
+    /// Lines that could have been played instead of this move.
+    pub variations: Vec<Line>,
+}
+
+impl PgnMove {
+    pub fn new(mv: Move, san: String) -> Self {
+        Self {
+            mv,
+            san,
+            nags: vec![],
+            comments: vec![],
+            commands: vec![],
+            variations: vec![],
+        }
+    }
+
+    pub fn command(&self, name: &str) -> Option<&str> {
+        self.commands
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The time left on the mover's clock, from a `[%clk 1:05:30]` command.
+    pub fn clock(&self) -> Option<Duration> {
+        parse_clock(self.command("clk")?)
+    }
+
+    /// Notes the engine's score, in pawns for White, and the depth it searched to, in the
+    /// usual `{+0.25/12}` form.
+    pub fn add_eval(&mut self, score: f32, depth: u64) {
+        self.comments.push(format!("{score:+.2}/{depth}"));
+    }
+}
+
+/// A sequence of moves, the mainline or a variation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Line {
+    /// Comments before the first move.
+    pub comments: Vec<String>,
+    pub moves: Vec<PgnMove>,
+}
+
+#[derive(Clone)]
+pub struct PgnGame {
+    /// The seven tag roster first, then the other tags in the order they were read.
+    pub tags: Vec<(String, String)>,
+    /// From the `FEN` tag, the standard position otherwise.
+    pub start: Board,
+    pub mainline: Line,
+    pub result: String,
+}
+
+impl PgnGame {
+    /// A game from `start` with no moves yet, `?` for every tag and an unknown result.
+    pub fn new(start: Board) -> Self {
+        Self {
+            tags: SEVEN_TAG_ROSTER
+                .iter()
+                .map(|&name| (name.to_owned(), "?".to_owned()))
+                .collect(),
+            start,
+            mainline: Line::default(),
+            result: "*".to_owned(),
+        }
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value.to_owned(),
+            None => self.tags.push((name.to_owned(), value.to_owned())),
+        }
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The position after the last move of the mainline.
+    pub fn end(&self) -> Board {
+        self.mainline
+            .moves
+            .iter()
+            .fold(self.start.clone(), |board, m| {
+                board.apply_move(&m.mv).unwrap()
+            })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PgnErrorKind {
+    Io(io::ErrorKind),
+    /// A tag pair that isn't `[Name "value"]`.
+    Tag,
+    Fen(String),
+    /// A string or a `{` comment that runs to the end of the game.
+    Unterminated,
+    /// A `(` without `)` or the other way round.
+    Unbalanced,
+    /// Something that belongs nowhere, like a NAG before the first move.
+    Unexpected(String),
+    /// A move that isn't legal in the position, e.g. `12... Nxe4`.
+    Move(String, SanError),
+}
+
+/// What went wrong with one game. The reader carries on with the next one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgnError {
+    /// Counting from 1, in the whole input.
+    pub game: usize,
+    pub line: usize,
+    pub kind: PgnErrorKind,
+}
+
+impl Display for PgnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "game {}, line {}: ", self.game, self.line)?;
+        match &self.kind {
+            PgnErrorKind::Io(kind) => write!(f, "read error: {kind}"),
+            PgnErrorKind::Tag => write!(f, "malformed tag pair"),
+            PgnErrorKind::Fen(fen) => write!(f, "bad FEN {fen}"),
+            PgnErrorKind::Unterminated => write!(f, "unterminated string or comment"),
+            PgnErrorKind::Unbalanced => write!(f, "unbalanced parentheses"),
+            PgnErrorKind::Unexpected(token) => write!(f, "unexpected {token}"),
+            PgnErrorKind::Move(mv, error) => write!(f, "{mv}: {error}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    TagOpen,
+    TagClose,
+    Str(String),
+    Open,
+    Close,
+    Comment(String),
+    Nag(u8),
+    /// Moves, move numbers and results.
+    Symbol(String),
+}
+
+/// Splits the text of a game into tokens, each with the line it starts on.
+fn lex(text: &str, mut line: usize) -> Result<Vec<(usize, Token)>, (usize, PgnErrorKind)> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let start = line;
+        let token = match c {
+            '\n' => {
+                line += 1;
+                continue;
+            }
+            c if c.is_whitespace() => continue,
+            '[' => Token::TagOpen,
+            ']' => Token::TagClose,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') if matches!(chars.peek(), Some('"' | '\\')) => {
+                            s.push(chars.next().unwrap())
+                        }
+                        Some('\n') | None => return Err((start, PgnErrorKind::Unterminated)),
+                        Some(c) => s.push(c),
+                    }
+                }
+                Token::Str(s)
+            }
+            '{' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            s.push(c);
+                        }
+                        None => return Err((start, PgnErrorKind::Unterminated)),
+                    }
+                }
+                Token::Comment(s)
+            }
+            ';' => {
+                let mut s = String::new();
+                while let Some(c) = chars.next_if(|&c| c != '\n') {
+                    s.push(c);
+                }
+                Token::Comment(s)
+            }
+            '$' => {
+                let mut digits = String::new();
+                while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                    digits.push(c);
+                }
+                match digits.parse() {
+                    Ok(nag) => Token::Nag(nag),
+                    Err(_) => return Err((start, PgnErrorKind::Unexpected(format!("${digits}")))),
+                }
+            }
+            c if is_symbol_char(c) => {
+                let mut s = c.to_string();
+                while let Some(c) = chars.next_if(|&c| is_symbol_char(c)) {
+                    s.push(c);
+                }
+                Token::Symbol(s)
+            }
+            c => return Err((start, PgnErrorKind::Unexpected(c.to_string()))),
+        };
+        tokens.push((start, token));
+    }
+    Ok(tokens)
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_+#=:-/.*!?".contains(c)
+}
+
+/// The NAG a move suffix like `!?` stands for.
+fn suffix_nag(suffix: &str) -> Option<u8> {
+    Some(match suffix {
+        "!" => 1,
+        "?" => 2,
+        "!!" => 3,
+        "??" => 4,
+        "!?" => 5,
+        "?!" => 6,
+        _ => return None,
+    })
+}
+
+/// Reads `h:mm:ss`, with optional fractions of a second.
+pub fn parse_clock(text: &str) -> Option<Duration> {
+    let mut secs = 0.0;
+    for part in text.trim().split(':') {
+        let part: f64 = part.parse().ok()?;
+        if part < 0.0 {
+            return None;
+        }
+        secs = secs * 60.0 + part;
+    }
+    Some(Duration::from_secs_f64(secs))
+}
+
+/// Takes the `[%cmd value]` commands out of a comment.
+fn split_commands(comment: &str) -> (String, Vec<(String, String)>) {
+    let mut text = String::new();
+    let mut commands = vec![];
+    let mut rest = comment;
+    while let Some(start) = rest.find("[%") {
+        let Some(len) = rest[start..].find(']') else {
+            break;
+        };
+        text.push_str(&rest[..start]);
+        let command = &rest[start + 2..start + len];
+        let (name, value) = command
+            .split_once(char::is_whitespace)
+            .unwrap_or((command, ""));
+        commands.push((name.to_owned(), value.trim().to_owned()));
+        rest = &rest[start + len + 1..];
+    }
+    text.push_str(rest);
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (text, commands)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    /// Where the game ends, for errors about missing tokens.
+    last_line: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn take(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).map(|(_, token)| token.clone());
+        self.next += 1;
+        token
+    }
+
+    fn current_line(&self) -> usize {
+        match self.tokens.get(self.next.saturating_sub(1)) {
+            Some(&(line, _)) => line,
+            None => self.last_line,
+        }
+    }
+
+    fn tags(&mut self) -> Result<Vec<(String, String)>, PgnErrorKind> {
+        let mut tags = vec![];
+        while self.peek() == Some(&Token::TagOpen) {
+            self.take();
+            match (self.take(), self.take(), self.take()) {
+                (Some(Token::Symbol(name)), Some(Token::Str(value)), Some(Token::TagClose)) => {
+                    tags.push((name, value))
+                }
+                _ => return Err(PgnErrorKind::Tag),
+            }
+        }
+        Ok(tags)
+    }
+
+    /// Reads moves played from `board` up to the end of the variation, or of the game for the
+    /// mainline, when it also returns the result.
+    fn line(
+        &mut self,
+        mut board: Board,
+        nested: bool,
+    ) -> Result<(Line, Option<String>), PgnErrorKind> {
+        let mut line = Line::default();
+        // The position before the last move, where its variations start.
+        let mut before = board.clone();
+        while let Some(token) = self.take() {
+            let last = line.moves.last_mut();
+            match (token, last) {
+                (Token::Comment(comment), None) => line.comments.push(comment.trim().to_owned()),
+                (Token::Comment(comment), Some(last)) => {
+                    let (text, commands) = split_commands(&comment);
+                    if !text.is_empty() {
+                        last.comments.push(text);
+                    }
+                    last.commands.extend(commands);
+                }
+                (Token::Nag(nag), Some(last)) => last.nags.push(nag),
+                (Token::Open, Some(last)) => {
+                    let (variation, _) = self.line(before.clone(), true)?;
+                    last.variations.push(variation);
+                }
+                (Token::Close, _) if nested => return Ok((line, None)),
+                (Token::Close, _) => return Err(PgnErrorKind::Unbalanced),
+                // The game ends inside a variation.
+                (Token::Symbol(symbol), _) if RESULTS.contains(&symbol.as_str()) && nested => {
+                    return Err(PgnErrorKind::Unbalanced)
+                }
+                (Token::Symbol(symbol), _) if RESULTS.contains(&symbol.as_str()) => {
+                    return match self.take() {
+                        None => Ok((line, Some(symbol))),
+                        Some(_) => Err(PgnErrorKind::Unexpected(format!("text after {symbol}"))),
+                    };
+                }
+                (Token::Symbol(symbol), _) => {
+                    // Move numbers, `12.` or `12...`, can be stuck to the move.
+                    let number = symbol.trim_start_matches(|c: char| c.is_ascii_digit());
+                    let symbol = match number.starts_with('.') {
+                        true => number.trim_start_matches('.'),
+                        false => &symbol,
+                    };
+                    if symbol.is_empty() {
+                        continue;
+                    }
+                    let san = symbol.trim_end_matches(['!', '?']);
+                    let nag = suffix_nag(&symbol[san.len()..]);
+                    let mv = board.parse_san(san).map_err(|error| {
+                        let number = board.fullmoves();
+                        let dots = match board.to_move() {
+                            Side::White => ".",
+                            Side::Black => "...",
+                        };
+                        PgnErrorKind::Move(format!("{number}{dots} {san}"), error)
+                    })?;
+                    let mut pgn_move = PgnMove::new(mv, san.to_owned());
+                    pgn_move.nags.extend(nag);
+                    line.moves.push(pgn_move);
+                    before = board.clone();
+                    board = board.apply_move(&mv).unwrap();
+                }
+                (token, _) => return Err(PgnErrorKind::Unexpected(format!("{token:?}"))),
+            }
+        }
+        match nested {
+            true => Err(PgnErrorKind::Unbalanced),
+            false => Ok((line, None)),
+        }
+    }
+}
+
+/// Parses the text of one game, which starts on line `first_line` of the input.
+fn parse_game(text: &str, first_line: usize) -> Result<PgnGame, (usize, PgnErrorKind)> {
+    let tokens = lex(text, first_line)?;
+    let mut parser = Parser {
+        tokens,
+        next: 0,
+        last_line: first_line + text.lines().count().saturating_sub(1),
+    };
+    let at = |parser: &Parser, kind| (parser.current_line(), kind);
+
+    let read = parser.tags().map_err(|kind| at(&parser, kind))?;
+    let tag = |name: &str| read.iter().find(|(n, _)| n == name).map(|(_, v)| v.clone());
+    let start = match tag("FEN") {
+        Some(fen) => {
+            Board::from_fen(&fen).map_err(|_| (first_line, PgnErrorKind::Fen(fen.clone())))?
+        }
+        None => Board::from_fen("startpos").unwrap(),
+    };
+    let (mainline, result) = parser
+        .line(start.clone(), false)
+        .map_err(|kind| at(&parser, kind))?;
+    let result = result
+        .or_else(|| tag("Result"))
+        .unwrap_or_else(|| "*".to_owned());
+
+    let mut tags: Vec<(String, String)> = SEVEN_TAG_ROSTER
+        .iter()
+        .map(|&name| match name {
+            "Result" => (name.to_owned(), tag(name).unwrap_or_else(|| result.clone())),
+            _ => (name.to_owned(), tag(name).unwrap_or_else(|| "?".to_owned())),
+        })
+        .collect();
+    tags.extend(
+        read.into_iter()
+            .filter(|(name, _)| !SEVEN_TAG_ROSTER.contains(&name.as_str())),
+    );
+    Ok(PgnGame {
+        tags,
+        start,
+        mainline,
+        result,
+    })
+}
+
+/// Reads the games of a PGN file one at a time. A game that can't be read comes out as an
+/// error and the reader goes on with the next one.
+pub struct PgnReader<R> {
+    input: R,
+    line: usize,
+    games: usize,
+    /// The tag line that began the next game, read while looking for the end of the last one.
+    pending: Option<String>,
+    failed: bool,
+}
+
+impl<R: BufRead> PgnReader<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            line: 0,
+            games: 0,
+            pending: None,
+            failed: false,
+        }
+    }
+
+    /// The text of the next game and the line it starts on. A game ends where a tag pair
+    /// follows its movetext.
+    fn next_text(&mut self) -> io::Result<(String, usize)> {
+        let mut text = String::new();
+        let mut first_line = self.line + 1;
+        let mut movetext = false;
+        let mut in_comment = false;
+        if let Some(pending) = self.pending.take() {
+            first_line = self.line;
+            text.push_str(&pending);
+        }
+        loop {
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Ok((text, first_line));
+            }
+            self.line += 1;
+            let trimmed = line.trim();
+            let is_tag = trimmed.starts_with('[') && !in_comment;
+            if is_tag && movetext {
+                self.pending = Some(line);
+                return Ok((text, first_line));
+            }
+            if text.trim().is_empty() {
+                text.clear();
+                first_line = self.line;
+            }
+            // Lines starting with `%` are escaped from PGN.
+            if line.starts_with('%') {
+                text.push('\n');
+                continue;
+            }
+            if is_tag {
+                // Tag values are quoted and may hold braces and semicolons.
+                text.push_str(&line);
+                continue;
+            }
+            if !in_comment && !trimmed.is_empty() {
+                movetext = true;
+            }
+            for c in line.chars() {
+                match c {
+                    '{' if !in_comment => in_comment = true,
+                    '}' if in_comment => in_comment = false,
+                    ';' if !in_comment => break,
+                    _ => {}
+                }
+            }
+            text.push_str(&line);
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for PgnReader<R> {
+    type Item = Result<PgnGame, PgnError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let (text, first_line) = match self.next_text() {
+            Ok((text, _)) if text.trim().is_empty() => return None,
+            Ok(text) => text,
+            Err(error) => {
+                self.failed = true;
+                return Some(Err(PgnError {
+                    game: self.games + 1,
+                    line: self.line,
+                    kind: PgnErrorKind::Io(error.kind()),
+                }));
+            }
+        };
+        self.games += 1;
+        Some(
+            parse_game(&text, first_line).map_err(|(line, kind)| PgnError {
+                game: self.games,
+                line,
+                kind,
+            }),
+        )
+    }
+}
+
+/// Movetext as words to be wrapped, with the brackets of comments and variations stuck to the
+/// words they enclose.
+#[derive(Default)]
+struct Movetext {
+    words: Vec<String>,
+    open: String,
+}
+
+impl Movetext {
+    fn push(&mut self, word: &str) {
+        let word = std::mem::take(&mut self.open) + word;
+        self.words.push(word);
+    }
+
+    fn close(&mut self, bracket: char) {
+        match self.words.last_mut() {
+            Some(last) if self.open.is_empty() => last.push(bracket),
+            _ => {
+                self.open.push(bracket);
+                self.push("");
+            }
+        }
+    }
+
+    fn comment(&mut self, text: &str) {
+        self.open.push('{');
+        for word in text.split_whitespace() {
+            self.push(word);
+        }
+        self.close('}');
+    }
+
+    /// Writes `line`, played from `board`. Black's moves get a number like `5...` where the
+    /// move before doesn't end right next to them.
+    fn line(&mut self, line: &Line, mut board: Board) {
+        for comment in &line.comments {
+            self.comment(comment);
+        }
+        let mut numbered = false;
+        for m in &line.moves {
+            match board.to_move() {
+                Side::White => self.push(&format!("{}.", board.fullmoves())),
+                Side::Black if !numbered => self.push(&format!("{}...", board.fullmoves())),
+                Side::Black => {}
+            }
+            self.push(&board.san(&m.mv));
+            for nag in &m.nags {
+                self.push(&format!("${nag}"));
+            }
+            // Commands go in front of the first comment.
+            let commands: Vec<String> = m
+                .commands
+                .iter()
+                .map(|(name, value)| match value.is_empty() {
+                    true => format!("[%{name}]"),
+                    false => format!("[%{name} {value}]"),
+                })
+                .collect();
+            let mut comments = m.comments.clone();
+            match comments.first_mut() {
+                Some(first) if !commands.is_empty() => {
+                    *first = format!("{} {first}", commands.join(" "))
+                }
+                None if !commands.is_empty() => comments.push(commands.join(" ")),
+                _ => {}
+            }
+            for comment in &comments {
+                self.comment(comment);
+            }
+            for variation in &m.variations {
+                self.open.push('(');
+                self.line(variation, board.clone());
+                self.close(')');
+            }
+            numbered = comments.is_empty() && m.variations.is_empty();
+            board = board.apply_move(&m.mv).unwrap();
+        }
+    }
+}
+
+/// Joins words into lines of less than 80 columns.
+fn wrap(words: &[String]) -> String {
+    let mut text = String::new();
+    let mut line = String::new();
+    for word in words {
+        if !line.is_empty() && line.len() + 1 + word.len() > 79 {
+            text.push_str(&line);
+            text.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    text.push_str(&line);
+    text.push('\n');
+    text
+}
+
+/// Writes the game in PGN export format: the seven tag roster, the other tags, `SetUp` and
+/// `FEN` if the game doesn't start from the standard position, and the movetext in SAN.
+impl Display for PgnGame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut tags: Vec<(&str, String)> = SEVEN_TAG_ROSTER
+            .iter()
+            .map(|&name| match name {
+                "Result" => (name, self.result.clone()),
+                _ => (name, self.tag(name).unwrap_or("?").to_owned()),
+            })
+            .collect();
+        for (name, value) in &self.tags {
+            if !SEVEN_TAG_ROSTER.contains(&name.as_str()) && name != "SetUp" && name != "FEN" {
+                tags.push((name, value.clone()));
+            }
+        }
+        let fen = self.start.to_fen();
+        if fen != Board::from_fen("startpos").unwrap().to_fen() {
+            tags.push(("SetUp", "1".to_owned()));
+            tags.push(("FEN", fen));
+        }
+        for (name, value) in tags {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            writeln!(f, "[{name} \"{value}\"]")?;
+        }
+        writeln!(f)?;
+
+        let mut movetext = Movetext::default();
+        movetext.line(&self.mainline, self.start.clone());
+        movetext.push(&self.result);
+        write!(f, "{}", wrap(&movetext.words))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::chess::{board::Board, san::SanError};
+
+    use super::{parse_clock, PgnErrorKind, PgnGame, PgnMove, PgnReader};
+
+    const GAMES: &str = r#"[Event "Casual game"]
+[Site "Berlin"]
+[Date "1852.??.??"]
+[Round "-"]
+[White "Adolf Anderssen"]
+[Black "Jean Dufresne"]
+[Result "1-0"]
+[ECO "C52"]
+
+{The Evergreen game.} 1.e4 e5 2.Nf3 Nc6 3.Bc4 Bc5 4.b4 Bxb4 5.c3 Ba5 6.d4 exd4
+7.O-O d3 8.Qb3 Qf6 9.e5 Qg6 10.Re1 Nge7 11.Ba3 b5 12.Qxb5 Rb8 13.Qa4 Bb6
+14.Nbd2 Bb7 15.Ne4 Qf5 16.Bxd3 Qh5 17.Nf6+ gxf6 18.exf6 Rg8 19.Rad1 $1 Qxf3
+20.Rxe7+ Nxe7 ( 20...Kd8 21.Rxd7+ Kc8 22.Rd8+ ( 22.Rxc7+ ) 22...Kxd8 23.Bf5+ )
+21.Qxd7+ Kxd7 22.Bf5+ Ke8 23.Bd7+ Kf8 24.Bxe7# 1-0
+
+[Event "Broken"]
+[Site "?"]
+
+1. e4 e5 2. Nf3 Nf6 3. Ke3 *
+
+[Event "Clocks"]
+[Result "*"]
+% an escaped line
+1. d4 { [%clk 0:05:00] } 1... d5 {[%clk 0:04:58.5] solid} 2. c4!? ; the gambit
+2... dxc4?! $146 *
+"#;
+
+    #[test]
+    fn test_read() {
+        let mut games = PgnReader::new(GAMES.as_bytes());
+
+        let game = games.next().unwrap().unwrap();
+        assert_eq!(game.tag("White"), Some("Adolf Anderssen"));
+        assert_eq!(game.tag("ECO"), Some("C52"));
+        assert_eq!(game.result, "1-0");
+        assert_eq!(game.mainline.comments, ["The Evergreen game."]);
+        assert_eq!(game.mainline.moves.len(), 47);
+        assert_eq!(game.mainline.moves[36].san, "Rad1");
+        assert_eq!(game.mainline.moves[36].nags, [1]);
+        let nxe7 = &game.mainline.moves[39];
+        assert_eq!(nxe7.san, "Nxe7");
+        assert_eq!(nxe7.variations.len(), 1);
+        let kd8 = &nxe7.variations[0].moves;
+        assert_eq!(kd8.len(), 6);
+        assert_eq!(kd8[3].variations[0].moves[0].san, "Rxc7+");
+        let end = game.end();
+        assert!(end.legal_moves().next().is_none());
+
+        let error = games.next().unwrap().err().unwrap();
+        assert_eq!(error.game, 2);
+        assert_eq!(error.line, 19);
+        assert_eq!(
+            error.kind,
+            PgnErrorKind::Move("3. Ke3".to_owned(), SanError::NoSuchMove)
+        );
+        assert_eq!(
+            error.to_string(),
+            "game 2, line 19: 3. Ke3: no such legal move"
+        );
+
+        let game = games.next().unwrap().unwrap();
+        // Missing roster tags are filled in, in order.
+        let names: Vec<&str> = game.tags.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, super::SEVEN_TAG_ROSTER);
+        assert_eq!(game.tag("Event"), Some("Clocks"));
+        assert_eq!(game.tag("Site"), Some("?"));
+        let moves = &game.mainline.moves;
+        assert_eq!(moves.len(), 4);
+        assert_eq!(moves[0].clock(), Some(Duration::from_secs(300)));
+        assert!(moves[0].comments.is_empty());
+        assert_eq!(moves[1].clock(), Some(Duration::from_millis(298_500)));
+        assert_eq!(moves[1].comments, ["solid"]);
+        assert_eq!(moves[2].nags, [5]);
+        assert_eq!(moves[2].comments, ["the gambit"]);
+        assert_eq!(moves[3].nags, [6, 146]);
+        assert_eq!(game.result, "*");
+
+        assert!(games.next().is_none());
+    }
+
+    #[test]
+    fn test_errors() {
+        let kind = |pgn: &str| {
+            let mut games = PgnReader::new(pgn.as_bytes());
+            games.next().unwrap().err().unwrap().kind
+        };
+        assert_eq!(kind("[Event Casual]\n1. e4 *"), PgnErrorKind::Tag);
+        assert_eq!(
+            kind("[Event \"Casual]\n1. e4 *"),
+            PgnErrorKind::Unterminated
+        );
+        assert_eq!(kind("1. e4 {no end *"), PgnErrorKind::Unterminated);
+        assert_eq!(kind("1. e4 (1. d4 *"), PgnErrorKind::Unbalanced);
+        assert_eq!(kind("1. e4 ) *"), PgnErrorKind::Unbalanced);
+        assert!(matches!(kind("$1 1. e4 *"), PgnErrorKind::Unexpected(_)));
+        assert!(matches!(kind("1. e4 1-0 e5"), PgnErrorKind::Unexpected(_)));
+        assert!(matches!(kind("[FEN \"8/8 w\"]\n*"), PgnErrorKind::Fen(_)));
+
+        // The game after a broken one is still read.
+        let mut games = PgnReader::new("1. e5 *\n\n[Event \"?\"]\n1. e4 *\n".as_bytes());
+        assert!(games.next().unwrap().is_err());
+        assert_eq!(games.next().unwrap().unwrap().mainline.moves.len(), 1);
+        assert!(games.next().is_none());
+    }
+
+    #[test]
+    fn test_brackets_in_tags() {
+        let pgn = concat!(
+            "[Event \"Open {2024\"]\n[Site \"a;b\"]\n\n1. e4 *\n\n",
+            "[Event \"}\"]\n\n1. d4 *\n"
+        );
+        let games: Vec<_> = PgnReader::new(pgn.as_bytes())
+            .map(|game| game.unwrap())
+            .collect();
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].tag("Event"), Some("Open {2024"));
+        assert_eq!(games[0].tag("Site"), Some("a;b"));
+        assert_eq!(games[0].mainline.moves[0].san, "e4");
+        assert_eq!(games[1].tag("Event"), Some("}"));
+        assert_eq!(games[1].mainline.moves[0].san, "d4");
+    }
+
+    #[test]
+    fn test_setup() {
+        let pgn = concat!(
+            "[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/8/R3K3 b - - 0 40\"]\n\n",
+            "40... Kd7 41. Ra7+ *"
+        );
+        let game = PgnReader::new(pgn.as_bytes()).next().unwrap().unwrap();
+        assert_eq!(game.mainline.moves.len(), 2);
+        assert_eq!(game.end().to_fen(), "8/R2k4/8/8/8/8/8/4K3 b - - 2 41");
+    }
+
+    #[test]
+    fn test_clock() {
+        assert_eq!(parse_clock("1:00:01"), Some(Duration::from_secs(3601)));
+        assert_eq!(parse_clock("0:00:00.25"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_clock("1:x"), None);
+    }
+
+    fn read(pgn: &str) -> PgnGame {
+        PgnReader::new(pgn.as_bytes()).next().unwrap().ok().unwrap()
+    }
+
+    #[test]
+    fn test_write() {
+        let game = read("1. e4 e5 {a comment} 2. Nf3 (2. f4 exf4) Nc6 $1 *");
+        let pgn = game.to_string();
+        assert!(pgn.starts_with("[Event \"?\"]\n[Site \"?\"]\n"));
+        assert!(pgn.contains("[Result \"*\"]\n\n"));
+        assert!(pgn.ends_with("\n1. e4 e5 {a comment} 2. Nf3 (2. f4 exf4) 2... Nc6 $1 *\n"));
+        assert!(!pgn.contains("FEN"));
+
+        // Moves are written in standard notation whatever they were read as.
+        let fen = "4k3/8/8/8/8/8/8/R3K3 b - - 0 40";
+        let game = read(&format!("[FEN \"{fen}\"]\n40... Kd7 Ra1-a7 *"));
+        let pgn = game.to_string();
+        assert!(pgn.contains(&format!("[SetUp \"1\"]\n[FEN \"{fen}\"]\n\n")));
+        assert!(pgn.ends_with("\n40... Kd7 41. Ra7+ *\n"));
+
+        let mut game = PgnGame::new(Board::from_fen("startpos").unwrap());
+        game.set_tag("White", "Say \"Cheese\"");
+        let mv = game.start.parse_san("d4").unwrap();
+        let mut d4 = PgnMove::new(mv, "d4".to_owned());
+        d4.add_eval(0.3, 12);
+        d4.commands.push(("clk".to_owned(), "0:01:00".to_owned()));
+        game.mainline.moves.push(d4);
+        game.result = "1-0".to_owned();
+        let pgn = game.to_string();
+        assert!(pgn.contains("[White \"Say \\\"Cheese\\\"\"]\n"));
+        assert!(pgn.contains("[Result \"1-0\"]\n"));
+        assert!(pgn.ends_with("\n1. d4 {[%clk 0:01:00] +0.30/12} 1-0\n"));
+        assert_eq!(read(&pgn).tag("White"), Some("Say \"Cheese\""));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for game in PgnReader::new(GAMES.as_bytes()).filter_map(Result::ok) {
+            let pgn = game.to_string();
+            assert!(pgn.lines().all(|line| line.len() < 80), "{pgn}");
+            let again = read(&pgn);
+            assert_eq!(again.tags, game.tags);
+            assert_eq!(again.start.to_fen(), game.start.to_fen());
+            assert_eq!(again.mainline, game.mainline);
+            assert_eq!(again.result, game.result);
+            assert_eq!(again.to_string(), pgn);
+        }
+    }
+}
@@ -4,7 +4,7 @@ use vampirc_uci::UciPiece;
 
 use super::side::Side;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(u8)]
 pub enum Piece {
     Pawn = 0,
@@ -0,0 +1,355 @@
+use std::{
+    io::{self, BufRead, Write},
+    time::{Duration, Instant},
+};
+
+use vampirc_uci::UciMove;
+
+use super::{
+    analysis::Analysis,
+    board::Board,
+    moves::Move,
+    pgn::{PgnGame, PgnMove},
+    piece::Piece,
+    side::Side,
+};
+
+/// How long the engine may think about its moves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeControl {
+    Depth(u64),
+    MoveTime(Duration),
+    /// Both players start with `time` and get `increment` back after every move. Whoever
+    /// runs out first loses.
+    Clock {
+        time: Duration,
+        increment: Duration,
+    },
+}
+
+pub struct PlayOptions {
+    /// The side the human plays. The board is drawn from their side.
+    pub human: Side,
+    pub time_control: TimeControl,
+    pub threads: usize,
+}
+
+/// A game that was played out, or abandoned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Game {
+    pub human: Side,
+    /// In SAN.
+    pub moves: Vec<String>,
+    /// `1-0`, `0-1`, `1/2-1/2` or `*` if the game was left unfinished.
+    pub result: &'static str,
+    pub reason: String,
+}
+
+impl Game {
+    pub fn pgn(&self) -> String {
+        let (white, black) = match self.human {
+            Side::White => ("Human", "Rust Chess"),
+            Side::Black => ("Rust Chess", "Human"),
+        };
+        let mut board = Board::from_fen("startpos").unwrap();
+        let mut game = PgnGame::new(board.clone());
+        for (tag, value) in [
+            ("Event", "Casual game"),
+            ("Date", "????.??.??"),
+            ("Round", "-"),
+            ("White", white),
+            ("Black", black),
+        ] {
+            game.set_tag(tag, value);
+        }
+        game.result = self.result.to_owned();
+        for san in &self.moves {
+            let mv = board.parse_san(san).unwrap();
+            game.mainline.moves.push(PgnMove::new(mv, san.clone()));
+            board = board.apply_move(&mv).unwrap();
+        }
+        match game.mainline.moves.last_mut() {
+            Some(last) => last.comments.push(self.reason.clone()),
+            None => game.mainline.comments.push(self.reason.clone()),
+        }
+        game.to_string()
+    }
+}
+
+fn winner(side: Side) -> &'static str {
+    match side {
+        Side::White => "1-0",
+        Side::Black => "0-1",
+    }
+}
+
+/// Neither side can mate with just a king and at most one minor piece between them.
+fn insufficient_material(board: &Board) -> bool {
+    let count = |piece| board.pieces(piece).into_iter().count();
+    count(Piece::Pawn) + count(Piece::Rook) + count(Piece::Queen) == 0
+        && count(Piece::Bishop) + count(Piece::Knight) <= 1
+}
+
+/// The result and why, if the game is over. `history` holds the hashes of every position so
+/// far, the current one included.
+fn outcome(board: &Board, history: &[u64]) -> Option<(&'static str, String)> {
+    let side = board.to_move();
+    if board.legal_moves().next().is_none() {
+        return Some(match board.is_in_check(side) {
+            true => (winner(side.other()), format!("{side:?} is mated")),
+            false => ("1/2-1/2", "stalemate".to_owned()),
+        });
+    }
+    let hash = board.hash();
+    let reason = if board.is_fifty_move_draw() {
+        "fifty move rule"
+    } else if history.iter().filter(|&&h| h == hash).count() >= 3 {
+        "threefold repetition"
+    } else if insufficient_material(board) {
+        "insufficient material"
+    } else {
+        return None;
+    };
+    Some(("1/2-1/2", reason.to_owned()))
+}
+
+/// Reads a move in SAN, like `Nf3`, or in coordinates, like `g1f3`.
+fn parse_move(board: &Board, input: &str) -> Option<Move> {
+    board
+        .legal_moves()
+        .find(|&mv| UciMove::from(mv).to_string() == input)
+        .or_else(|| board.parse_san(input).ok())
+}
+
+fn format_clock(time: Duration) -> String {
+    let secs = time.as_secs();
+    format!(
+        "{}:{:02}.{}",
+        secs / 60,
+        secs % 60,
+        time.subsec_millis() / 100
+    )
+}
+
+/// Plays a game against the engine, reading the human's moves from `input`. Besides moves,
+/// the human can type `resign` or `quit`.
+pub fn play(
+    mut input: impl BufRead,
+    output: &mut impl Write,
+    options: &PlayOptions,
+) -> io::Result<Game> {
+    let start = "startpos";
+    let analysis = Analysis::new(false, options.threads);
+    let mut board = Board::from_fen(start).unwrap();
+    let mut moves: Vec<Move> = vec![];
+    let mut game = Game {
+        human: options.human,
+        moves: vec![],
+        result: "*",
+        reason: "game abandoned".to_owned(),
+    };
+    let mut history = vec![board.hash()];
+    let mut clocks = match options.time_control {
+        TimeControl::Clock { time, .. } => Some([time; 2]),
+        _ => None,
+    };
+
+    loop {
+        write!(output, "\n{}", board.view_from(options.human))?;
+        if let Some(clocks) = clocks {
+            writeln!(
+                output,
+                "White {}  Black {}",
+                format_clock(clocks[Side::White]),
+                format_clock(clocks[Side::Black])
+            )?;
+        }
+        if let Some((result, reason)) = outcome(&board, &history) {
+            game.result = result;
+            game.reason = reason;
+            break;
+        }
+
+        let side = board.to_move();
+        let started = Instant::now();
+        let mv = if side == options.human {
+            let mv = loop {
+                write!(output, "your move: ")?;
+                output.flush()?;
+                let mut line = String::new();
+                if input.read_line(&mut line)? == 0 {
+                    return Ok(game);
+                }
+                match line.trim() {
+                    "" => continue,
+                    "quit" => return Ok(game),
+                    "resign" => {
+                        game.result = winner(side.other());
+                        game.reason = format!("{side:?} resigns");
+                        break None;
+                    }
+                    text => match parse_move(&board, text) {
+                        Some(mv) => break Some(mv),
+                        None => {
+                            let mut legal: Vec<String> =
+                                board.legal_moves().map(|mv| board.san(&mv)).collect();
+                            legal.sort();
+                            writeln!(
+                                output,
+                                "illegal move {text}; legal moves: {}",
+                                legal.join(" ")
+                            )?;
+                        }
+                    },
+                }
+            };
+            let Some(mv) = mv else {
+                break;
+            };
+            mv
+        } else {
+            let go = match (options.time_control, clocks) {
+                (TimeControl::Depth(depth), _) => format!("go depth {depth}"),
+                (TimeControl::MoveTime(time), _) => format!("go movetime {}", time.as_millis()),
+                (TimeControl::Clock { increment, .. }, Some([white, black])) => format!(
+                    "go wtime {} btime {} winc {} binc {}",
+                    white.as_millis(),
+                    black.as_millis(),
+                    increment.as_millis(),
+                    increment.as_millis()
+                ),
+                (TimeControl::Clock { .. }, None) => unreachable!(),
+            };
+            let reply = analysis.search(start, &moves, &go).unwrap();
+            let mv = board
+                .legal_moves()
+                .find(|&mv| UciMove::from(mv) == reply)
+                .unwrap();
+            writeln!(output, "engine plays {}", board.san(&mv))?;
+            mv
+        };
+
+        if let (Some(clocks), TimeControl::Clock { increment, .. }) =
+            (clocks.as_mut(), options.time_control)
+        {
+            let clock = &mut clocks[side];
+            match clock.checked_sub(started.elapsed()) {
+                Some(left) => *clock = left + increment,
+                None => {
+                    game.result = winner(side.other());
+                    game.reason = format!("{side:?} lost on time");
+                    break;
+                }
+            }
+        }
+        game.moves.push(board.san(&mv));
+        board = board.apply_move(&mv).unwrap();
+        moves.push(mv);
+        history.push(board.hash());
+    }
+    writeln!(output, "{} {{{}}}", game.result, game.reason)?;
+    Ok(game)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chess::{board::Board, side::Side};
+
+    use super::{outcome, play, Game, PlayOptions, TimeControl};
+
+    fn options(human: Side) -> PlayOptions {
+        PlayOptions {
+            human,
+            time_control: TimeControl::Depth(1),
+            threads: 1,
+        }
+    }
+
+    #[test]
+    fn test_play() {
+        let input = "e4\ne4\nNf3\nresign\n".as_bytes();
+        let mut output = vec![];
+        let game = play(input, &mut output, &options(Side::White)).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.matches("engine plays ").count(), 2);
+        // The second e4 is not legal anymore; Nf3 entered at the same prompt is.
+        assert!(output.contains("illegal move e4; legal moves: "));
+        assert_eq!(game.moves.len(), 4);
+        assert_eq!(game.moves[0], "e4");
+        assert_eq!(game.moves[2], "Nf3");
+        assert_eq!(game.result, "0-1");
+        assert!(output.ends_with("0-1 {White resigns}\n"));
+    }
+
+    #[test]
+    fn test_play_black() {
+        let input = "e7e5\nquit\n".as_bytes();
+        let mut output = vec![];
+        let game = play(input, &mut output, &options(Side::Black)).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        // The engine opens, and the board is drawn with the first rank at the top.
+        assert!(output.find("engine plays ").unwrap() < output.find("your move").unwrap());
+        assert!(output.find("\n1 ").unwrap() < output.find("\n8 ").unwrap());
+        assert_eq!(game.moves.len(), 3);
+        assert_eq!(game.moves[1], "e5");
+        assert_eq!(game.result, "*");
+    }
+
+    #[test]
+    fn test_outcome() {
+        let over = |fen: &str| {
+            let board = Board::from_fen(fen).unwrap();
+            outcome(&board, &[board.hash()])
+        };
+        assert_eq!(over("startpos"), None);
+        assert_eq!(
+            over("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1"),
+            Some(("1-0", "Black is mated".to_owned()))
+        );
+        assert_eq!(
+            over("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap().1,
+            "stalemate"
+        );
+        assert_eq!(
+            over("4k3/8/8/8/8/8/8/4KB2 w - - 0 1").unwrap().1,
+            "insufficient material"
+        );
+        assert_eq!(over("4k3/8/8/8/8/8/8/3RK3 w - - 0 1"), None);
+        assert_eq!(
+            over("4k3/8/8/8/8/8/8/3RK3 w - - 100 80").unwrap().1,
+            "fifty move rule"
+        );
+
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/3RK3 w - - 0 1").unwrap();
+        let hash = board.hash();
+        assert_eq!(outcome(&board, &[hash, 1, hash]), None);
+        assert_eq!(
+            outcome(&board, &[hash, 1, hash, 2, hash]).unwrap().1,
+            "threefold repetition"
+        );
+    }
+
+    #[test]
+    fn test_pgn() {
+        let game = Game {
+            human: Side::Black,
+            moves: ["f3", "e5", "g4", "Qh4#"].map(String::from).to_vec(),
+            result: "0-1",
+            reason: "White is mated".to_owned(),
+        };
+        let pgn = game.pgn();
+        assert!(pgn.starts_with("[Event \"Casual game\"]\n"));
+        assert!(pgn.contains("[White \"Rust Chess\"]\n[Black \"Human\"]\n[Result \"0-1\"]\n\n"));
+        assert!(pgn.ends_with("\n1. f3 e5 2. g4 Qh4# {White is mated} 0-1\n"));
+
+        let long = Game {
+            moves: ["Nf3", "Nf6", "Ng1", "Ng8"]
+                .repeat(15)
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            ..game
+        };
+        assert!(long.pgn().lines().all(|line| line.len() < 80));
+    }
+}
@@ -0,0 +1,795 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs,
+    io::{self, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use super::{board::Board, piece::Piece, side::Side, square::Square};
+
+/// The most pieces, kings included, a table is generated for. Each piece more makes the table
+/// 64 times bigger.
+pub const MAX_PIECES: usize = 4;
+
+/// The score of a won position, in pawns, less the plies to mate from the root. It stays above
+/// [`super::syzygy::TB_WIN`], as these wins come with the way to mate.
+pub const DTM_WIN: f32 = 1000.0;
+
+const MAGIC: &[u8; 4] = b"DTM1";
+const EXTENSION: &str = "dtm";
+
+/// What the tables store for a position: 0 for a draw, and otherwise one more than the
+/// distance to mate in plies, which is odd when the side to move mates and even when it gets
+/// mated.
+const DRAW: u8 = 0;
+/// Stored for indices that don't stand for a position: pieces on top of each other, the side
+/// not to move in check, or a position that is stored under a symmetric one.
+const INVALID: u8 = u8::MAX;
+
+/// The order of the pieces in signatures and indices.
+const ORDER: [Piece; 5] = [
+    Piece::Queen,
+    Piece::Rook,
+    Piece::Bishop,
+    Piece::Knight,
+    Piece::Pawn,
+];
+
+/// The squares of the a1-d1-d4 triangle. Without pawns, every position is stored with the
+/// white king there.
+const TRIANGLE: [u8; 10] = [0, 1, 2, 3, 9, 10, 11, 18, 19, 27];
+
+/// The outcome of a position with best play, not counting the fifty-move rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dtm {
+    /// The side to move mates in this many plies.
+    Win(u32),
+    /// The side to move is mated in this many plies, 0 if it already is.
+    Loss(u32),
+    Draw,
+}
+
+impl Dtm {
+    fn from_value(value: u8) -> Option<Self> {
+        match value {
+            DRAW => Some(Dtm::Draw),
+            INVALID => None,
+            value => {
+                let plies = value as u32 - 1;
+                Some(match plies % 2 {
+                    1 => Dtm::Win(plies),
+                    _ => Dtm::Loss(plies),
+                })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignatureError {
+    Malformed(String),
+    TooManyPieces(String),
+    /// En passant would need positions of its own.
+    PawnsOnBothSides(String),
+}
+
+impl Display for SignatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignatureError::Malformed(s) => write!(f, "'{s}' is not like KQK or KBNK"),
+            SignatureError::TooManyPieces(s) => {
+                write!(f, "{s} has more than {MAX_PIECES} pieces")
+            }
+            SignatureError::PawnsOnBothSides(s) => write!(f, "{s} has pawns on both sides"),
+        }
+    }
+}
+
+/// The material of a table, written like `KBNK`: the white king and pieces, then the black.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Signature {
+    /// The pieces other than the kings, white's then black's, in the order of [`ORDER`].
+    pieces: [Vec<Piece>; 2],
+}
+
+impl FromStr for Signature {
+    type Err = SignatureError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || SignatureError::Malformed(s.to_owned());
+        let rest = s.strip_prefix('K').ok_or_else(malformed)?;
+        let (white, black) = rest.split_once('K').ok_or_else(malformed)?;
+        let mut pieces = [vec![], vec![]];
+        for (side, letters) in [white, black].into_iter().enumerate() {
+            for c in letters.chars() {
+                let piece = match c {
+                    'Q' => Piece::Queen,
+                    'R' => Piece::Rook,
+                    'B' => Piece::Bishop,
+                    'N' => Piece::Knight,
+                    'P' => Piece::Pawn,
+                    _ => return Err(malformed()),
+                };
+                pieces[side].push(piece);
+            }
+        }
+        let signature = Self::new(pieces);
+        if signature.count() > MAX_PIECES {
+            return Err(SignatureError::TooManyPieces(signature.to_string()));
+        }
+        if signature
+            .pieces
+            .iter()
+            .all(|pieces| pieces.contains(&Piece::Pawn))
+        {
+            return Err(SignatureError::PawnsOnBothSides(signature.to_string()));
+        }
+        Ok(signature)
+    }
+}
+
+impl Display for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for pieces in &self.pieces {
+            write!(f, "K")?;
+            for piece in pieces {
+                write!(f, "{}", piece.to_char(Side::White))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Signature {
+    fn new(mut pieces: [Vec<Piece>; 2]) -> Self {
+        for pieces in pieces.iter_mut() {
+            pieces.sort_by_key(|&piece| ORDER.iter().position(|&p| p == piece));
+        }
+        Self { pieces }
+    }
+
+    /// The material on `board`.
+    pub fn of(board: &Board) -> Self {
+        let mut pieces = [vec![], vec![]];
+        for (side, pieces) in [Side::White, Side::Black]
+            .into_iter()
+            .zip(pieces.iter_mut())
+        {
+            for piece in ORDER {
+                for _ in board.pieces(piece) & board.color_pieces(side) {
+                    pieces.push(piece);
+                }
+            }
+        }
+        Self { pieces }
+    }
+
+    /// The number of pieces, kings included.
+    pub fn count(&self) -> usize {
+        2 + self.pieces[0].len() + self.pieces[1].len()
+    }
+
+    fn has_pawns(&self) -> bool {
+        self.pieces
+            .iter()
+            .flatten()
+            .any(|&piece| piece == Piece::Pawn)
+    }
+
+    /// The material a capture or a promotion leaves.
+    fn successors(&self) -> Vec<Signature> {
+        let mut successors = vec![];
+        for side in 0..2 {
+            for (i, &piece) in self.pieces[side].iter().enumerate() {
+                let mut pieces = self.pieces.clone();
+                pieces[side].remove(i);
+                successors.push(Self::new(pieces.clone()));
+                if piece == Piece::Pawn {
+                    for promo in [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight] {
+                        let mut pieces = pieces.clone();
+                        pieces[side].push(promo);
+                        successors.push(Self::new(pieces));
+                    }
+                }
+            }
+        }
+        successors
+    }
+
+    /// The pieces in the order their squares are indexed: the kings, then the other white
+    /// pieces and the other black pieces.
+    fn layout(&self) -> Vec<(Piece, Side)> {
+        let mut layout = vec![(Piece::King, Side::White), (Piece::King, Side::Black)];
+        for (side, pieces) in [Side::White, Side::Black].into_iter().zip(&self.pieces) {
+            layout.extend(pieces.iter().map(|&piece| (piece, side)));
+        }
+        layout
+    }
+
+    /// Where the white king is indexed. With pawns only the board's mirror image is the same
+    /// position, otherwise its rotations and reflections are too.
+    fn king_squares(&self) -> usize {
+        if self.has_pawns() {
+            32
+        } else {
+            TRIANGLE.len()
+        }
+    }
+
+    /// The number of indices of the table.
+    pub fn size(&self) -> usize {
+        2 * self.king_squares() * 64usize.pow(self.count() as u32 - 1)
+    }
+
+    /// Turns the board so the white king is where the index wants it, and puts the squares of
+    /// alike pieces in order.
+    fn canonical(&self, layout: &[(Piece, Side)], squares: &mut [u8]) {
+        fn transform(squares: &mut [u8], f: fn(u8) -> u8) {
+            squares.iter_mut().for_each(|sq| *sq = f(*sq));
+        }
+        let file = |sq: u8| sq % 8;
+        let rank = |sq: u8| sq / 8;
+        let diagonal = |sq: u8| ((sq >> 3) | (sq << 3)) & 63;
+        if file(squares[0]) > 3 {
+            transform(squares, |sq| sq ^ 7);
+        }
+        if !self.has_pawns() {
+            if rank(squares[0]) > 3 {
+                transform(squares, |sq| sq ^ 56);
+            }
+            if rank(squares[0]) > file(squares[0]) {
+                transform(squares, diagonal);
+            }
+        }
+        Self::sort_groups(layout, squares);
+        // A king on the diagonal leaves two ways to turn the board; take the smaller.
+        if !self.has_pawns() && rank(squares[0]) == file(squares[0]) {
+            let mut mirrored = squares.to_vec();
+            transform(&mut mirrored, diagonal);
+            Self::sort_groups(layout, &mut mirrored);
+            if *mirrored < *squares {
+                squares.copy_from_slice(&mirrored);
+            }
+        }
+    }
+
+    fn sort_groups(layout: &[(Piece, Side)], squares: &mut [u8]) {
+        let mut start = 0;
+        for end in 1..=layout.len() {
+            if end == layout.len() || layout[end] != layout[start] {
+                squares[start..end].sort_unstable();
+                start = end;
+            }
+        }
+    }
+
+    fn encode(&self, to_move: Side, squares: &[u8]) -> usize {
+        let king = match self.has_pawns() {
+            true => (squares[0] / 8 * 4 + squares[0] % 8) as usize,
+            false => TRIANGLE.iter().position(|&sq| sq == squares[0]).unwrap(),
+        };
+        let mut idx = to_move as usize * self.king_squares() + king;
+        for &sq in &squares[1..] {
+            idx = idx * 64 + sq as usize;
+        }
+        idx
+    }
+
+    /// The index of `board`, which must have this material.
+    pub fn index(&self, board: &Board) -> usize {
+        let layout = self.layout();
+        let mut squares: Vec<u8> = vec![];
+        let mut seen = vec![];
+        for &(piece, side) in &layout {
+            if seen.contains(&(piece, side)) {
+                continue;
+            }
+            seen.push((piece, side));
+            let on = board.pieces(piece) & board.color_pieces(side);
+            squares.extend(on.into_iter().map(|sq| sq.0));
+        }
+        self.canonical(&layout, &mut squares);
+        self.encode(board.to_move(), &squares)
+    }
+
+    /// The position stored at `idx`, or `None` if there is none.
+    fn board(&self, idx: usize) -> Option<Board> {
+        let layout = self.layout();
+        let mut squares = vec![0u8; layout.len()];
+        let mut rest = idx;
+        for sq in squares[1..].iter_mut().rev() {
+            *sq = (rest % 64) as u8;
+            rest /= 64;
+        }
+        let king = rest % self.king_squares();
+        squares[0] = match self.has_pawns() {
+            true => (king / 4 * 8 + king % 4) as u8,
+            false => TRIANGLE[king],
+        };
+        let to_move = match rest / self.king_squares() {
+            0 => Side::White,
+            _ => Side::Black,
+        };
+
+        let mut board = Board::default();
+        for (&(piece, side), &sq) in layout.iter().zip(&squares) {
+            let back_rank = sq / 8 == 0 || sq / 8 == 7;
+            if board.piece(Square(sq)).is_some() || (piece == Piece::Pawn && back_rank) {
+                return None;
+            }
+            board.set_square(Square(sq), piece, side);
+        }
+        for side in [Side::White, Side::Black] {
+            board.castle_rights_mut(side).remove_kingside();
+            board.castle_rights_mut(side).remove_queenside();
+        }
+        board.set_to_move(to_move);
+        if board.validate().is_err() {
+            return None;
+        }
+        let mut canonical = squares.clone();
+        self.canonical(&layout, &mut canonical);
+        (canonical == squares).then_some(board)
+    }
+}
+
+/// The distance to mate of every position with some material.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DtmTable {
+    signature: Signature,
+    values: Vec<u8>,
+}
+
+impl DtmTable {
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    /// The outcome of `board`, which must have the table's material. The tables know nothing
+    /// of castling.
+    pub fn probe(&self, board: &Board) -> Option<Dtm> {
+        Dtm::from_value(self.values[self.signature.index(board)])
+    }
+
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        let name = self.signature.to_string();
+        out.write_all(MAGIC)?;
+        out.write_all(&[name.len() as u8])?;
+        out.write_all(name.as_bytes())?;
+        out.write_all(&self.values)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_owned());
+        let rest = bytes
+            .strip_prefix(MAGIC)
+            .ok_or_else(|| invalid("not a table"))?;
+        let (&len, rest) = rest.split_first().ok_or_else(|| invalid("truncated"))?;
+        if rest.len() < len as usize {
+            return Err(invalid("truncated"));
+        }
+        let (name, values) = rest.split_at(len as usize);
+        let signature: Signature = std::str::from_utf8(name)
+            .ok()
+            .and_then(|name| name.parse().ok())
+            .ok_or_else(|| invalid("bad material"))?;
+        if values.len() != signature.size() {
+            return Err(invalid("wrong size"));
+        }
+        Ok(Self {
+            signature,
+            values: values.to_vec(),
+        })
+    }
+
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn summary(&self) -> TableSummary {
+        let mut summary = TableSummary {
+            signature: self.signature.to_string(),
+            ..TableSummary::default()
+        };
+        for dtm in self
+            .values
+            .iter()
+            .filter_map(|&value| Dtm::from_value(value))
+        {
+            summary.positions += 1;
+            match dtm {
+                Dtm::Win(plies) => {
+                    summary.wins += 1;
+                    summary.longest = summary.longest.max(plies);
+                }
+                Dtm::Loss(_) => summary.losses += 1,
+                Dtm::Draw => {}
+            }
+        }
+        summary
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TableSummary {
+    pub signature: String,
+    /// Counted once for every symmetric version stored.
+    pub positions: usize,
+    pub wins: usize,
+    pub losses: usize,
+    /// The longest win, in plies.
+    pub longest: u32,
+}
+
+impl Display for TableSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} positions, {} won and {} lost by the side to move, longest mate in {} plies",
+            self.signature, self.positions, self.wins, self.losses, self.longest
+        )
+    }
+}
+
+/// Builds the table of `signature` by retrograde analysis. Mates are found first; a position
+/// with a move to one lost `n` plies away wins in `n + 1`, and one whose every move leads to a
+/// win for the opponent loses one ply after the slowest of them. Captures and promotions leave
+/// the table, and are looked up in `tables`.
+fn generate(signature: &Signature, tables: &DtmTables) -> DtmTable {
+    let size = signature.size();
+    let mut values = vec![INVALID; size];
+    // Per position, the moves within the table not yet known to lose, counting moves to
+    // symmetric positions once.
+    let mut remaining = vec![0u8; size];
+    // The ply a position is lost at if all its moves within the table lose sooner, or `None`
+    // if some capture or promotion draws or wins.
+    let mut lost_at: Vec<Option<u8>> = vec![None; size];
+    let mut plies: Vec<Vec<u32>> = vec![];
+    let schedule = |plies: &mut Vec<Vec<u32>>, ply: usize, idx: usize| {
+        assert!(
+            ply < INVALID as usize - 1,
+            "{signature} mates take too long"
+        );
+        if plies.len() <= ply {
+            plies.resize(ply + 1, vec![]);
+        }
+        plies[ply].push(idx as u32);
+    };
+
+    for idx in 0..size {
+        let Some(board) = signature.board(idx) else {
+            continue;
+        };
+        values[idx] = DRAW;
+        let mut children = vec![];
+        let mut win: Option<u32> = None;
+        let mut escape = false;
+        let mut slowest_loss = 0;
+        let mut moves = 0;
+        for mv in board.legal_moves() {
+            moves += 1;
+            let exit = board.piece(mv.dest()).is_some() || mv.promo().is_some();
+            let child = board.clone().apply_move(&mv).unwrap();
+            if !exit {
+                children.push(signature.index(&child));
+                continue;
+            }
+            match tables.probe(&child).expect("tables to leave to") {
+                Dtm::Loss(plies) => win = Some(win.unwrap_or(u32::MAX).min(plies + 1)),
+                Dtm::Win(plies) => slowest_loss = slowest_loss.max(plies + 1),
+                Dtm::Draw => escape = true,
+            }
+        }
+        if moves == 0 {
+            if board.is_in_check(board.to_move()) {
+                schedule(&mut plies, 0, idx);
+            }
+            continue;
+        }
+        children.sort_unstable();
+        children.dedup();
+        remaining[idx] = children.len() as u8;
+        if let Some(win) = win {
+            schedule(&mut plies, win as usize, idx);
+        } else if !escape {
+            lost_at[idx] = Some(slowest_loss as u8);
+            if children.is_empty() {
+                schedule(&mut plies, slowest_loss as usize, idx);
+            }
+        }
+    }
+
+    let mut ply = 0;
+    while ply < plies.len() {
+        for idx in std::mem::take(&mut plies[ply]) {
+            let idx = idx as usize;
+            // Found sooner already.
+            if values[idx] != DRAW {
+                continue;
+            }
+            values[idx] = ply as u8 + 1;
+            let board = signature.board(idx).unwrap();
+            let mut parents: Vec<usize> = board
+                .unmoves()
+                .iter()
+                .map(|unmove| signature.index(&board.unmake(unmove)))
+                .collect();
+            parents.sort_unstable();
+            parents.dedup();
+            for parent in parents {
+                if values[parent] != DRAW {
+                    continue;
+                }
+                if ply % 2 == 0 {
+                    schedule(&mut plies, ply + 1, parent);
+                    continue;
+                }
+                remaining[parent] -= 1;
+                if let Some(floor) = lost_at[parent].filter(|_| remaining[parent] == 0) {
+                    schedule(&mut plies, (ply + 1).max(floor as usize), parent);
+                }
+            }
+        }
+        ply += 1;
+    }
+    DtmTable {
+        signature: signature.clone(),
+        values,
+    }
+}
+
+/// Generated tables, by material.
+#[derive(Debug, Default)]
+pub struct DtmTables {
+    tables: HashMap<Signature, DtmTable>,
+}
+
+impl DtmTables {
+    /// Generates the table of `signature` and the tables of the material it can turn into,
+    /// unless they are there already. Returns the signatures generated, in order.
+    pub fn generate(&mut self, signature: &Signature) -> Vec<Signature> {
+        let mut generated = vec![];
+        if signature.count() == 2 || self.tables.contains_key(signature) {
+            return generated;
+        }
+        for successor in signature.successors() {
+            generated.extend(self.generate(&successor));
+        }
+        let table = generate(signature, self);
+        self.tables.insert(signature.clone(), table);
+        generated.push(signature.clone());
+        generated
+    }
+
+    pub fn insert(&mut self, table: DtmTable) {
+        self.tables.insert(table.signature.clone(), table);
+    }
+
+    pub fn get(&self, signature: &Signature) -> Option<&DtmTable> {
+        self.tables.get(signature)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    /// The outcome of `board`, if there is a table for it. Bare kings are always a draw. The
+    /// tables are generated without castling and en passant, so positions that allow either
+    /// have no entry.
+    pub fn probe(&self, board: &Board) -> Option<Dtm> {
+        let castling = [Side::White, Side::Black]
+            .iter()
+            .any(|&side| !board.castle_rights(side).is_empty());
+        if castling || !board.enpassant().is_empty() {
+            return None;
+        }
+        let signature = Signature::of(board);
+        if signature.count() == 2 {
+            return Some(Dtm::Draw);
+        }
+        if let Some(table) = self.tables.get(&signature) {
+            return table.probe(board);
+        }
+        // A table also answers for the material with the colours swapped, on the mirrored board.
+        let mirrored = board.mirrored();
+        self.tables.get(&Signature::of(&mirrored))?.probe(&mirrored)
+    }
+
+    /// Reads every `.dtm` file in `dir`.
+    pub fn load(dir: &str) -> io::Result<Self> {
+        let mut tables = Self::default();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == EXTENSION) {
+                tables.insert(DtmTable::open(&path)?);
+            }
+        }
+        Ok(tables)
+    }
+
+    /// Writes the table of `signature` to `dir`, named like `KQK.dtm`.
+    pub fn save(&self, signature: &Signature, dir: &str) -> io::Result<()> {
+        let table = self.get(signature).expect("a generated table");
+        let path = Path::new(dir).join(format!("{signature}.{EXTENSION}"));
+        let mut file = io::BufWriter::new(fs::File::create(path)?);
+        table.write(&mut file)?;
+        file.flush()
+    }
+}
+
+/// Generates the tables of `signatures`, and those they need, into `dir`.
+pub fn generate_table_files(signatures: &[Signature], dir: &str) -> io::Result<Vec<TableSummary>> {
+    let mut tables = DtmTables::default();
+    let mut summaries = vec![];
+    for signature in signatures {
+        for generated in tables.generate(signature) {
+            tables.save(&generated, dir)?;
+            summaries.push(tables.get(&generated).unwrap().summary());
+        }
+    }
+    Ok(summaries)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chess::board::Board;
+
+    use super::{generate_table_files, Dtm, DtmTable, DtmTables, Signature, SignatureError};
+
+    fn probe(tables: &DtmTables, fen: &str) -> Option<Dtm> {
+        tables.probe(&Board::from_fen(fen).unwrap())
+    }
+
+    #[test]
+    fn test_signature() {
+        let signature: Signature = "KNBK".parse().unwrap();
+        assert_eq!(signature.to_string(), "KBNK");
+        assert_eq!(signature.count(), 4);
+        assert_eq!("KKQ".parse::<Signature>().unwrap().to_string(), "KKQ");
+        assert_eq!(
+            "KQ".parse::<Signature>(),
+            Err(SignatureError::Malformed("KQ".to_owned()))
+        );
+        assert_eq!(
+            "KQXK".parse::<Signature>(),
+            Err(SignatureError::Malformed("KQXK".to_owned()))
+        );
+        assert_eq!(
+            "KQRKB".parse::<Signature>(),
+            Err(SignatureError::TooManyPieces("KQRKB".to_owned()))
+        );
+        assert_eq!(
+            "KPKP".parse::<Signature>(),
+            Err(SignatureError::PawnsOnBothSides("KPKP".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_index() {
+        let signature: Signature = "KRKN".parse().unwrap();
+        let fens = [
+            "8/8/8/3k4/8/8/2n5/4K2R w - - 0 1",
+            "8/8/8/4k3/8/8/5n2/R2K4 w - - 0 1",
+            "R7/8/8/K7/4k3/1n6/8/8 w - - 0 1",
+            "8/8/8/8/8/2K5/8/k1R1n3 b - - 0 1",
+        ];
+        let boards: Vec<Board> = fens
+            .iter()
+            .map(|fen| Board::from_fen(fen).unwrap())
+            .collect();
+        for board in &boards {
+            let idx = signature.index(board);
+            assert!(idx < signature.size());
+            let stored = signature.board(idx).unwrap();
+            assert_eq!(signature.index(&stored), idx);
+        }
+        // The first three are mirror images of each other.
+        assert_eq!(signature.index(&boards[0]), signature.index(&boards[1]));
+        assert_eq!(signature.index(&boards[0]), signature.index(&boards[2]));
+        assert_ne!(signature.index(&boards[0]), signature.index(&boards[3]));
+    }
+
+    #[test]
+    fn test_kqk_krk() {
+        let mut tables = DtmTables::default();
+        let generated = tables.generate(&"KRK".parse().unwrap());
+        assert_eq!(generated, vec!["KRK".parse().unwrap()]);
+        tables.generate(&"KQK".parse().unwrap());
+        assert_eq!(tables.len(), 2);
+
+        let kqk = tables.get(&"KQK".parse().unwrap()).unwrap().summary();
+        let krk = tables.get(&"KRK".parse().unwrap()).unwrap().summary();
+        assert_eq!(kqk.longest, 19);
+        assert_eq!(krk.longest, 31);
+
+        assert_eq!(
+            probe(&tables, "k7/8/1K6/8/8/8/8/6Q1 w - - 0 1"),
+            Some(Dtm::Win(1))
+        );
+        assert_eq!(
+            probe(&tables, "k7/1Q6/2K5/8/8/8/8/8 b - - 0 1"),
+            Some(Dtm::Loss(0))
+        );
+        assert_eq!(
+            probe(&tables, "7k/6Q1/5K2/8/8/8/8/8 b - - 0 1"),
+            Some(Dtm::Loss(0))
+        );
+        assert_eq!(
+            probe(&tables, "k7/1Q6/8/8/8/3K4/8/8 b - - 0 1"),
+            Some(Dtm::Draw)
+        );
+        assert_eq!(
+            probe(&tables, "k7/2Q5/1K6/8/8/8/8/8 b - - 0 1"),
+            Some(Dtm::Draw)
+        );
+        assert_eq!(
+            probe(&tables, "8/8/8/8/8/8/8/k1K5 w - - 0 1"),
+            Some(Dtm::Draw)
+        );
+        // Black with the queen, from the KQK table.
+        assert_eq!(
+            probe(&tables, "6q1/8/8/8/8/1k6/8/K7 b - - 0 1"),
+            Some(Dtm::Win(1))
+        );
+        assert_eq!(
+            probe(&tables, "8/8/3k4/8/8/8/1q6/K7 w - - 0 1"),
+            Some(Dtm::Draw)
+        );
+        assert_eq!(probe(&tables, "r3k3/8/8/8/8/8/8/4K3 w q - 0 1"), None);
+        assert_eq!(probe(&tables, "k7/8/1K6/8/8/8/8/6BN w - - 0 1"), None);
+
+        let table = tables.get(&"KQK".parse().unwrap()).unwrap();
+        let mut bytes = vec![];
+        table.write(&mut bytes).unwrap();
+        assert_eq!(&DtmTable::from_bytes(&bytes).unwrap(), table);
+        assert!(DtmTable::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(DtmTable::from_bytes(b"DTM0").is_err());
+    }
+
+    #[test]
+    fn test_kpk() {
+        let dir = std::env::temp_dir().join(format!("dtm-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let summaries =
+            generate_table_files(&["KPK".parse().unwrap()], dir.to_str().unwrap()).unwrap();
+        let names: Vec<&str> = summaries.iter().map(|s| s.signature.as_str()).collect();
+        assert_eq!(names, ["KQK", "KRK", "KBK", "KNK", "KPK"]);
+        assert_eq!(summaries[2].wins, 0);
+        assert_eq!(summaries[4].longest, 55);
+
+        let tables = DtmTables::load(dir.to_str().unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(tables.len(), 5);
+        // Outside the square of the pawn, and a rook pawn with the defender in the corner.
+        assert!(matches!(
+            probe(&tables, "8/8/8/8/8/8/P7/K6k b - - 0 1"),
+            Some(Dtm::Loss(_))
+        ));
+        assert_eq!(
+            probe(&tables, "k7/8/8/8/8/8/P7/K7 w - - 0 1"),
+            Some(Dtm::Draw)
+        );
+        // Promoting at once mates.
+        assert_eq!(
+            probe(&tables, "k7/2P5/1K6/8/8/8/8/8 w - - 0 1"),
+            Some(Dtm::Win(1))
+        );
+        // The same with black's pawn.
+        assert!(matches!(
+            probe(&tables, "k6K/p7/8/8/8/8/8/8 w - - 0 1"),
+            Some(Dtm::Loss(_))
+        ));
+        assert_eq!(
+            probe(&tables, "8/8/8/8/8/1k6/2p5/K7 b - - 0 1"),
+            Some(Dtm::Win(1))
+        );
+        // Right after a double push, which the tables don't know about.
+        assert_eq!(probe(&tables, "k7/8/8/8/4P3/8/8/K7 b - e3 0 1"), None);
+    }
+}
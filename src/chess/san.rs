@@ -0,0 +1,282 @@
+use std::fmt::{Display, Write};
+
+use super::{
+    board::Board,
+    moves::Move,
+    piece::Piece,
+    side::Side,
+    square::{File, Rank, Square},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SanError {
+    /// Not a move in any notation we know.
+    Malformed,
+    /// No legal move is written that way.
+    NoSuchMove,
+    /// More than one legal move fits, e.g. `Nd2` when both knights can go there.
+    Ambiguous,
+}
+
+impl Display for SanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SanError::Malformed => write!(f, "not a move"),
+            SanError::NoSuchMove => write!(f, "no such legal move"),
+            SanError::Ambiguous => write!(f, "ambiguous move"),
+        }
+    }
+}
+
+fn piece_from_char(c: char) -> Option<Piece> {
+    Some(match c.to_ascii_uppercase() {
+        'N' => Piece::Knight,
+        'B' => Piece::Bishop,
+        'R' => Piece::Rook,
+        'Q' => Piece::Queen,
+        'K' => Piece::King,
+        _ => return None,
+    })
+}
+
+impl Board {
+    /// The move in standard algebraic notation, e.g. `Nbd2`, `exd6`, `O-O` or `e8=Q#`. `mv`
+    /// must be legal here.
+    pub fn san(&self, mv: &Move) -> String {
+        let (piece, _) = self.piece(mv.start()).unwrap();
+        let mut san = String::new();
+        if mv.is_castling(self) {
+            san.push_str(if mv.is_kingside_castle(self) {
+                "O-O"
+            } else {
+                "O-O-O"
+            });
+        } else {
+            let capture = self.piece(mv.dest()).is_some()
+                || (piece == Piece::Pawn && mv.start().file() != mv.dest().file());
+            if piece == Piece::Pawn {
+                if capture {
+                    write!(san, "{}", mv.start().file()).unwrap();
+                }
+            } else {
+                san.push(piece.to_char(Side::White));
+                let rivals: Vec<Square> = self
+                    .legal_moves()
+                    .filter(|m| m.dest() == mv.dest() && m.start() != mv.start())
+                    .filter(|m| self.check_piece(m.start()) == Some(piece))
+                    .map(|m| m.start())
+                    .collect();
+                // Disambiguate by file if that's enough, else by rank, else by both.
+                let start = mv.start();
+                if rivals.iter().all(|sq| sq.file() != start.file()) {
+                    if !rivals.is_empty() {
+                        write!(san, "{}", start.file()).unwrap();
+                    }
+                } else if rivals.iter().all(|sq| sq.rank() != start.rank()) {
+                    write!(san, "{}", start.rank()).unwrap();
+                } else {
+                    write!(san, "{start}").unwrap();
+                }
+            }
+            if capture {
+                san.push('x');
+            }
+            write!(san, "{}", mv.dest()).unwrap();
+            if let Some(promo) = mv.promo() {
+                write!(san, "={}", promo.to_char(Side::White)).unwrap();
+            }
+        }
+        let child = self.clone().apply_move(mv).unwrap();
+        if child.is_in_check(child.to_move()) {
+            san.push(match child.legal_moves().next() {
+                Some(_) => '+',
+                None => '#',
+            });
+        }
+        san
+    }
+
+    /// Finds the legal move written as `san`. Besides strict SAN this accepts what people and
+    /// other programs commonly write: `0-0` for castling, captures without `x`, promotions
+    /// without `=` (`e8Q`), more disambiguation than needed, long algebraic (`Ng1-f3`) and
+    /// check marks or annotations like `!?` that are wrong or missing.
+    pub fn parse_san(&self, san: &str) -> Result<Move, SanError> {
+        let san = san
+            .trim()
+            .trim_end_matches("e.p.")
+            .trim_end_matches(['+', '#', '!', '?', ' ']);
+        let castle = san.replace('0', "O");
+        if castle == "O-O" || castle == "O-O-O" {
+            let kingside = castle == "O-O";
+            return self
+                .legal_moves()
+                .find(|mv| mv.is_castling(self) && mv.is_kingside_castle(self) == kingside)
+                .ok_or(SanError::NoSuchMove);
+        }
+
+        let mut chars: Vec<char> = san
+            .chars()
+            .filter(|c| !matches!(c, 'x' | ':' | '-' | '='))
+            .collect();
+        let promo = match chars.last() {
+            Some(&c) if !c.is_ascii_digit() => {
+                chars.pop();
+                let promo = piece_from_char(c).filter(|&p| p != Piece::King);
+                Some(promo.ok_or(SanError::Malformed)?)
+            }
+            _ => None,
+        };
+        let piece = match chars.first() {
+            Some(&c) if c.is_ascii_uppercase() => {
+                chars.remove(0);
+                piece_from_char(c).ok_or(SanError::Malformed)?
+            }
+            _ => Piece::Pawn,
+        };
+        if !(2..=4).contains(&chars.len()) {
+            return Err(SanError::Malformed);
+        }
+        let file = |c: char| File::try_from(c).ok();
+        let rank = |c: char| c.to_digit(10).and_then(|d| Rank::try_from(d as u8).ok());
+        let (from, to) = chars.split_at(chars.len() - 2);
+        let dest = match (file(to[0]), rank(to[1])) {
+            (Some(file), Some(rank)) => Square::from_rank_and_file(rank, file),
+            _ => return Err(SanError::Malformed),
+        };
+        let (mut from_file, mut from_rank) = (None, None);
+        for &c in from {
+            match (file(c), rank(c)) {
+                (Some(file), _) => from_file = Some(file),
+                (_, Some(rank)) => from_rank = Some(rank),
+                _ => return Err(SanError::Malformed),
+            }
+        }
+
+        let mut candidates = self.legal_moves().filter(|mv| {
+            mv.dest() == dest
+                && mv.promo() == promo
+                && self.check_piece(mv.start()) == Some(piece)
+                && !mv.is_castling(self)
+                && from_file.iter().all(|&file| mv.start().file() == file)
+                && from_rank.iter().all(|&rank| mv.start().rank() == rank)
+        });
+        match (candidates.next(), candidates.next()) {
+            (Some(mv), None) => Ok(mv),
+            (None, _) => Err(SanError::NoSuchMove),
+            (Some(_), Some(_)) => Err(SanError::Ambiguous),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chess::board::Board;
+
+    use super::SanError;
+
+    fn sans(fen: &str) -> Vec<String> {
+        let board = Board::from_fen(fen).unwrap();
+        let mut sans: Vec<String> = board.legal_moves().map(|mv| board.san(&mv)).collect();
+        sans.sort();
+        sans
+    }
+
+    #[test]
+    fn test_san() {
+        let start = sans("startpos");
+        assert!(start.contains(&"e4".to_owned()));
+        assert!(start.contains(&"Nf3".to_owned()));
+
+        let has = |fen: &str, san: &str| assert!(sans(fen).contains(&san.to_owned()), "{san}");
+        has("r3k3/8/8/8/8/8/8/R3K2R w KQq - 0 1", "O-O");
+        has("r3k3/8/8/8/8/8/8/R3K2R w KQq - 0 1", "O-O-O");
+        has("r3k3/8/8/8/8/8/8/R3K2R w KQq - 0 1", "Rxa8+");
+        has("4k3/8/8/8/8/8/3q4/4K3 w - - 0 1", "Kxd2");
+        has("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "exd6");
+        has("4k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a8=Q+");
+        has("4k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a8=N");
+        has("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "Ra8#");
+
+        let rooks = sans("4k3/8/8/R7/8/8/4K3/R6R w - - 0 1");
+        for san in ["Rad1", "Rhd1", "R1a3", "R5a3", "Rb5"] {
+            assert!(rooks.contains(&san.to_owned()), "{san}");
+        }
+        has("4k3/8/8/8/8/Q7/8/Q1Q1K3 w - - 0 1", "Qa1b2");
+    }
+
+    #[test]
+    fn test_parse_san() {
+        let board = Board::from_fen("startpos").unwrap();
+        let mv = board.parse_san("Nf3").unwrap();
+        assert_eq!(board.san(&mv), "Nf3");
+        assert_eq!(board.parse_san("e4!?"), board.parse_san("e4"));
+        assert_eq!(board.parse_san("e5"), Err(SanError::NoSuchMove));
+        assert_eq!(board.parse_san("Nd2"), Err(SanError::NoSuchMove));
+        assert_eq!(board.parse_san(""), Err(SanError::Malformed));
+        assert_eq!(board.parse_san("Zf3"), Err(SanError::Malformed));
+        assert_eq!(board.parse_san("Nf9"), Err(SanError::Malformed));
+        assert_eq!(board.parse_san("hello"), Err(SanError::Malformed));
+    }
+
+    #[test]
+    fn test_parse_variants() {
+        let same = |fen: &str, strict: &str, variants: &[&str]| {
+            let board = Board::from_fen(fen).unwrap();
+            let mv = board.parse_san(strict).unwrap();
+            assert_eq!(board.san(&mv), strict);
+            for variant in variants {
+                assert_eq!(board.parse_san(variant), Ok(mv), "{variant}");
+            }
+        };
+        let castling = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        same(castling, "O-O", &["0-0", "O-O+", "O-O!"]);
+        same(castling, "O-O-O", &["0-0-0"]);
+        same(
+            castling,
+            "Rxa8+",
+            &["Ra8", "Rxa8", "Raxa8+", "Ra1xa8", "Ra1-a8"],
+        );
+        same(
+            "4k3/P7/8/8/8/8/8/4K3 w - - 0 1",
+            "a8=Q+",
+            &["a8Q", "a8=Q", "a8q", "a7a8Q", "a8=Q+!!"],
+        );
+        same(
+            "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1",
+            "exd6",
+            &["ed6", "exd6 e.p.", "e5d6"],
+        );
+        same(
+            "startpos",
+            "Nf3",
+            &["Ng1f3", "Ng1-f3", "Ngf3", "N1f3", "Nf3?!", "Nf3+"],
+        );
+
+        let board = Board::from_fen("4k3/P7/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(board.parse_san("a8"), Err(SanError::NoSuchMove));
+        assert_eq!(board.parse_san("a8=K"), Err(SanError::Malformed));
+
+        let rooks = Board::from_fen("4k3/8/8/R7/8/8/4K3/R6R w - - 0 1").unwrap();
+        assert_eq!(rooks.parse_san("Rd1"), Err(SanError::Ambiguous));
+        assert_eq!(rooks.parse_san("Ra3"), Err(SanError::Ambiguous));
+        assert!(rooks.parse_san("Rhd1").is_ok());
+        assert!(rooks.parse_san("R5a3").is_ok());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for fen in [
+            "startpos",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "4k3/8/8/8/8/Q7/8/Q1Q1K3 w - - 0 1",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            for mv in board.legal_moves() {
+                let san = board.san(&mv);
+                assert_eq!(board.parse_san(&san), Ok(mv), "{fen}: {san}");
+            }
+        }
+    }
+}
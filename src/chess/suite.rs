@@ -0,0 +1,298 @@
+use std::{
+    fmt::Display,
+    fs,
+    io::{self, BufRead, BufReader},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{ab::SearchSettings, tt::TranspositionTable};
+
+use super::epd::{Epd, EpdError};
+
+/// How much search each position gets. The search ends at whichever limit comes first, and
+/// lasts [`Budget::DEFAULT_TIME`] if none is set.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    pub time: Option<Duration>,
+    pub depth: Option<u64>,
+    pub nodes: Option<u64>,
+}
+
+impl Budget {
+    pub const DEFAULT_TIME: Duration = Duration::from_secs(1);
+
+    /// An unlimited search would never end, so it gets the default time instead.
+    fn limited(self) -> Self {
+        if self == Self::default() {
+            Self {
+                time: Some(Self::DEFAULT_TIME),
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+}
+
+/// How the engine did on one position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attempt {
+    /// The `id` of the record, or its line in the file.
+    pub id: String,
+    /// What a solution is, e.g. `bm Qg6` or `am Rxb2`.
+    pub expected: String,
+    /// In SAN, `None` if the first iteration didn't finish.
+    pub best: Option<String>,
+    /// When the search found the solution and kept it to the end, and at what depth.
+    pub found: Option<(Duration, u64)>,
+    pub depth: u64,
+    pub nodes: u64,
+    pub time: Duration,
+}
+
+impl Attempt {
+    pub fn solved(&self) -> bool {
+        self.found.is_some()
+    }
+}
+
+impl Display for Attempt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let best = self.best.as_deref().unwrap_or("none");
+        match self.found {
+            Some((time, depth)) => write!(
+                f,
+                "ok   {} {best} found at depth {depth} in {:.3}s",
+                self.id,
+                time.as_secs_f64()
+            ),
+            None => write!(
+                f,
+                "FAIL {} {best} ({}) at depth {} in {:.3}s",
+                self.id,
+                self.expected,
+                self.depth,
+                self.time.as_secs_f64()
+            ),
+        }
+    }
+}
+
+/// Searches the position of `epd` within `budget` and checks the move against its `bm` and
+/// `am` moves. The table is cleared first.
+pub fn solve(
+    epd: &Epd,
+    budget: &Budget,
+    tt: &Arc<TranspositionTable>,
+) -> Result<Attempt, EpdError> {
+    let best_moves = epd.best_moves()?;
+    let avoid_moves = epd.avoid_moves()?;
+    let expected: Vec<String> = ["bm", "am"]
+        .iter()
+        .filter_map(|&opcode| Some(format!("{opcode} {}", epd.operands(opcode)?.join(" "))))
+        .collect();
+    let board = &epd.board;
+    let budget = budget.limited();
+    tt.clear();
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let nodes = Arc::new(AtomicU64::new(0));
+    let done = AtomicBool::new(false);
+    let start = Instant::now();
+    let mut attempt = Attempt {
+        id: epd.id().unwrap_or("?").to_owned(),
+        expected: expected.join(", "),
+        best: None,
+        found: None,
+        depth: 0,
+        nodes: 0,
+        time: Duration::ZERO,
+    };
+    thread::scope(|scope| {
+        // The time and node limits can cut an iteration short.
+        scope.spawn(|| {
+            while !done.load(Ordering::SeqCst) {
+                let out_of_time = budget.time.is_some_and(|time| start.elapsed() >= time);
+                let out_of_nodes = budget
+                    .nodes
+                    .is_some_and(|limit| nodes.load(Ordering::Relaxed) >= limit);
+                if out_of_time || out_of_nodes {
+                    stop.store(true, Ordering::SeqCst);
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+
+        for depth in 1..=budget.depth.unwrap_or(u64::MAX) {
+            let settings = SearchSettings {
+                stop: Some(stop.clone()),
+                tt: Some(tt.clone()),
+                nodes: Some(nodes.clone()),
+                ..SearchSettings::search(depth)
+            };
+            let res = board.alphabeta(&settings);
+            if stop.load(Ordering::SeqCst) {
+                break;
+            }
+            let Some(mv) = res.data.last().map(|data| data.mv) else {
+                break;
+            };
+            let solution =
+                (best_moves.is_empty() || best_moves.contains(&mv)) && !avoid_moves.contains(&mv);
+            attempt.found = match solution {
+                true => attempt.found.or(Some((start.elapsed(), depth))),
+                false => None,
+            };
+            attempt.best = Some(board.san(&mv));
+            attempt.depth = depth;
+        }
+        done.store(true, Ordering::SeqCst);
+    });
+    attempt.nodes = nodes.load(Ordering::Relaxed);
+    attempt.time = start.elapsed();
+    Ok(attempt)
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SuiteSummary {
+    pub solved: usize,
+    pub total: usize,
+    /// Records that couldn't be read or whose moves aren't legal.
+    pub errors: usize,
+    pub nodes: u64,
+    pub time: Duration,
+}
+
+impl Display for SuiteSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "solved {} of {}, {} bad records, {} nodes in {:.3}s",
+            self.solved,
+            self.total,
+            self.errors,
+            self.nodes,
+            self.time.as_secs_f64()
+        )
+    }
+}
+
+/// Runs every record of an EPD file with `bm` or `am` moves, printing how each went and a
+/// summary at the end.
+pub fn run_suite(input: impl BufRead, budget: &Budget) -> io::Result<SuiteSummary> {
+    let tt = Arc::new(TranspositionTable::new(64));
+    let mut summary = SuiteSummary::default();
+    for (number, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.trim().starts_with('#') {
+            continue;
+        }
+        let attempt = Epd::parse(&line).and_then(|mut epd| {
+            if epd.operands("bm").is_none() && epd.operands("am").is_none() {
+                return Ok(None);
+            }
+            if epd.id().is_none() {
+                epd.set("id", &[&format!("line {}", number + 1)]);
+            }
+            solve(&epd, budget, &tt).map(Some)
+        });
+        match attempt {
+            Ok(Some(attempt)) => {
+                println!("{attempt}");
+                summary.total += 1;
+                summary.solved += attempt.solved() as usize;
+                summary.nodes += attempt.nodes;
+                summary.time += attempt.time;
+            }
+            Ok(None) => {}
+            Err(err) => {
+                println!("line {}: {err}", number + 1);
+                summary.errors += 1;
+            }
+        }
+    }
+    println!("{summary}");
+    Ok(summary)
+}
+
+pub fn run_suite_file(path: &str, budget: &Budget) -> io::Result<SuiteSummary> {
+    run_suite(BufReader::new(fs::File::open(path)?), budget)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use crate::{chess::epd::Epd, tt::TranspositionTable};
+
+    use super::{run_suite, solve, Budget};
+
+    const QUEEN: &str = "4k3/8/8/3q4/8/8/8/3RK3 w - -";
+
+    fn budget(depth: u64) -> Budget {
+        Budget {
+            time: None,
+            depth: Some(depth),
+            nodes: None,
+        }
+    }
+
+    #[test]
+    fn test_solve() {
+        let tt = Arc::new(TranspositionTable::new(1));
+        let epd = Epd::parse(&format!("{QUEEN} bm Rxd5; id \"queen\";")).unwrap();
+        let attempt = solve(&epd, &budget(3), &tt).unwrap();
+        assert!(attempt.solved());
+        assert_eq!(attempt.best.as_deref(), Some("Rxd5"));
+        assert_eq!(attempt.depth, 3);
+        assert!(attempt
+            .to_string()
+            .starts_with("ok   queen Rxd5 found at depth "));
+
+        let epd = Epd::parse(&format!("{QUEEN} am Rxd5;")).unwrap();
+        let attempt = solve(&epd, &budget(2), &tt).unwrap();
+        assert!(!attempt.solved());
+        assert!(attempt
+            .to_string()
+            .starts_with("FAIL ? Rxd5 (am Rxd5) at depth 2"));
+
+        // A node limit stops the search, here before the first iteration is done.
+        let epd = Epd::parse(&format!("{QUEEN} bm Rxd5;")).unwrap();
+        let nodes = Budget {
+            nodes: Some(1),
+            ..budget(100)
+        };
+        let attempt = solve(&epd, &nodes, &tt).unwrap();
+        assert!(attempt.depth < 100);
+
+        let time = Budget {
+            time: Some(Duration::from_millis(50)),
+            ..budget(100)
+        };
+        let attempt = solve(&epd, &time, &tt).unwrap();
+        assert!(attempt.solved());
+        assert!(attempt.time < Duration::from_secs(5));
+
+        // Without limits the search still ends.
+        let attempt = solve(&epd, &Budget::default(), &tt).unwrap();
+        assert!(attempt.solved());
+        assert!(attempt.time >= Budget::DEFAULT_TIME);
+        assert!(attempt.time < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_run_suite() {
+        let suite = format!(
+            "# a comment\n\n{QUEEN} bm Rxd5;\n{QUEEN} bm Ke2;\n{QUEEN} bm Qd4;\n{QUEEN} D1 5;\n"
+        );
+        let summary = run_suite(suite.as_bytes(), &budget(2)).unwrap();
+        assert_eq!(summary.solved, 1);
+        assert_eq!(summary.total, 2);
+        assert_eq!(summary.errors, 1);
+    }
+}
@@ -0,0 +1,1425 @@
+use std::{
+    collections::HashMap,
+    fs,
+    ops::Neg,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+};
+
+use memmap2::Mmap;
+
+use super::{board::Board, moves::Move, piece::Piece, side::Side, square::Square};
+
+/// The score of a tablebase win, in pawns. Wins found closer to the root score higher.
+pub const TB_WIN: f32 = 200.0;
+
+/// The rank of a root move that wins for certain, see [`Tablebases::rank_root_moves`].
+pub const CERTAIN_WIN: i32 = 1 << 18;
+
+/// The most pieces, kings included, that any Syzygy table has.
+const MAX_PIECES: usize = 7;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+const DTZ_MAGIC: [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+
+/// Flags of a table's pairs data.
+const STM: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+/// The outcome with best play for the side to move. Cursed wins and blessed losses are wins
+/// and losses that the fifty-move rule turns into draws.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Self {
+        match value {
+            ..=-2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            0 => Wdl::Draw,
+            1 => Wdl::CursedWin,
+            _ => Wdl::Win,
+        }
+    }
+
+    fn signum(self) -> i32 {
+        (self as i32).signum()
+    }
+}
+
+impl Neg for Wdl {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Wdl::from_value(-(self as i32))
+    }
+}
+
+/// The DTZ of a position whose best move resets the fifty-move counter.
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Win => 1,
+        Wdl::CursedWin => 101,
+        Wdl::Draw => 0,
+        Wdl::BlessedLoss => -101,
+        Wdl::Loss => -1,
+    }
+}
+
+/// Squares are numbered like [`Square`], a1 = 0 and h8 = 63.
+const fn rank(sq: usize) -> usize {
+    sq / 8
+}
+
+const fn file(sq: usize) -> usize {
+    sq % 8
+}
+
+/// How far `sq` is above the a1-h8 diagonal, negative below it.
+const fn off_diagonal(sq: usize) -> i32 {
+    rank(sq) as i32 - file(sq) as i32
+}
+
+/// The lookup tables of the index encoding, the same for every table.
+struct Encoding {
+    /// `binomial[k][n]` ways to pick `k` of `n` squares.
+    binomial: [[u64; 64]; MAX_PIECES],
+    /// The squares below the a1-h8 diagonal, numbered 0 to 27.
+    map_b1h1h7: [u64; 64],
+    /// The a1-d1-d4 triangle numbered 0 to 9, the diagonal last.
+    map_a1d1d4: [u64; 64],
+    /// The 462 ways to place two kings with the first in the triangle.
+    map_kk: [[u64; 64]; 10],
+    /// Pawn squares numbered from 47 down, so the leading pawn has the highest number.
+    map_pawns: [u64; 64],
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+const fn kings_touch(a: usize, b: usize) -> bool {
+    let files = file(a).abs_diff(file(b));
+    let ranks = rank(a).abs_diff(rank(b));
+    files <= 1 && ranks <= 1
+}
+
+const fn generate() -> Encoding {
+    let mut e = Encoding {
+        binomial: [[0; 64]; MAX_PIECES],
+        map_b1h1h7: [0; 64],
+        map_a1d1d4: [0; 64],
+        map_kk: [[0; 64]; 10],
+        map_pawns: [0; 64],
+        lead_pawn_idx: [[0; 64]; 6],
+        lead_pawns_size: [[0; 4]; 6],
+    };
+
+    let mut code = 0;
+    let mut sq = 0;
+    while sq < 64 {
+        if off_diagonal(sq) < 0 {
+            e.map_b1h1h7[sq] = code;
+            code += 1;
+        }
+        sq += 1;
+    }
+
+    let mut code = 0;
+    let mut diagonal = [0; 4];
+    let mut diagonals = 0;
+    let mut sq = 0;
+    while sq <= 27 {
+        if off_diagonal(sq) < 0 && file(sq) <= 3 {
+            e.map_a1d1d4[sq] = code;
+            code += 1;
+        } else if off_diagonal(sq) == 0 && file(sq) <= 3 {
+            diagonal[diagonals] = sq;
+            diagonals += 1;
+        }
+        sq += 1;
+    }
+    let mut i = 0;
+    while i < diagonals {
+        e.map_a1d1d4[diagonal[i]] = code;
+        code += 1;
+        i += 1;
+    }
+
+    // With the first king on the diagonal, the second is mapped below it. Both on the
+    // diagonal come last.
+    let mut code = 0;
+    let mut both = [(0, 0); 32];
+    let mut boths = 0;
+    let mut idx = 0;
+    while idx < 10 {
+        let mut s1 = 0;
+        while s1 <= 27 {
+            // b1 is the only square of the triangle mapped to 0.
+            if e.map_a1d1d4[s1] == idx as u64 && (idx != 0 || s1 == 1) {
+                let mut s2 = 0;
+                while s2 < 64 {
+                    if kings_touch(s1, s2) || (off_diagonal(s1) == 0 && off_diagonal(s2) > 0) {
+                    } else if off_diagonal(s1) == 0 && off_diagonal(s2) == 0 {
+                        both[boths] = (idx, s2);
+                        boths += 1;
+                    } else {
+                        e.map_kk[idx][s2] = code;
+                        code += 1;
+                    }
+                    s2 += 1;
+                }
+            }
+            s1 += 1;
+        }
+        idx += 1;
+    }
+    let mut i = 0;
+    while i < boths {
+        e.map_kk[both[i].0][both[i].1] = code;
+        code += 1;
+        i += 1;
+    }
+
+    e.binomial[0][0] = 1;
+    let mut n = 1;
+    while n < 64 {
+        let mut k = 0;
+        while k < MAX_PIECES && k <= n {
+            let left = if k > 0 { e.binomial[k - 1][n - 1] } else { 0 };
+            let right = if k < n { e.binomial[k][n - 1] } else { 0 };
+            e.binomial[k][n] = left + right;
+            k += 1;
+        }
+        n += 1;
+    }
+
+    let mut available = 47;
+    let mut count = 1;
+    while count <= 5 {
+        let mut f = 0;
+        while f < 4 {
+            let mut idx = 0;
+            let mut r = 1;
+            while r <= 6 {
+                let sq = r * 8 + f;
+                if count == 1 {
+                    e.map_pawns[sq] = available;
+                    e.map_pawns[sq ^ 7] = available - 1;
+                    available = available.saturating_sub(2);
+                }
+                e.lead_pawn_idx[count][sq] = idx;
+                idx += e.binomial[count - 1][e.map_pawns[sq] as usize];
+                r += 1;
+            }
+            e.lead_pawns_size[count][f] = idx;
+            f += 1;
+        }
+        count += 1;
+    }
+    e
+}
+
+static ENCODING: Encoding = generate();
+
+/// What a table covers, as its file name says.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Material {
+    /// Like `KRPvKR`, the side the file calls white first.
+    name: String,
+    pieces: usize,
+    has_pawns: bool,
+    /// Some piece other than a king that is alone of its kind, so three pieces can lead.
+    has_unique_pieces: bool,
+    /// Pawns of the leading side, the one with fewer pawns if both have some, then the other's.
+    pawns: [usize; 2],
+    /// Both sides have the same pieces.
+    symmetric: bool,
+}
+
+const PIECE_CHARS: &str = "KQRBNP";
+
+impl Material {
+    fn parse(name: &str) -> Option<Self> {
+        let (white, black) = name.split_once('v')?;
+        for side in [white, black] {
+            let valid =
+                side.starts_with('K') && side[1..].chars().all(|c| PIECE_CHARS[1..].contains(c));
+            if !valid {
+                return None;
+            }
+        }
+        let pieces = white.len() + black.len();
+        if pieces > MAX_PIECES {
+            return None;
+        }
+        let count = |side: &str, c: char| side.chars().filter(|&p| p == c).count();
+        let has_unique_pieces = PIECE_CHARS[1..]
+            .chars()
+            .any(|c| count(white, c) == 1 || count(black, c) == 1);
+        let (white_pawns, black_pawns) = (count(white, 'P'), count(black, 'P'));
+        let white_leads = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+        Some(Self {
+            name: name.to_owned(),
+            pieces,
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces,
+            pawns: match white_leads {
+                true => [white_pawns, black_pawns],
+                false => [black_pawns, white_pawns],
+            },
+            symmetric: white == black,
+        })
+    }
+}
+
+/// The pieces of `side` the way table names write them.
+fn signature(board: &Board, side: Side) -> String {
+    let mut signature = String::new();
+    for (piece, c) in [
+        (Piece::King, 'K'),
+        (Piece::Queen, 'Q'),
+        (Piece::Rook, 'R'),
+        (Piece::Bishop, 'B'),
+        (Piece::Knight, 'N'),
+        (Piece::Pawn, 'P'),
+    ] {
+        let count = (board.pieces(piece) & board.color_pieces(side))
+            .into_iter()
+            .count();
+        for _ in 0..count {
+            signature.push(c);
+        }
+    }
+    signature
+}
+
+/// Pieces as the files code them: pawn to king are 1 to 6, plus 8 for black.
+fn piece_code(piece: Piece, side: Side) -> u8 {
+    let code = match piece {
+        Piece::Pawn => 1,
+        Piece::Knight => 2,
+        Piece::Bishop => 3,
+        Piece::Rook => 4,
+        Piece::Queen => 5,
+        Piece::King => 6,
+    };
+    code + 8 * (side == Side::Black) as u8
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Wdl,
+    Dtz,
+}
+
+/// Reads the little-endian numbers of a table's header, failing at the end of the file.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Option<&[u8]> {
+        let bytes = self.bytes.get(self.pos..self.pos.checked_add(n)?)?;
+        self.pos += n;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn align(&mut self, to: usize) {
+        self.pos = self.pos.next_multiple_of(to);
+    }
+}
+
+/// How one part of a table, for one side to move and one leading pawn file, is encoded and
+/// compressed. The offsets point into the table's bytes.
+#[derive(Debug, Clone, Default)]
+struct PairsData {
+    flags: u8,
+    /// The order the pieces are indexed in.
+    pieces: [u8; MAX_PIECES],
+    /// Pieces per group, ending with a zero.
+    group_len: [usize; MAX_PIECES + 1],
+    /// What an index step of each group is worth. The one after the last is the table size.
+    group_idx: [u64; MAX_PIECES + 1],
+    block_size: usize,
+    span: u64,
+    blocks: usize,
+    /// The value of every position if the table has just one.
+    min_sym_len: u8,
+    lowest_sym: usize,
+    /// The lowest code of each symbol length, left-aligned.
+    base64: Vec<u64>,
+    /// How many values each symbol expands to, minus one.
+    symlen: Vec<u8>,
+    btree: usize,
+    sparse_index: usize,
+    block_lengths: usize,
+    data: usize,
+    /// Where the DTZ values of each outcome start in the table's map.
+    map_idx: [usize; 4],
+}
+
+impl PairsData {
+    /// Splits the pieces into groups of alike pieces, the leading group first, and works out
+    /// the index range of each in the order the table stores them.
+    fn set_groups(&mut self, material: &Material, order: [u8; 2], pawn_file: usize) {
+        let mut first_len: i32 = match (material.has_pawns, material.has_unique_pieces) {
+            (true, _) => 0,
+            (false, true) => 3,
+            (false, false) => 2,
+        };
+        let mut n = 0;
+        self.group_len[0] = 1;
+        for i in 1..material.pieces {
+            first_len -= 1;
+            if first_len > 0 || self.pieces[i] == self.pieces[i - 1] {
+                self.group_len[n] += 1;
+            } else {
+                n += 1;
+                self.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        self.group_len[n] = 0;
+
+        let both_pawns = material.has_pawns && material.pawns[1] > 0;
+        let mut next = if both_pawns { 2 } else { 1 };
+        let mut free = 64 - self.group_len[0] - if both_pawns { self.group_len[1] } else { 0 };
+        let mut idx = 1;
+        let mut k = 0;
+        while next < n || k == order[0] as usize || k == order[1] as usize {
+            if k == order[0] as usize {
+                self.group_idx[0] = idx;
+                idx *= match (material.has_pawns, material.has_unique_pieces) {
+                    (true, _) => ENCODING.lead_pawns_size[self.group_len[0]][pawn_file],
+                    (false, true) => 31332,
+                    (false, false) => 462,
+                };
+            } else if k == order[1] as usize {
+                self.group_idx[1] = idx;
+                idx *= ENCODING.binomial[self.group_len[1]][48 - self.group_len[0]];
+            } else {
+                self.group_idx[next] = idx;
+                idx *= ENCODING.binomial[self.group_len[next]][free];
+                free -= self.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        self.group_idx[n] = idx;
+    }
+
+    /// Reads the compression parameters and the symbol tree.
+    fn set_sizes(&mut self, reader: &mut Reader) -> Option<()> {
+        self.flags = reader.u8()?;
+        if self.flags & SINGLE_VALUE != 0 {
+            self.min_sym_len = reader.u8()?;
+            return Some(());
+        }
+        let groups = self.group_len.iter().position(|&len| len == 0)?;
+        let size = self.group_idx[groups];
+        self.block_size = 1usize.checked_shl(reader.u8()? as u32)?;
+        self.span = 1u64.checked_shl(reader.u8()? as u32)?;
+        let sparse_entries = size.div_ceil(self.span) as usize;
+        let padding = reader.u8()? as usize;
+        self.blocks = reader.u32()? as usize;
+        let max_sym_len = reader.u8()?;
+        self.min_sym_len = reader.u8()?;
+        if max_sym_len < self.min_sym_len || self.min_sym_len == 0 {
+            return None;
+        }
+        self.lowest_sym = reader.pos;
+        let lengths = (max_sym_len - self.min_sym_len) as usize + 1;
+        let lowest = reader.take(2 * lengths)?;
+        let lowest = |i: usize| u16::from_le_bytes([lowest[2 * i], lowest[2 * i + 1]]) as u64;
+        // Longer codes have lower values, so the lowest code of each length can be worked out
+        // from the next longer one.
+        self.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            self.base64[i] = (self.base64[i + 1] + lowest(i)).wrapping_sub(lowest(i + 1)) / 2;
+        }
+        for (i, base) in self.base64.iter_mut().enumerate() {
+            let shift = 64 - i as u32 - self.min_sym_len as u32;
+            *base = base.checked_shl(shift).unwrap_or(0);
+        }
+
+        let symbols = reader.u16()? as usize;
+        self.btree = reader.pos;
+        let tree = reader.take(3 * symbols + (symbols & 1))?;
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for sym in 0..symbols {
+            if !visited[sym] {
+                self.symlen[sym] = set_symlen(tree, &mut self.symlen, &mut visited, sym);
+            }
+        }
+
+        // The sparse index and the block lengths come later, after those of every part.
+        self.sparse_index = sparse_entries;
+        self.block_lengths = self.blocks + padding;
+        Some(())
+    }
+}
+
+/// The left and right halves of a symbol. A symbol without a right half stands for a value,
+/// which is its left half.
+fn pair(tree: &[u8], sym: usize) -> (usize, usize) {
+    let lr = &tree[3 * sym..3 * sym + 3];
+    let left = ((lr[1] as usize & 0xf) << 8) | lr[0] as usize;
+    let right = ((lr[2] as usize) << 4) | (lr[1] as usize >> 4);
+    (left, right)
+}
+
+fn set_symlen(tree: &[u8], symlen: &mut [u8], visited: &mut [bool], sym: usize) -> u8 {
+    visited[sym] = true;
+    let (left, right) = pair(tree, sym);
+    if right == 0xfff || left >= symlen.len() || right >= symlen.len() {
+        return 0;
+    }
+    for half in [left, right] {
+        if !visited[half] {
+            symlen[half] = set_symlen(tree, symlen, visited, half);
+        }
+    }
+    symlen[left].wrapping_add(symlen[right]).wrapping_add(1)
+}
+
+/// A table file, mapped into memory. The tests build theirs in a `Vec`.
+struct Table<B = Mmap> {
+    bytes: B,
+    /// Indexed by side to move, then by leading pawn file.
+    parts: Vec<Vec<PairsData>>,
+    /// Where the DTZ value maps start.
+    map: usize,
+}
+
+fn le16(bytes: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([bytes[pos], bytes[pos + 1]])
+}
+
+impl<B: AsRef<[u8]>> Table<B> {
+    fn parse(bytes: B, kind: Kind, material: &Material) -> Option<Self> {
+        let magic = match kind {
+            Kind::Wdl => WDL_MAGIC,
+            Kind::Dtz => DTZ_MAGIC,
+        };
+        if bytes.as_ref().get(..4)? != magic {
+            return None;
+        }
+        let mut reader = Reader {
+            bytes: bytes.as_ref(),
+            pos: 4,
+        };
+        let flags = reader.u8()?;
+        if (flags & 2 != 0) != material.has_pawns {
+            return None;
+        }
+        let sides = match kind {
+            Kind::Wdl if !material.symmetric => 2,
+            _ => 1,
+        };
+        let files = if material.has_pawns { 4 } else { 1 };
+        let both_pawns = material.has_pawns && material.pawns[1] > 0;
+        let mut parts = vec![vec![PairsData::default(); files]; sides];
+
+        for file in 0..files {
+            let first = reader.u8()?;
+            let second = if both_pawns { reader.u8()? } else { 0xff };
+            let orders = [[first & 0xf, second & 0xf], [first >> 4, second >> 4]];
+            for k in 0..material.pieces {
+                let byte = reader.u8()?;
+                for (side, part) in parts.iter_mut().enumerate() {
+                    part[file].pieces[k] = if side == 0 { byte & 0xf } else { byte >> 4 };
+                }
+            }
+            for (side, part) in parts.iter_mut().enumerate() {
+                part[file].set_groups(material, orders[side], file);
+            }
+        }
+        reader.align(2);
+
+        for file in 0..files {
+            for part in parts.iter_mut() {
+                part[file].set_sizes(&mut reader)?;
+            }
+        }
+
+        let map = reader.pos;
+        if kind == Kind::Dtz {
+            for part in parts[0].iter_mut() {
+                if part.flags & MAPPED == 0 {
+                    continue;
+                }
+                if part.flags & WIDE != 0 {
+                    reader.align(2);
+                    for i in 0..4 {
+                        part.map_idx[i] = (reader.pos - map) / 2 + 1;
+                        let len = reader.u16()? as usize;
+                        reader.take(2 * len)?;
+                    }
+                } else {
+                    for i in 0..4 {
+                        part.map_idx[i] = reader.pos - map + 1;
+                        let len = reader.u8()? as usize;
+                        reader.take(len)?;
+                    }
+                }
+            }
+            reader.align(2);
+        }
+
+        // `set_sizes` left the counts in place of the offsets.
+        for file in 0..files {
+            for part in parts.iter_mut() {
+                let entries = part[file].sparse_index;
+                part[file].sparse_index = reader.pos;
+                reader.take(6 * entries)?;
+            }
+        }
+        for file in 0..files {
+            for part in parts.iter_mut() {
+                let lengths = part[file].block_lengths;
+                part[file].block_lengths = reader.pos;
+                reader.take(2 * lengths)?;
+            }
+        }
+        for file in 0..files {
+            for part in parts.iter_mut() {
+                let part = &mut part[file];
+                // Single values have no data, which may start past the end of the file.
+                if part.flags & SINGLE_VALUE != 0 {
+                    continue;
+                }
+                reader.align(64);
+                part.data = reader.pos;
+                reader.take(part.blocks.checked_mul(part.block_size)?)?;
+            }
+        }
+        Some(Self { bytes, parts, map })
+    }
+
+    fn part(&self, stm: usize, pawn_file: usize) -> &PairsData {
+        let side = &self.parts[stm % self.parts.len()];
+        &side[pawn_file.min(side.len() - 1)]
+    }
+
+    /// The value stored at `idx`.
+    fn decompress(&self, d: &PairsData, idx: u64) -> i32 {
+        if d.flags & SINGLE_VALUE != 0 {
+            return d.min_sym_len as i32;
+        }
+        let bytes = self.bytes.as_ref();
+        // The sparse index says where in which block the value at `k * span + span / 2` is,
+        // and the block lengths lead from there to `idx`.
+        let k = (idx / d.span) as usize;
+        let entry = d.sparse_index + 6 * k;
+        let mut block = u32::from_le_bytes(bytes[entry..entry + 4].try_into().unwrap()) as usize;
+        let mut offset = le16(bytes, entry + 4) as i64;
+        offset += (idx % d.span) as i64 - (d.span / 2) as i64;
+        let block_length = |block: usize| le16(bytes, d.block_lengths + 2 * block) as i64;
+        while offset < 0 {
+            block -= 1;
+            offset += block_length(block) + 1;
+        }
+        while offset > block_length(block) {
+            offset -= block_length(block) + 1;
+            block += 1;
+        }
+
+        // Walk the canonical Huffman codes of the block until the symbol that covers `offset`.
+        let mut ptr = d.data + block * d.block_size;
+        let mut buf = u64::from_be_bytes(bytes[ptr..ptr + 8].try_into().unwrap());
+        ptr += 8;
+        let mut buf_size = 64;
+        let min_len = d.min_sym_len as u32;
+        let tree = &bytes[d.btree..];
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while len + 1 < d.base64.len() && buf < d.base64[len] {
+                len += 1;
+            }
+            sym = ((buf - d.base64[len]) >> (64 - len as u32 - min_len)) as usize;
+            sym += le16(bytes, d.lowest_sym + 2 * len) as usize;
+            if offset < d.symlen[sym] as i64 + 1 {
+                break;
+            }
+            offset -= d.symlen[sym] as i64 + 1;
+            let len = len as u32 + min_len;
+            buf = buf.checked_shl(len).unwrap_or(0);
+            buf_size -= len;
+            if buf_size <= 32 {
+                buf_size += 32;
+                let next = u32::from_be_bytes(bytes[ptr..ptr + 4].try_into().unwrap());
+                buf |= (next as u64) << (64 - buf_size);
+                ptr += 4;
+            }
+        }
+
+        // The symbol stands for a run of values, made of the runs of its halves.
+        while d.symlen[sym] != 0 {
+            let (left, right) = pair(tree, sym);
+            if offset < d.symlen[left] as i64 + 1 {
+                sym = left;
+            } else {
+                offset -= d.symlen[left] as i64 + 1;
+                sym = right;
+            }
+        }
+        pair(tree, sym).0 as i32
+    }
+
+    /// Turns a stored DTZ value into plies.
+    fn map_dtz(&self, d: &PairsData, mut value: i32, wdl: Wdl) -> i32 {
+        if d.flags & MAPPED != 0 {
+            let map = d.map_idx[[1, 3, 0, 2, 0][(wdl as i32 + 2) as usize]];
+            value = if d.flags & WIDE != 0 {
+                le16(self.bytes.as_ref(), self.map + 2 * (map + value as usize)) as i32
+            } else {
+                self.bytes.as_ref()[self.map + map + value as usize] as i32
+            };
+        }
+        let in_moves = match wdl {
+            Wdl::Win => d.flags & WIN_PLIES == 0,
+            Wdl::Loss => d.flags & LOSS_PLIES == 0,
+            Wdl::CursedWin | Wdl::BlessedLoss => true,
+            Wdl::Draw => false,
+        };
+        if in_moves {
+            value *= 2;
+        }
+        value + 1
+    }
+}
+
+/// The index of a position in a table part. The pieces are in `squares`, already flipped so
+/// that the table's white is to move or stronger, and listed like the part's `pieces` after
+/// the `lead_pawns` pawns of the leading side.
+fn encode(d: &PairsData, material: &Material, squares: &mut [usize], lead_pawns: usize) -> u64 {
+    let e = &ENCODING;
+    let size = squares.len();
+    if file(squares[0]) > 3 {
+        for sq in squares.iter_mut() {
+            *sq ^= 7;
+        }
+    }
+
+    let mut idx;
+    if material.has_pawns {
+        idx = e.lead_pawn_idx[lead_pawns][squares[0]];
+        squares[1..lead_pawns].sort_by_key(|&sq| e.map_pawns[sq]);
+        for (i, &sq) in squares[..lead_pawns].iter().enumerate().skip(1) {
+            idx += e.binomial[i][e.map_pawns[sq] as usize];
+        }
+    } else {
+        if rank(squares[0]) > 3 {
+            for sq in squares.iter_mut() {
+                *sq ^= 56;
+            }
+        }
+        // Mirror along the diagonal so the first piece of the leading group that is off it
+        // ends up below it.
+        for i in 0..d.group_len[0] {
+            let off = off_diagonal(squares[i]);
+            if off == 0 {
+                continue;
+            }
+            if off > 0 {
+                for sq in squares[i..].iter_mut() {
+                    *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                }
+            }
+            break;
+        }
+
+        idx = if material.has_unique_pieces {
+            let (s0, s1, s2) = (squares[0], squares[1], squares[2]);
+            let adjust1 = (s1 > s0) as u64;
+            let adjust2 = (s2 > s0) as u64 + (s2 > s1) as u64;
+            let (r0, r1, r2) = (rank(s0) as u64, rank(s1) as u64, rank(s2) as u64);
+            if off_diagonal(s0) != 0 {
+                (e.map_a1d1d4[s0] * 63 + (s1 as u64 - adjust1)) * 62 + s2 as u64 - adjust2
+            } else if off_diagonal(s1) != 0 {
+                (6 * 63 + r0 * 28 + e.map_b1h1h7[s1]) * 62 + s2 as u64 - adjust2
+            } else if off_diagonal(s2) != 0 {
+                6 * 63 * 62 + 4 * 28 * 62 + r0 * 7 * 28 + (r1 - adjust1) * 28 + e.map_b1h1h7[s2]
+            } else {
+                6 * 63 * 62
+                    + 4 * 28 * 62
+                    + 4 * 7 * 28
+                    + r0 * 7 * 6
+                    + (r1 - adjust1) * 6
+                    + (r2 - adjust2)
+            }
+        } else {
+            e.map_kk[e.map_a1d1d4[squares[0]] as usize][squares[1]]
+        };
+    }
+
+    // The other groups are sets of alike pieces on the squares the groups before left free.
+    idx *= d.group_idx[0];
+    let mut start = d.group_len[0];
+    let mut remaining_pawns = material.has_pawns && material.pawns[1] > 0;
+    let mut next = 1;
+    while d.group_len[next] != 0 {
+        let end = (start + d.group_len[next]).min(size);
+        squares[start..end].sort();
+        let mut n = 0;
+        for i in start..end {
+            let sq = squares[i];
+            let below = squares[..start].iter().filter(|&&s| s < sq).count();
+            let free = sq - below - if remaining_pawns { 8 } else { 0 };
+            n += e.binomial[i - start + 1][free];
+        }
+        remaining_pawns = false;
+        idx += n * d.group_idx[next];
+        start = end;
+        next += 1;
+    }
+    idx
+}
+
+/// A table file found on disk, mapped the first time it is probed. Only the parts of it that
+/// probes touch are ever read.
+struct TableFile {
+    path: PathBuf,
+    table: OnceLock<Option<Table>>,
+}
+
+impl TableFile {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            table: OnceLock::new(),
+        }
+    }
+
+    fn get(&self, kind: Kind, material: &Material) -> Option<&Table> {
+        self.table
+            .get_or_init(|| {
+                let file = fs::File::open(&self.path).ok()?;
+                // The files are not expected to change while they are in use.
+                let bytes = unsafe { Mmap::map(&file) }.ok()?;
+                Table::parse(bytes, kind, material)
+            })
+            .as_ref()
+    }
+}
+
+struct Entry {
+    material: Material,
+    wdl: Option<TableFile>,
+    dtz: Option<TableFile>,
+}
+
+enum Probed {
+    Value(i32),
+    /// DTZ tables only store one side to move, and it is the other one.
+    ChangeStm,
+}
+
+/// Syzygy tablebases. Tables are looked up by the pieces on the board, and each file is mapped
+/// into memory the first time a position needs it.
+#[derive(Default)]
+pub struct Tablebases {
+    /// By material from both sides' point of view, e.g. `KRvK` and `KvKR`.
+    tables: HashMap<String, Arc<Entry>>,
+    count: usize,
+    max_pieces: usize,
+}
+
+impl Tablebases {
+    /// Finds the tables in the directories of `path`, separated like the `PATH` variable.
+    /// Directories that can't be read are skipped.
+    pub fn new(path: &str) -> Self {
+        let separator = if cfg!(windows) { ';' } else { ':' };
+        let mut found: HashMap<String, (Option<PathBuf>, Option<PathBuf>)> = HashMap::new();
+        for dir in path.split(separator).filter(|dir| !dir.is_empty()) {
+            let Ok(files) = fs::read_dir(Path::new(dir)) else {
+                continue;
+            };
+            for file in files.flatten() {
+                let path = file.path();
+                let (Some(name), Some(extension)) = (path.file_stem(), path.extension()) else {
+                    continue;
+                };
+                let name = name.to_string_lossy().into_owned();
+                let files = found.entry(name).or_default();
+                match extension.to_str() {
+                    Some("rtbw") => files.0 = files.0.take().or(Some(path)),
+                    Some("rtbz") => files.1 = files.1.take().or(Some(path)),
+                    _ => {}
+                }
+            }
+        }
+
+        let mut tablebases = Self::default();
+        for (name, (wdl, dtz)) in found {
+            let Some(material) = Material::parse(&name) else {
+                continue;
+            };
+            if wdl.is_some() {
+                tablebases.count += 1;
+                tablebases.max_pieces = tablebases.max_pieces.max(material.pieces);
+            }
+            let (white, black) = name.split_once('v').unwrap();
+            let swapped = format!("{black}v{white}");
+            let entry = Arc::new(Entry {
+                material,
+                wdl: wdl.map(TableFile::new),
+                dtz: dtz.map(TableFile::new),
+            });
+            tablebases.tables.insert(swapped, entry.clone());
+            tablebases.tables.insert(name, entry);
+        }
+        tablebases
+    }
+
+    /// The number of WDL tables found.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// The most pieces any WDL table has, 0 without tables.
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    /// Whether the tables can say anything about `board`. They know nothing of castling.
+    pub fn covers(&self, board: &Board) -> bool {
+        let pieces = (0..64)
+            .filter(|&sq| board.piece(Square(sq)).is_some())
+            .count();
+        pieces <= self.max_pieces
+            && [Side::White, Side::Black].iter().all(|&side| {
+                let rights = board.castle_rights(side);
+                !rights.kingside() && !rights.queenside()
+            })
+    }
+
+    fn probe_table(&self, board: &Board, kind: Kind, wdl: Wdl) -> Option<Probed> {
+        let mut squares = vec![];
+        let mut pieces = vec![];
+        for sq in 0..64 {
+            if let Some((piece, side)) = board.piece(Square(sq)) {
+                squares.push(sq as usize);
+                pieces.push(piece_code(piece, side));
+            }
+        }
+        if squares.len() == 2 {
+            return Some(Probed::Value(0));
+        }
+        let key = format!(
+            "{}v{}",
+            signature(board, Side::White),
+            signature(board, Side::Black)
+        );
+        let entry = self.tables.get(&key)?;
+        let material = &entry.material;
+        let table = match kind {
+            Kind::Wdl => entry.wdl.as_ref(),
+            Kind::Dtz => entry.dtz.as_ref(),
+        }?
+        .get(kind, material)?;
+
+        // Tables are made for the side their name puts first, and symmetric ones only for
+        // white to move. Otherwise the colours are swapped and the board turned around.
+        let black_to_move = board.to_move() == Side::Black;
+        let flip = key != material.name || (material.symmetric && black_to_move);
+        let stm = (flip ^ black_to_move) as usize;
+        for (sq, piece) in squares.iter_mut().zip(pieces.iter_mut()) {
+            if flip {
+                *sq ^= 56;
+                *piece ^= 8;
+            }
+        }
+
+        // With pawns, the tables are split by the file of the leading pawn, the one of the
+        // leading side's pawns furthest to the edge and then lowest.
+        let mut lead_pawns = 0;
+        let mut pawn_file = 0;
+        if material.has_pawns {
+            let lead = table.part(0, 0).pieces[0];
+            let mut order: Vec<usize> = (0..squares.len()).collect();
+            order.sort_by_key(|&i| pieces[i] != lead);
+            squares = order.iter().map(|&i| squares[i]).collect();
+            pieces = order.iter().map(|&i| pieces[i]).collect();
+            lead_pawns = pieces.iter().filter(|&&piece| piece == lead).count();
+            let map = &ENCODING.map_pawns;
+            let mut first = 0;
+            for i in 1..lead_pawns {
+                if map[squares[i]] > map[squares[first]] {
+                    first = i;
+                }
+            }
+            squares.swap(0, first);
+            pawn_file = file(squares[0]).min(7 - file(squares[0]));
+        }
+
+        if kind == Kind::Dtz {
+            let flags = table.part(0, pawn_file).flags;
+            let both_sides = material.symmetric && !material.has_pawns;
+            if (flags & STM) as usize != stm && !both_sides {
+                return Some(Probed::ChangeStm);
+            }
+        }
+
+        let d = table.part(stm, pawn_file);
+        for i in lead_pawns..squares.len().saturating_sub(1) {
+            if let Some(j) = (i + 1..squares.len()).find(|&j| pieces[j] == d.pieces[i]) {
+                squares.swap(i, j);
+                pieces.swap(i, j);
+            }
+        }
+        let idx = encode(d, material, &mut squares, lead_pawns);
+        let value = table.decompress(d, idx);
+        Some(Probed::Value(match kind {
+            Kind::Wdl => value - 2,
+            Kind::Dtz => table.map_dtz(d, value, wdl),
+        }))
+    }
+
+    fn probe_wdl_table(&self, board: &Board) -> Option<Wdl> {
+        match self.probe_table(board, Kind::Wdl, Wdl::Draw)? {
+            Probed::Value(value) => Some(Wdl::from_value(value)),
+            Probed::ChangeStm => None,
+        }
+    }
+
+    /// The outcome of `board`, and whether the best move resets the fifty-move counter. The
+    /// tables may store anything for positions where a capture wins, and nothing about en
+    /// passant, so captures, and pawn moves if `zeroing`, are tried first.
+    fn search(&self, board: &Board, zeroing: bool) -> Option<(Wdl, bool)> {
+        let moves: Vec<Move> = board.legal_moves().collect();
+        let mut best = Wdl::Loss;
+        let mut searched = 0;
+        for mv in &moves {
+            let pawn = board.check_piece(mv.start()) == Some(Piece::Pawn);
+            if !is_capture(board, mv) && !(zeroing && pawn) {
+                continue;
+            }
+            searched += 1;
+            let child = board.clone().apply_move(mv).unwrap();
+            let value = -self.search(&child, false)?.0;
+            if value > best {
+                best = value;
+                if value >= Wdl::Win {
+                    return Some((value, true));
+                }
+            }
+        }
+        let all_searched = searched > 0 && searched == moves.len();
+        let value = match all_searched {
+            true => best,
+            false => self.probe_wdl_table(board)?,
+        };
+        match best >= value {
+            true => Some((best, best > Wdl::Draw || all_searched)),
+            false => Some((value, false)),
+        }
+    }
+
+    /// Win, draw or loss for the side to move, not counting the fifty-move counter.
+    pub fn probe_wdl(&self, board: &Board) -> Option<Wdl> {
+        if !self.covers(board) {
+            return None;
+        }
+        self.search(board, false).map(|(wdl, _)| wdl)
+    }
+
+    /// The distance to the next capture or pawn move in plies, with the sign of the outcome:
+    /// positive when the side to move wins, negative when it loses and 0 for a draw. Cursed
+    /// wins and blessed losses are 100 plies further.
+    pub fn probe_dtz(&self, board: &Board) -> Option<i32> {
+        if !self.covers(board) {
+            return None;
+        }
+        self.dtz(board)
+    }
+
+    fn dtz(&self, board: &Board) -> Option<i32> {
+        let (wdl, zeroing) = self.search(board, true)?;
+        if wdl == Wdl::Draw {
+            return Some(0);
+        }
+        if zeroing {
+            return Some(dtz_before_zeroing(wdl));
+        }
+        match self.probe_table(board, Kind::Dtz, wdl)? {
+            Probed::Value(dtz) => {
+                let cursed = matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss);
+                Some((dtz + 100 * cursed as i32) * wdl.signum())
+            }
+            Probed::ChangeStm => {
+                // Only the other side's DTZ is stored, so look one move ahead.
+                let mut min_dtz = i32::MAX;
+                for mv in board.legal_moves() {
+                    let zeroing = is_capture(board, &mv)
+                        || board.check_piece(mv.start()) == Some(Piece::Pawn);
+                    let child = board.clone().apply_move(&mv).unwrap();
+                    let mut dtz = match zeroing {
+                        true => -dtz_before_zeroing(self.search(&child, false)?.0),
+                        false => -self.dtz(&child)?,
+                    };
+                    if dtz == 1 && is_mate(&child) {
+                        min_dtz = 1;
+                    }
+                    if !zeroing {
+                        dtz += dtz.signum();
+                    }
+                    if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                        min_dtz = dtz;
+                    }
+                }
+                Some(if min_dtz == i32::MAX { -1 } else { min_dtz })
+            }
+        }
+    }
+
+    /// Ranks the legal moves of `board` by DTZ, counting the fifty-move counter: certain wins
+    /// rank [`CERTAIN_WIN`], draws 0 and certain losses the negative, with wins and losses the
+    /// counter may spoil in between. `repeated` says whether the position was seen before since
+    /// the last capture or pawn move, which makes even quick wins uncertain.
+    pub fn rank_root_moves(&self, board: &Board, repeated: bool) -> Option<Vec<(Move, i32)>> {
+        if !self.covers(board) {
+            return None;
+        }
+        let clock = board.halfmove_clock() as i32;
+        let mut ranked = vec![];
+        for mv in board.legal_moves() {
+            let child = board.clone().apply_move(&mv).unwrap();
+            let mut dtz = if child.halfmove_clock() == 0 {
+                dtz_before_zeroing(-self.search(&child, false)?.0)
+            } else if child.is_fifty_move_draw() {
+                0
+            } else {
+                let dtz = -self.dtz(&child)?;
+                dtz + dtz.signum()
+            };
+            if dtz == 2 && is_mate(&child) {
+                dtz = 1;
+            }
+            let rank = match dtz {
+                0 => 0,
+                dtz if dtz > 0 => match dtz + clock <= 99 && !repeated {
+                    true => CERTAIN_WIN,
+                    false => CERTAIN_WIN / 2 - (dtz + clock),
+                },
+                dtz => match -dtz * 2 + clock < 100 {
+                    true => -CERTAIN_WIN,
+                    false => -CERTAIN_WIN / 2 + (-dtz + clock),
+                },
+            };
+            ranked.push((mv, rank));
+        }
+        Some(ranked)
+    }
+
+    /// The moves that keep the best outcome the tables promise, to search among.
+    pub fn root_moves(&self, board: &Board, repeated: bool) -> Option<Vec<Move>> {
+        let ranked = self.rank_root_moves(board, repeated)?;
+        let best = ranked.iter().map(|&(_, rank)| rank).max()?;
+        Some(
+            ranked
+                .into_iter()
+                .filter(|&(_, rank)| rank == best)
+                .map(|(mv, _)| mv)
+                .collect(),
+        )
+    }
+}
+
+fn is_capture(board: &Board, mv: &Move) -> bool {
+    board.piece(mv.dest()).is_some()
+        || (board.check_piece(mv.start()) == Some(Piece::Pawn)
+            && mv.start().file() != mv.dest().file())
+}
+
+fn is_mate(board: &Board) -> bool {
+    board.is_in_check(board.to_move()) && board.legal_moves().next().is_none()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, fs, path::PathBuf, sync::Arc};
+
+    use crate::{ab::SearchSettings, chess::board::Board};
+
+    use super::{
+        encode, Kind, Material, Table, Tablebases, Wdl, CERTAIN_WIN, DTZ_MAGIC, ENCODING, TB_WIN,
+        WDL_MAGIC,
+    };
+
+    /// Codes of the pieces of `KQvK`, as the files store them: white king, queen, black king.
+    const KQVK_PIECES: [u8; 3] = [6, 5, 14];
+    const LEAF: u16 = 0xfff;
+    const KQVK_SIZE: usize = 31332;
+
+    fn values(tree: &[(u16, u16)], sym: usize) -> Vec<u8> {
+        match tree[sym] {
+            (value, LEAF) => vec![value as u8],
+            (left, right) => [values(tree, left as usize), values(tree, right as usize)].concat(),
+        }
+    }
+
+    /// A `KQvK.rtbw` whose positions with white to move are compressed into `blocks` of 128
+    /// bytes, made of 4-bit codes that are the symbols of `tree` in order. With black to move
+    /// every position is a loss.
+    fn kqvk_wdl(tree: &[(u16, u16)], blocks: &[&[usize]]) -> Vec<u8> {
+        let mut bytes = WDL_MAGIC.to_vec();
+        bytes.push(1);
+        bytes.push(0);
+        bytes.extend(KQVK_PIECES.map(|piece| piece | piece << 4));
+        bytes.push(0);
+        bytes.extend([0, 7, 16, 0]);
+        bytes.extend((blocks.len() as u32).to_le_bytes());
+        bytes.extend([4, 4, 0, 0]);
+        bytes.extend((tree.len() as u16).to_le_bytes());
+        for &(left, right) in tree {
+            bytes.extend([
+                left as u8,
+                (left >> 8) as u8 | (right << 4) as u8,
+                (right >> 4) as u8,
+            ]);
+        }
+        bytes.extend(vec![0; tree.len() & 1]);
+        bytes.extend([0x80, 0]);
+        // One sparse entry, at the middle of the span of 2^16 positions.
+        bytes.extend([0, 0, 0, 0, 0, 0x80]);
+        for block in blocks {
+            let len: usize = block.iter().map(|&sym| values(tree, sym).len()).sum();
+            bytes.extend((len as u16 - 1).to_le_bytes());
+        }
+        bytes.resize(bytes.len().next_multiple_of(64), 0);
+        for block in blocks {
+            let mut data = [0; 128];
+            for (i, &sym) in block.iter().enumerate() {
+                data[i / 2] |= (sym as u8) << (4 - 4 * (i % 2));
+            }
+            bytes.extend(data);
+        }
+        bytes
+    }
+
+    /// Symbol `i` stands for 2^i wins. Symbols expand to at most 256 values.
+    fn all_wins() -> Vec<(u16, u16)> {
+        let mut tree = vec![(4, LEAF)];
+        tree.extend((0..8).map(|i| (i, i)));
+        tree
+    }
+
+    /// 31332 wins: 122 * 256 + 64 + 32 + 4.
+    fn all_win_codes() -> Vec<usize> {
+        let mut codes = vec![8; 122];
+        codes.extend([6, 5, 2]);
+        codes
+    }
+
+    /// A `KQvK.rtbz` where white to move always wins in 3 moves.
+    fn kqvk_dtz() -> Vec<u8> {
+        let mut bytes = DTZ_MAGIC.to_vec();
+        bytes.extend([1, 0]);
+        bytes.extend(KQVK_PIECES);
+        bytes.extend([0, 0x80, 3]);
+        bytes
+    }
+
+    fn tablebases(name: &str) -> (Tablebases, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("syzygy-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let tree = all_wins();
+        let wdl = kqvk_wdl(&tree, &[&all_win_codes()]);
+        fs::write(dir.join("KQvK.rtbw"), wdl).unwrap();
+        fs::write(dir.join("KQvK.rtbz"), kqvk_dtz()).unwrap();
+        fs::write(dir.join("KQvK.txt"), "").unwrap();
+        let tablebases = Tablebases::new(&format!("/no/such/dir:{}", dir.to_str().unwrap()));
+        (tablebases, dir)
+    }
+
+    fn board(fen: &str) -> Board {
+        Board::from_fen(fen).unwrap()
+    }
+
+    #[test]
+    fn test_encoding_tables() {
+        let e = &ENCODING;
+        assert_eq!(e.binomial[2][48], 1128);
+        assert_eq!(e.binomial[6][63], 67945521);
+        let kk: HashSet<u64> = e.map_kk.iter().flatten().copied().collect();
+        assert_eq!(kk.len(), 462);
+        assert_eq!(kk.iter().max(), Some(&461));
+        // The leading pawn squares of a file, times the ways to place the others below.
+        assert_eq!(e.lead_pawns_size[1], [6, 6, 6, 6]);
+        assert_eq!(e.map_pawns[8], 47);
+        assert_eq!(e.map_pawns[15], 46);
+        assert_eq!(e.map_pawns[52], 0);
+    }
+
+    #[test]
+    fn test_material() {
+        let krpvkr = Material::parse("KRPvKR").unwrap();
+        assert_eq!(krpvkr.pieces, 5);
+        assert!(krpvkr.has_pawns && krpvkr.has_unique_pieces && !krpvkr.symmetric);
+        assert_eq!(krpvkr.pawns, [1, 0]);
+        let kpvkpp = Material::parse("KPvKPP").unwrap();
+        assert_eq!(kpvkpp.pawns, [1, 2]);
+        assert_eq!(Material::parse("KPPvKP").unwrap().pawns, [1, 2]);
+        let krrvkrr = Material::parse("KRRvKRR").unwrap();
+        assert!(krrvkrr.symmetric && !krrvkrr.has_unique_pieces);
+        assert_eq!(Material::parse("KQvX"), None);
+        assert_eq!(Material::parse("KQQQQvKQQ"), None);
+    }
+
+    #[test]
+    fn test_decompress() {
+        let tree = [(4, LEAF), (2, LEAF), (0, LEAF), (0, 1), (3, 2)];
+        let blocks: [&[usize]; 3] = [&[3, 4, 0, 1], &[2, 2, 4], &[1; 20]];
+        let bytes = kqvk_wdl(&tree, &blocks);
+        let material = Material::parse("KQvK").unwrap();
+        let table = Table::parse(bytes, Kind::Wdl, &material).unwrap();
+        let expected: Vec<u8> = blocks
+            .iter()
+            .flat_map(|block| block.iter().flat_map(|&sym| values(&tree, sym)))
+            .collect();
+        assert_eq!(expected.len(), 32);
+        for (idx, &value) in expected.iter().enumerate() {
+            assert_eq!(table.decompress(table.part(0, 0), idx as u64), value as i32);
+        }
+        assert_eq!(table.decompress(table.part(1, 0), 12345), 0);
+        // Cut short, the blocks are missing.
+        let mut bytes = kqvk_wdl(&tree, &blocks);
+        bytes.truncate(bytes.len() - 1);
+        assert!(Table::parse(bytes, Kind::Wdl, &material).is_none());
+    }
+
+    #[test]
+    fn test_encode() {
+        let material = Material::parse("KQvK").unwrap();
+        let bytes = kqvk_wdl(&all_wins(), &[&all_win_codes()]);
+        let table = Table::parse(bytes, Kind::Wdl, &material).unwrap();
+        let d = table.part(0, 0);
+        assert_eq!(d.group_idx[1], KQVK_SIZE as u64);
+
+        // Positions that are the same but for symmetry share an index, and no other does.
+        let mut seen = vec![None; KQVK_SIZE];
+        let mut positions = 0;
+        for wk in 0..64 {
+            for wq in (0..64).filter(|&sq| sq != wk) {
+                for bk in (0..64).filter(|&sq| sq != wk && sq != wq) {
+                    let idx = encode(d, &material, &mut [wk, wq, bk], 0) as usize;
+                    let canonical = [0, 7, 56, 63].map(|flip| {
+                        let squares = [wk ^ flip, wq ^ flip, bk ^ flip];
+                        let mirrored = squares.map(|sq| ((sq >> 3) | (sq << 3)) & 63);
+                        squares.min(mirrored)
+                    });
+                    let canonical = canonical.iter().min().copied();
+                    match seen[idx] {
+                        None => seen[idx] = canonical,
+                        Some(other) => assert_eq!(Some(other), canonical),
+                    }
+                    positions += 1;
+                }
+            }
+        }
+        assert_eq!(positions, 64 * 63 * 62);
+        assert!(seen.iter().filter(|s| s.is_some()).count() > 31000);
+    }
+
+    #[test]
+    fn test_probe() {
+        let (tb, dir) = tablebases("probe");
+        assert_eq!((tb.count(), tb.max_pieces()), (1, 3));
+        let win = board("8/8/4k3/8/3Q4/8/8/K7 w - - 0 1");
+        assert_eq!(tb.probe_wdl(&win), Some(Wdl::Win));
+        assert_eq!(tb.probe_dtz(&win), Some(7));
+        let loss = board("8/8/4k3/8/3Q4/8/8/K7 b - - 0 1");
+        assert_eq!(tb.probe_wdl(&loss), Some(Wdl::Loss));
+        assert_eq!(tb.probe_dtz(&loss), Some(-8));
+        // The colours swapped.
+        let flipped = board("k7/8/8/3q4/8/4K3/8/8 b - - 0 1");
+        assert_eq!(tb.probe_wdl(&flipped), Some(Wdl::Win));
+        // Black takes the queen.
+        let hanging = board("8/8/4k3/3Q4/8/8/8/K7 b - - 0 1");
+        assert_eq!(tb.probe_wdl(&hanging), Some(Wdl::Draw));
+        assert_eq!(tb.probe_dtz(&hanging), Some(0));
+        assert_eq!(
+            tb.probe_wdl(&board("8/8/4k3/8/8/8/8/K7 w - - 0 1")),
+            Some(Wdl::Draw)
+        );
+
+        // No table, or too many pieces.
+        assert_eq!(tb.probe_wdl(&board("8/8/4k3/8/3R4/8/8/K7 w - - 0 1")), None);
+        assert_eq!(
+            tb.probe_wdl(&board("8/8/4k3/8/3Q4/8/P7/K7 w - - 0 1")),
+            None
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_root_moves() {
+        let (tb, dir) = tablebases("root");
+        let board = board("8/8/4k3/8/3Q4/8/8/K7 w - - 0 1");
+        let ranked = tb.rank_root_moves(&board, false).unwrap();
+        assert_eq!(ranked.len(), board.legal_moves().count());
+        let hanging = ["Qd5+", "Qe5+", "Qd6+", "Qd7+", "Qf6+"];
+        for (mv, rank) in &ranked {
+            let expected = match hanging.contains(&board.san(mv).as_str()) {
+                true => 0,
+                false => CERTAIN_WIN,
+            };
+            assert_eq!(*rank, expected, "{}", board.san(mv));
+        }
+        let moves = tb.root_moves(&board, false).unwrap();
+        assert_eq!(moves.len(), ranked.len() - hanging.len());
+
+        // Near the end of the fifty moves, the win is no longer certain.
+        let late = Board::from_fen("8/8/4k3/8/3Q4/8/8/K7 w - - 95 80").unwrap();
+        let ranked = tb.rank_root_moves(&late, false).unwrap();
+        assert!(ranked.iter().all(|&(_, rank)| rank < CERTAIN_WIN));
+        assert!(tb.rank_root_moves(&late, true).is_some());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_search() {
+        let (tb, dir) = tablebases("search");
+        // Taking the knight wins, which the search can't see at this depth.
+        let board = board("8/1n6/4k3/8/8/8/8/K6Q w - - 0 1");
+        let settings = SearchSettings {
+            tablebases: Some(Arc::new(tb)),
+            ..SearchSettings::search(2)
+        };
+        let res = board.alphabeta(&settings);
+        assert_eq!(res.value, TB_WIN - 1.0);
+        assert_eq!(board.san(&res.data.last().unwrap().mv), "Qxb7");
+
+        // The same with the colours swapped. Values are from white's point of view.
+        let board = board.mirrored();
+        let res = board.alphabeta(&settings);
+        assert_eq!(res.value, -TB_WIN + 1.0);
+        assert_eq!(board.san(&res.data.last().unwrap().mv), "Qxb2");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,283 @@
+use std::time::{Duration, Instant};
+
+use vampirc_uci::{UciMove, UciTimeControl};
+
+use super::side::Side;
+
+/// Number of moves we assume are left in a sudden-death game.
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+/// Never plan on more than this fraction of the remaining clock for a single move.
+const MAX_CLOCK_FRACTION: f32 = 0.6;
+/// How far past the planned time the search may run before it is aborted.
+const HARD_LIMIT_FACTOR: u32 = 4;
+/// A score drop (in pawns) between iterations that is worth spending extra time on.
+const SCORE_DROP: f32 = 0.3;
+
+const MIN_STABILITY: f32 = 0.5;
+const MAX_STABILITY: f32 = 2.5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeLimits {
+    /// Don't start another iteration once this much time has passed.
+    pub soft: Duration,
+    /// Abort the search outright at this point.
+    pub hard: Duration,
+}
+
+impl TimeLimits {
+    pub fn infinite() -> Self {
+        Self {
+            soft: Duration::from_secs(100000000),
+            hard: Duration::from_secs(100000000),
+        }
+    }
+
+    pub fn fixed(time: Duration) -> Self {
+        Self {
+            soft: time,
+            hard: time,
+        }
+    }
+
+    /// Budget a move from the clock. The hard limit always leaves `overhead` plus a share of
+    /// the clock in reserve, so short sudden-death games don't lose on time.
+    pub fn from_clock(
+        time: Duration,
+        increment: Duration,
+        moves_to_go: Option<u8>,
+        overhead: Duration,
+    ) -> Self {
+        let usable = time.saturating_sub(overhead);
+        let moves_to_go = moves_to_go
+            .map(|x| x as u32)
+            .unwrap_or(DEFAULT_MOVES_TO_GO)
+            .clamp(1, DEFAULT_MOVES_TO_GO * 2);
+        let max = usable.mul_f32(MAX_CLOCK_FRACTION);
+
+        // The increment comes back after the move, but only once the move is made in time.
+        let soft = (usable / moves_to_go + increment.mul_f32(0.75)).min(max);
+        let hard = (soft * HARD_LIMIT_FACTOR).min(max);
+        Self {
+            soft: soft.min(hard),
+            hard,
+        }
+    }
+
+    pub fn new(time_control: Option<&UciTimeControl>, our_side: Side, overhead: Duration) -> Self {
+        let std = |x: &vampirc_uci::Duration| x.to_std().unwrap_or_default();
+        match time_control {
+            Some(UciTimeControl::Ponder) | Some(UciTimeControl::Infinite) => Self::infinite(),
+            Some(UciTimeControl::TimeLeft {
+                white_time,
+                black_time,
+                white_increment,
+                black_increment,
+                moves_to_go,
+            }) => {
+                let (time, inc) = match our_side {
+                    Side::White => (white_time, white_increment),
+                    Side::Black => (black_time, black_increment),
+                };
+                match time {
+                    Some(time) => Self::from_clock(
+                        std(time),
+                        inc.as_ref().map(std).unwrap_or_default(),
+                        *moves_to_go,
+                        overhead,
+                    ),
+                    None => Self::infinite(),
+                }
+            }
+            Some(UciTimeControl::MoveTime(x)) => Self::fixed(std(x).saturating_sub(overhead)),
+            None => Self {
+                soft: Duration::from_millis(5000),
+                hard: Duration::from_secs(10),
+            },
+        }
+    }
+}
+
+/// Where searches get the time from, so that tests can run them on a clock they control.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Registers `wake` to be called whenever the clock jumps ahead, so that whoever waits for
+    /// a point in time doesn't sleep past it. The system clock never jumps.
+    fn on_jump(&self, _wake: Box<dyn Fn() + Send>) {}
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that stands still until it is advanced by hand.
+#[cfg(test)]
+pub struct ManualClock {
+    start: Instant,
+    elapsed: std::sync::Mutex<Duration>,
+    wakers: std::sync::Mutex<Vec<Box<dyn Fn() + Send>>>,
+}
+
+#[cfg(test)]
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed: std::sync::Mutex::new(Duration::ZERO),
+            wakers: std::sync::Mutex::new(vec![]),
+        }
+    }
+}
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+        for wake in self.wakers.lock().unwrap().iter() {
+            wake();
+        }
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + *self.elapsed.lock().unwrap()
+    }
+
+    fn on_jump(&self, wake: Box<dyn Fn() + Send>) {
+        self.wakers.lock().unwrap().push(wake);
+    }
+}
+
+/// Decides how long to think about a move. The limits come from the clock, and the soft limit
+/// is stretched or shrunk depending on how stable the search looks from iteration to iteration.
+#[derive(Debug, Clone)]
+pub struct TimeManager {
+    limits: TimeLimits,
+    stability: f32,
+    last_best: Option<UciMove>,
+    last_score: Option<f32>,
+}
+
+impl TimeManager {
+    pub fn new(limits: TimeLimits) -> Self {
+        Self {
+            limits,
+            stability: 1.0,
+            last_best: None,
+            last_score: None,
+        }
+    }
+
+    /// Replace the limits, keeping what has been learned about the search so far.
+    pub fn set_limits(&mut self, limits: TimeLimits) {
+        self.limits = limits;
+    }
+
+    /// Record the outcome of a completed iteration.
+    pub fn update(&mut self, best: Option<UciMove>, score: f32) {
+        if self.last_best.is_some() {
+            if best != self.last_best {
+                self.stability *= 1.5;
+            } else {
+                self.stability *= 0.85;
+            }
+        }
+        if let Some(last_score) = self.last_score {
+            if score < last_score - SCORE_DROP {
+                self.stability *= 1.3;
+            }
+        }
+        self.stability = self.stability.clamp(MIN_STABILITY, MAX_STABILITY);
+        self.last_best = best;
+        self.last_score = Some(score);
+    }
+
+    pub fn soft_limit(&self) -> Duration {
+        self.limits
+            .soft
+            .mul_f32(self.stability)
+            .min(self.limits.hard)
+    }
+
+    pub fn hard_limit(&self) -> Duration {
+        self.limits.hard
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use vampirc_uci::{UciMove, UciSquare};
+
+    use super::{TimeLimits, TimeManager};
+
+    fn ms(x: u64) -> Duration {
+        Duration::from_millis(x)
+    }
+
+    #[test]
+    fn test_sudden_death_never_flags() {
+        let overhead = ms(30);
+        for time in [0, 10, 50, 100, 500, 1000, 3000, 60000] {
+            for inc in [0, 100, 1000, 5000] {
+                let limits = TimeLimits::from_clock(ms(time), ms(inc), None, overhead);
+                assert!(limits.soft <= limits.hard);
+                assert!(limits.hard + overhead <= ms(time).max(overhead));
+                assert!(limits.hard <= ms(time).mul_f32(0.6));
+            }
+        }
+    }
+
+    #[test]
+    fn test_moves_to_go_and_increment() {
+        let base = TimeLimits::from_clock(ms(60000), ms(0), None, ms(0));
+        let inc = TimeLimits::from_clock(ms(60000), ms(2000), None, ms(0));
+        let mtg = TimeLimits::from_clock(ms(60000), ms(0), Some(5), ms(0));
+        let last = TimeLimits::from_clock(ms(60000), ms(0), Some(1), ms(0));
+        assert!(inc.soft > base.soft);
+        assert!(mtg.soft > base.soft);
+        assert!(last.soft > mtg.soft);
+        assert!(last.hard <= ms(60000).mul_f32(0.6));
+    }
+
+    #[test]
+    fn test_stability() {
+        let limits = TimeLimits::from_clock(ms(60000), ms(0), None, ms(0));
+        let a = Some(UciMove::from_to(
+            UciSquare::from('e', 2),
+            UciSquare::from('e', 4),
+        ));
+        let b = Some(UciMove::from_to(
+            UciSquare::from('d', 2),
+            UciSquare::from('d', 4),
+        ));
+
+        let mut stable = TimeManager::new(limits);
+        for _ in 0..5 {
+            stable.update(a, 0.2);
+        }
+        assert!(stable.soft_limit() < limits.soft);
+
+        let mut unstable = TimeManager::new(limits);
+        for i in 0..5 {
+            unstable.update(if i % 2 == 0 { a } else { b }, 0.2);
+        }
+        assert!(unstable.soft_limit() > limits.soft);
+        assert!(unstable.soft_limit() <= unstable.hard_limit());
+
+        let mut dropping = TimeManager::new(limits);
+        dropping.update(a, 1.0);
+        dropping.update(a, 0.0);
+        let mut steady = TimeManager::new(limits);
+        steady.update(a, 1.0);
+        steady.update(a, 1.0);
+        assert!(dropping.soft_limit() > steady.soft_limit());
+    }
+}
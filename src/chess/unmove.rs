@@ -0,0 +1,154 @@
+use super::{
+    bitboard::BitBoard,
+    board::Board,
+    moves::Move,
+    piece::Piece,
+    piecemoves,
+    side::Side,
+    square::{Rank, Square},
+};
+
+impl Board {
+    /// The moves that could have been played to reach this position, each from the square its
+    /// piece stands on now back to the square it came from. Only quiet moves are undone, as a
+    /// capture or a promotion would have to bring back material that isn't on the board.
+    /// Castling and en passant are left out too.
+    pub fn unmoves(&self) -> Vec<Move> {
+        let mover = self.to_move().other();
+        let mut occupied = self.color_pieces(Side::White);
+        occupied ^= self.color_pieces(Side::Black);
+        let mut unmoves = vec![];
+        for sq in self.color_pieces(mover) {
+            let piece = self.check_piece(sq).unwrap();
+            let sources = match piece {
+                Piece::Pawn => pawn_sources(sq, mover, occupied),
+                // Pieces other than pawns move the same way back as forth.
+                _ => piecemoves::get_piece_moves(
+                    piece,
+                    mover,
+                    sq,
+                    BitBoard::default(),
+                    BitBoard::default(),
+                    occupied,
+                ),
+            };
+            unmoves.extend(
+                sources
+                    .into_iter()
+                    .map(|source| Move::new(sq, source, None)),
+            );
+        }
+        // The side to move now can't have been left in check.
+        unmoves.retain(|unmove| !self.unmake(unmove).is_in_check(self.to_move()));
+        unmoves
+    }
+
+    /// The position before `unmove`, one of [`Board::unmoves`]. The move counters are kept.
+    pub fn unmake(&self, unmove: &Move) -> Board {
+        let (piece, side) = self.piece(unmove.start()).unwrap();
+        let mut board = self.clone();
+        board.clear_square(unmove.start());
+        board.set_square(unmove.dest(), piece, side);
+        board.set_to_move(side);
+        board.set_enpassant(BitBoard::default());
+        board
+    }
+}
+
+/// Where a pawn of `side` on `sq` can have stepped from, one square back or two from its
+/// starting rank.
+fn pawn_sources(sq: Square, side: Side, occupied: BitBoard) -> BitBoard {
+    let (back, start) = match side {
+        Side::White => (-8, Rank::new(2)),
+        Side::Black => (8, Rank::new(7)),
+    };
+    let step = |sq: Square| {
+        let source = Square((sq.0 as i8 + back) as u8);
+        (!occupied.get(source)).then_some(source)
+    };
+    let mut sources = BitBoard::default();
+    let on_board = |sq: Square| (2..=7).contains(&sq.rank().0);
+    if !on_board(sq) {
+        return sources;
+    }
+    if let Some(one) = step(sq).filter(|&one| on_board(one)) {
+        sources.set(one, true);
+        if let Some(two) = step(one).filter(|two| two.rank() == start) {
+            sources.set(two, true);
+        }
+    }
+    sources
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chess::board::Board;
+
+    const POSITIONS: [&str; 5] = [
+        "8/8/4k3/8/3Q4/8/8/K7 b - - 0 1",
+        "8/8/8/4k3/8/2P5/8/K7 b - - 0 1",
+        "8/8/8/3k4/2P5/8/8/K7 b - - 0 1",
+        "8/2p5/8/8/4K3/8/8/k7 w - - 0 1",
+        "r3k3/8/8/8/8/8/3B4/R3K1N1 w - - 0 1",
+    ];
+
+    #[test]
+    fn test_unmoves_lead_back() {
+        for fen in POSITIONS {
+            let board = Board::from_fen(fen).unwrap();
+            let unmoves = board.unmoves();
+            assert!(!unmoves.is_empty(), "{fen}");
+            for unmove in &unmoves {
+                let before = board.unmake(unmove);
+                assert!(before.validate().is_ok(), "{fen} {unmove}");
+                let forward = before
+                    .legal_moves()
+                    .find(|mv| mv.start() == unmove.dest() && mv.dest() == unmove.start());
+                let after = before.apply_move(&forward.unwrap()).unwrap();
+                assert_eq!(after.to_fen().split(' ').next(), fen.split(' ').next());
+            }
+        }
+    }
+
+    #[test]
+    fn test_unmoves_complete() {
+        // Every quiet move of a position can be undone from where it leads.
+        for fen in POSITIONS {
+            let board = Board::from_fen(fen).unwrap();
+            for mv in board.legal_moves() {
+                if board.piece(mv.dest()).is_some() || mv.promo().is_some() {
+                    continue;
+                }
+                let after = board.clone().apply_move(&mv).unwrap();
+                let back = after
+                    .unmoves()
+                    .into_iter()
+                    .any(|unmove| unmove.start() == mv.dest() && unmove.dest() == mv.start());
+                assert!(back, "{fen} {mv}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_pawn_unmoves() {
+        let board = Board::from_fen("8/8/8/3k4/2P5/8/8/K7 b - - 0 1").unwrap();
+        let mut pawn: Vec<String> = board
+            .unmoves()
+            .iter()
+            .filter(|unmove| unmove.start().to_string() == "c4")
+            .map(|unmove| unmove.dest().to_string())
+            .collect();
+        pawn.sort();
+        assert_eq!(pawn, ["c2", "c3"]);
+        // Nothing comes from behind the starting rank, and nothing jumps over a piece.
+        let board = Board::from_fen("8/8/8/3k4/2P5/2N5/2P5/K7 b - - 0 1").unwrap();
+        assert!(board
+            .unmoves()
+            .iter()
+            .all(|unmove| unmove.start().to_string() != "c4"));
+        assert!(board
+            .unmoves()
+            .iter()
+            .all(|unmove| unmove.start().to_string() != "c2"));
+    }
+}
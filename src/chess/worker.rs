@@ -0,0 +1,515 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use vampirc_uci::{UciInfoAttribute, UciMessage, UciMove, UciSearchControl, UciTimeControl};
+
+use crate::{ab::SearchSettings, tt::TranspositionTable};
+
+use super::{
+    board::Board,
+    io::{Logger, OutputSink},
+    retro::DtmTables,
+    side::Side,
+    syzygy::Tablebases,
+    timeman::{Clock, TimeLimits, TimeManager},
+};
+
+/// What the search thread is doing. Every `go` moves the worker out of `Idle`, and it only
+/// returns there after answering that `go` with its one `bestmove`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SearchState {
+    Idle,
+    Searching,
+    Pondering,
+    /// The search has been told to finish and is about to report its move.
+    Stopping,
+}
+
+impl SearchState {
+    fn from_u8(x: u8) -> Self {
+        match x {
+            1 => Self::Searching,
+            2 => Self::Pondering,
+            3 => Self::Stopping,
+            _ => Self::Idle,
+        }
+    }
+}
+
+/// Everything a `go` needs, captured when the command arrives.
+#[derive(Clone)]
+pub struct SearchJob {
+    pub board: Board,
+    /// When the `go` arrived. Its time runs from here, not from when the search thread gets to
+    /// it.
+    pub started: Instant,
+    pub time_control: Option<UciTimeControl>,
+    pub search_control: Option<UciSearchControl>,
+    pub ponder: bool,
+    pub move_overhead: Duration,
+    /// Searching threads, including the one whose result is played.
+    pub threads: usize,
+    pub tt: Arc<TranspositionTable>,
+    /// Hashes of the positions played before `board`, oldest first.
+    pub history: Vec<u64>,
+    /// In pawns, like scores.
+    pub contempt: f32,
+    pub tablebases: Option<Arc<Tablebases>>,
+    pub dtm_tables: Option<Arc<DtmTables>>,
+}
+
+/// The outcome of one completed iteration.
+#[derive(Debug, Clone, PartialEq)]
+struct SearchResult {
+    best_move: Option<UciMove>,
+    ponder: Option<UciMove>,
+    pv: Vec<UciMove>,
+    depth: u64,
+    /// From the side to move's point of view, as UCI reports it.
+    score: f32,
+    confidence: f32,
+}
+
+enum Event {
+    Go(Box<SearchJob>),
+    Stop,
+    /// Carries the time the ponderhit arrived.
+    PonderHit(Instant),
+    Quit,
+    /// The clock jumped ahead, so any deadline has to be checked again.
+    Tick,
+    /// Sent by the thread doing the actual searching, tagged with the search it belongs to.
+    Iteration(u64, SearchResult),
+    /// The iterations ran out before the search was stopped, e.g. because of `go depth`.
+    Exhausted(u64),
+}
+
+/// How a search ended, from the point of view of the worker loop.
+enum Outcome {
+    Done,
+    /// Another `go` arrived while searching. It counts as a stop for the current search.
+    Next(Box<SearchJob>),
+    Quit,
+}
+
+/// Handle to the search thread. Commands are queued over a channel, so none of these calls
+/// ever wait for a search. Dropping the handle ends the thread without reporting a move.
+pub struct SearchWorker {
+    events: Sender<Event>,
+    state: Arc<AtomicU8>,
+    clock: Arc<dyn Clock>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SearchWorker {
+    pub fn spawn(
+        output: Arc<dyn OutputSink>,
+        logger: Arc<dyn Logger>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        let (events, recv) = channel();
+        let state = Arc::new(AtomicU8::new(SearchState::Idle as u8));
+        let waker = events.clone();
+        clock.on_jump(Box::new(move || {
+            let _ = waker.send(Event::Tick);
+        }));
+        let worker = Worker {
+            events: recv,
+            sender: events.clone(),
+            state: state.clone(),
+            output,
+            logger,
+            clock: clock.clone(),
+            generation: 0,
+        };
+        let thread = thread::Builder::new()
+            .name("search".into())
+            .spawn(move || worker.run())
+            .unwrap();
+        Self {
+            events,
+            state,
+            clock,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn state(&self) -> SearchState {
+        SearchState::from_u8(self.state.load(Ordering::SeqCst))
+    }
+
+    pub fn go(&self, job: SearchJob) {
+        let state = if job.ponder {
+            SearchState::Pondering
+        } else {
+            SearchState::Searching
+        };
+        self.state.store(state as u8, Ordering::SeqCst);
+        self.send(Event::Go(Box::new(job)));
+    }
+
+    pub fn stop(&self) {
+        self.send(Event::Stop);
+    }
+
+    pub fn ponderhit(&self) {
+        self.send(Event::PonderHit(self.clock.now()));
+    }
+
+    fn send(&self, event: Event) {
+        // The thread only goes away when the handle is dropped.
+        let _ = self.events.send(event);
+    }
+}
+
+impl Drop for SearchWorker {
+    fn drop(&mut self) {
+        self.send(Event::Quit);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Time bookkeeping for one `go`.
+struct SearchTimer {
+    clock: Arc<dyn Clock>,
+    start: Instant,
+    time_control: Option<UciTimeControl>,
+    our_side: Side,
+    move_overhead: Duration,
+    time: TimeManager,
+}
+
+impl SearchTimer {
+    fn new(job: &SearchJob, clock: Arc<dyn Clock>) -> Self {
+        let our_side = job.board.to_move();
+        Self {
+            clock,
+            start: job.started,
+            time: TimeManager::new(TimeLimits::new(
+                job.time_control.as_ref(),
+                our_side,
+                job.move_overhead,
+            )),
+            time_control: job.time_control.clone(),
+            our_side,
+            move_overhead: job.move_overhead,
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.clock.now().duration_since(self.start)
+    }
+
+    /// Our clock starts running at the ponderhit, which arrived `at`, and has lost whatever
+    /// time the GUI spent waiting for the opponent's move.
+    fn ponderhit(&mut self, at: Instant) {
+        let time_since = at.duration_since(self.start);
+        if let Some(UciTimeControl::TimeLeft {
+            white_time,
+            black_time,
+            ..
+        }) = &mut self.time_control
+        {
+            let time = match self.our_side {
+                Side::White => white_time,
+                Side::Black => black_time,
+            };
+            if let Some(time) = time {
+                *time = time
+                    .checked_sub(&vampirc_uci::Duration::from_std(time_since).unwrap())
+                    .unwrap_or(vampirc_uci::Duration::milliseconds(100));
+            }
+        }
+        self.time.set_limits(TimeLimits::new(
+            self.time_control.as_ref(),
+            self.our_side,
+            self.move_overhead,
+        ));
+        self.start = at;
+    }
+}
+
+struct Worker {
+    events: Receiver<Event>,
+    /// Handed to the threads doing the searching so they can report back.
+    sender: Sender<Event>,
+    state: Arc<AtomicU8>,
+    output: Arc<dyn OutputSink>,
+    logger: Arc<dyn Logger>,
+    clock: Arc<dyn Clock>,
+    generation: u64,
+}
+
+impl Worker {
+    fn run(mut self) {
+        let mut next = None;
+        loop {
+            let job = match next.take() {
+                Some(job) => job,
+                None => match self.events.recv() {
+                    Ok(Event::Go(job)) => job,
+                    Ok(Event::Quit) | Err(_) => return,
+                    // A stop, ponderhit or clock jump with nothing to apply it to, or the
+                    // leftovers of a search that already reported its move.
+                    Ok(_) => continue,
+                },
+            };
+            match self.search(job) {
+                Outcome::Done => {}
+                Outcome::Next(job) => next = Some(job),
+                Outcome::Quit => return,
+            }
+        }
+    }
+
+    fn set_state(&self, state: SearchState) {
+        self.state.store(state as u8, Ordering::SeqCst);
+    }
+
+    fn log(&self, args: std::fmt::Arguments<'_>) {
+        self.logger.log(args);
+    }
+
+    fn search(&mut self, job: Box<SearchJob>) -> Outcome {
+        self.generation += 1;
+        let generation = self.generation;
+        let mut pondering = job.ponder;
+        self.set_state(if pondering {
+            SearchState::Pondering
+        } else {
+            SearchState::Searching
+        });
+
+        let infinite = matches!(job.time_control, Some(UciTimeControl::Infinite));
+        let max_depth = job
+            .search_control
+            .as_ref()
+            .and_then(|sc| sc.depth)
+            .map(u64::from);
+        let mut timer = SearchTimer::new(&job, self.clock.clone());
+        let started = self.clock.now();
+        let shared = Shared {
+            stop: Arc::new(AtomicBool::new(false)),
+            nodes: Arc::new(AtomicU64::new(0)),
+            tt: job.tt.clone(),
+            history: Arc::new(job.history.clone()),
+            contempt: job.contempt,
+            root_moves: root_moves(&job).map(Arc::new),
+            tablebases: job.tablebases.clone(),
+            dtm_tables: job.dtm_tables.clone(),
+        };
+        let mut best: Option<SearchResult> = None;
+        let mut outcome = Outcome::Done;
+
+        thread::scope(|scope| {
+            // Lazy SMP: every thread searches the whole tree, and they help each other only
+            // through the transposition table. Only the first one's results are used.
+            for helper in 0..job.threads.max(1) {
+                let sender = self.sender.clone();
+                let board = job.board.clone();
+                let shared = shared.clone();
+                scope.spawn(move || {
+                    iterate(&board, helper, max_depth, &shared, |res| {
+                        if helper == 0 {
+                            let _ = sender.send(Event::Iteration(generation, res));
+                        }
+                    });
+                    if helper == 0 {
+                        let _ = sender.send(Event::Exhausted(generation));
+                    }
+                });
+            }
+
+            loop {
+                // Pondering and infinite searches have no time limit; they end with a command.
+                let limited = !pondering && !infinite;
+                let event = if limited {
+                    let left = timer.time.hard_limit().saturating_sub(timer.elapsed());
+                    if left.is_zero() {
+                        break;
+                    }
+                    match self.events.recv_timeout(left) {
+                        Ok(event) => event,
+                        // Only the clock says when time is up, and one moved by hand may not
+                        // be there yet.
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => Event::Quit,
+                    }
+                } else {
+                    self.events.recv().unwrap_or(Event::Quit)
+                };
+                match event {
+                    Event::Iteration(g, res) if g == generation => {
+                        timer.time.update(res.best_move, res.score);
+                        let elapsed = self.clock.now().duration_since(started);
+                        self.send_info(&res, elapsed, &shared);
+                        let done = limited && good_enough(&res, &timer);
+                        best = Some(res);
+                        if done {
+                            break;
+                        }
+                    }
+                    Event::Exhausted(g) if g == generation => {
+                        if limited {
+                            break;
+                        }
+                    }
+                    Event::PonderHit(at) if pondering => {
+                        pondering = false;
+                        self.set_state(SearchState::Searching);
+                        timer.ponderhit(at);
+                        if infinite {
+                            continue;
+                        }
+                        if best.as_ref().is_some_and(|res| good_enough(res, &timer)) {
+                            break;
+                        }
+                    }
+                    Event::Stop => break,
+                    Event::Go(job) => {
+                        outcome = Outcome::Next(job);
+                        break;
+                    }
+                    Event::Quit => {
+                        outcome = Outcome::Quit;
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            self.set_state(SearchState::Stopping);
+            shared.stop.store(true, Ordering::SeqCst);
+        });
+
+        if let Outcome::Quit = outcome {
+            self.set_state(SearchState::Idle);
+            return outcome;
+        }
+        let best = best
+            .filter(|res| res.best_move.is_some())
+            .map(|res| (res.best_move.unwrap(), res.ponder))
+            .or_else(|| job.board.legal_moves().next().map(|mv| (mv.into(), None)));
+        self.set_state(SearchState::Idle);
+        match best {
+            Some((best_move, ponder)) => {
+                self.output.send(UciMessage::BestMove { best_move, ponder })
+            }
+            None => self.log(format_args!("no legal moves in {}", job.board.to_fen())),
+        }
+        outcome
+    }
+
+    fn send_info(&self, res: &SearchResult, elapsed: Duration, shared: &Shared) {
+        let nodes = shared.nodes.load(Ordering::Relaxed);
+        let mut info = vec![
+            UciInfoAttribute::Depth(res.depth.min(u8::MAX as u64) as u8),
+            UciInfoAttribute::from_centipawns((res.score * 100.0) as i32),
+            UciInfoAttribute::Nodes(nodes),
+            UciInfoAttribute::Nps(nodes * 1000 / (elapsed.as_millis() as u64).max(1)),
+            UciInfoAttribute::Time(vampirc_uci::Duration::from_std(elapsed).unwrap()),
+            UciInfoAttribute::HashFull(shared.tt.hashfull()),
+        ];
+        if !res.pv.is_empty() {
+            info.push(UciInfoAttribute::Pv(res.pv.clone()));
+        }
+        self.output.send(UciMessage::Info(info));
+    }
+}
+
+/// What the threads searching one position have in common.
+#[derive(Clone)]
+struct Shared {
+    stop: Arc<AtomicBool>,
+    /// Nodes visited by all threads together.
+    nodes: Arc<AtomicU64>,
+    tt: Arc<TranspositionTable>,
+    history: Arc<Vec<u64>>,
+    contempt: f32,
+    /// The root moves that keep the outcome the tablebases promise, by their index.
+    root_moves: Option<Arc<Vec<usize>>>,
+    tablebases: Option<Arc<Tablebases>>,
+    dtm_tables: Option<Arc<DtmTables>>,
+}
+
+/// With the root in the tablebases, only the moves that keep its outcome are searched, so the
+/// search can't throw away a win it can't see to the end of.
+fn root_moves(job: &SearchJob) -> Option<Vec<usize>> {
+    let tablebases = job.tablebases.as_ref()?;
+    let repeated = job.history.contains(&job.board.hash());
+    let allowed = tablebases.root_moves(&job.board, repeated)?;
+    let indices = job
+        .board
+        .legal_moves()
+        .enumerate()
+        .filter(|(_, mv)| allowed.contains(mv))
+        .map(|(i, _)| i)
+        .collect();
+    Some(indices)
+}
+
+/// Whether a time-limited search can stop after `res`.
+fn good_enough(res: &SearchResult, timer: &SearchTimer) -> bool {
+    res.confidence >= 0.0 || timer.elapsed() >= timer.time.soft_limit()
+}
+
+/// Iterative deepening, reporting every iteration that was not cut short by `stop`. Helpers
+/// with odd numbers search the odd depths the main thread skips.
+fn iterate(
+    board: &Board,
+    helper: usize,
+    max_depth: Option<u64>,
+    shared: &Shared,
+    mut report: impl FnMut(SearchResult),
+) {
+    let mut depth = 1 + (helper % 2) as u64;
+    if let Some(max) = max_depth {
+        depth = depth.min(max.max(1));
+    }
+    loop {
+        let settings = SearchSettings {
+            stop: Some(shared.stop.clone()),
+            tt: Some(shared.tt.clone()),
+            nodes: Some(shared.nodes.clone()),
+            helper,
+            history: shared.history.to_vec(),
+            contempt: shared.contempt,
+            tablebases: shared.tablebases.clone(),
+            dtm_tables: shared.dtm_tables.clone(),
+            root_moves: shared.root_moves.as_ref().map(|moves| moves.to_vec()),
+            ..SearchSettings::search(depth)
+        };
+        let res = board.alphabeta(&settings);
+        if shared.stop.load(Ordering::SeqCst) {
+            return;
+        }
+        let pv: Vec<UciMove> = res.data.iter().rev().map(|x| x.mv.into()).collect();
+        report(SearchResult {
+            best_move: pv.first().copied(),
+            ponder: pv.get(1).copied(),
+            depth,
+            score: match board.to_move() {
+                Side::White => res.value,
+                Side::Black => -res.value,
+            },
+            confidence: (depth as f32) - 7.,
+            pv,
+        });
+        if res.data.is_empty() || max_depth.is_some_and(|max| depth >= max) {
+            return;
+        }
+        depth = match max_depth {
+            Some(max) => (depth + 2).min(max),
+            None => depth + 2,
+        };
+    }
+}
@@ -0,0 +1,150 @@
+use super::{
+    board::Board,
+    piece::{ALL_PIECES, NR_PIECE_TYPES},
+    side::Side,
+};
+
+/// Random keys for Zobrist hashing. They are generated at compile time, so a position hashes
+/// the same in every run.
+struct Keys {
+    pieces: [[[u64; 64]; NR_PIECE_TYPES]; 2],
+    /// White kingside, white queenside, black kingside, black queenside.
+    castling: [u64; 4],
+    enpassant_file: [u64; 8],
+    black_to_move: u64,
+}
+
+/// splitmix64, which turns consecutive integers into well-spread keys.
+const fn key(n: u64) -> u64 {
+    let mut z = (n + 1).wrapping_mul(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+const fn generate() -> Keys {
+    let mut keys = Keys {
+        pieces: [[[0; 64]; NR_PIECE_TYPES]; 2],
+        castling: [0; 4],
+        enpassant_file: [0; 8],
+        black_to_move: 0,
+    };
+    let mut n = 0;
+    let mut side = 0;
+    while side < 2 {
+        let mut piece = 0;
+        while piece < NR_PIECE_TYPES {
+            let mut sq = 0;
+            while sq < 64 {
+                keys.pieces[side][piece][sq] = key(n);
+                n += 1;
+                sq += 1;
+            }
+            piece += 1;
+        }
+        side += 1;
+    }
+    let mut i = 0;
+    while i < 4 {
+        keys.castling[i] = key(n);
+        n += 1;
+        i += 1;
+    }
+    let mut i = 0;
+    while i < 8 {
+        keys.enpassant_file[i] = key(n);
+        n += 1;
+        i += 1;
+    }
+    keys.black_to_move = key(n);
+    keys
+}
+
+static KEYS: Keys = generate();
+
+impl Board {
+    /// The Zobrist hash of the position. Move counters are not part of it, so the same position
+    /// reached by different move orders hashes the same.
+    pub fn hash(&self) -> u64 {
+        let mut hash = 0;
+        for side in [Side::White, Side::Black] {
+            for piece in ALL_PIECES {
+                for sq in self.pieces(piece) & self.color_pieces(side) {
+                    hash ^= KEYS.pieces[side][piece][sq.0 as usize];
+                }
+            }
+            let rights = self.castle_rights(side);
+            if rights.kingside() {
+                hash ^= KEYS.castling[side as usize * 2];
+            }
+            if rights.queenside() {
+                hash ^= KEYS.castling[side as usize * 2 + 1];
+            }
+        }
+        if let Some(sq) = self.enpassant().to_square() {
+            hash ^= KEYS.enpassant_file[sq.file() as usize];
+        }
+        if self.to_move() == Side::Black {
+            hash ^= KEYS.black_to_move;
+        }
+        hash
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use vampirc_uci::UciMove;
+
+    use crate::chess::{board::Board, moves::Move};
+
+    fn play(fen: &str, moves: &[&str]) -> Board {
+        let mut board = Board::from_fen(fen).unwrap();
+        for mv in moves {
+            let mv = board
+                .legal_moves()
+                .find(|&m| UciMove::from(m).to_string() == *mv)
+                .unwrap_or_else(|| panic!("{mv} is not legal"));
+            board = board.apply_move(&mv).unwrap();
+        }
+        board
+    }
+
+    #[test]
+    fn test_transpositions() {
+        let a = play("startpos", &["g1f3", "g8f6", "b1c3", "b8c6"]);
+        let b = play("startpos", &["b1c3", "b8c6", "g1f3", "g8f6"]);
+        assert_eq!(a.hash(), b.hash());
+        assert_ne!(a.hash(), Board::from_fen("startpos").unwrap().hash());
+
+        // Knights out and back is the start position again, apart from the move counters.
+        let c = play("startpos", &["g1f3", "g8f6", "f3g1", "f6g8"]);
+        assert_eq!(c.hash(), Board::from_fen("startpos").unwrap().hash());
+    }
+
+    #[test]
+    fn test_state_is_hashed() {
+        let hash = |fen: &str| Board::from_fen(fen).unwrap().hash();
+        let base = hash("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        assert_ne!(base, hash("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1"));
+        assert_ne!(base, hash("r3k2r/8/8/8/8/8/8/R3K2R w Kkq - 0 1"));
+        assert_ne!(base, hash("r3k2r/8/8/8/8/8/8/R3K2R w KQq - 0 1"));
+        assert_ne!(
+            hash("4k3/8/8/8/3pP3/8/8/4K3 b - e3 0 1"),
+            hash("4k3/8/8/8/3pP3/8/8/4K3 b - - 0 1")
+        );
+        assert_eq!(base, hash("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 12 40"));
+    }
+
+    #[test]
+    fn test_moves_change_hash() {
+        let board = Board::from_fen("startpos").unwrap();
+        let mut hashes: Vec<u64> = board
+            .legal_moves()
+            .map(|m: Move| board.clone().apply_move(&m).unwrap().hash())
+            .collect();
+        hashes.push(board.hash());
+        hashes.sort();
+        hashes.dedup();
+        assert_eq!(hashes.len(), 21);
+    }
+}
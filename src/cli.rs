@@ -0,0 +1,638 @@
+use std::{io::stdin, time::Duration};
+
+use crate::chess::{
+    analysis::Analysis,
+    bench,
+    board::Board,
+    bookbuild::{self, BuildOptions},
+    debug,
+    engine::Engine,
+    perft,
+    play::{self, PlayOptions, TimeControl},
+    retro::{self, Signature},
+    side::Side,
+    suite::{self, Budget},
+    worker::SearchState,
+};
+
+pub const SUCCESS: i32 = 0;
+/// A check the command ran failed, e.g. a perft count was wrong.
+pub const FAILURE: i32 = 1;
+/// The command line or its input didn't make sense.
+pub const USAGE: i32 = 2;
+
+struct CommandHelp {
+    name: &'static str,
+    usage: &'static str,
+    description: &'static str,
+    /// The `--name value` options the command takes.
+    flags: &'static [&'static str],
+}
+
+const COMMANDS: [CommandHelp; 10] = [
+    CommandHelp {
+        name: "uci",
+        usage: "uci",
+        description: "Talk UCI on stdin and stdout. This is what runs without a command.",
+        flags: &[],
+    },
+    CommandHelp {
+        name: "perft",
+        usage: "perft <fen> <depth>",
+        description: "Count the leaf nodes of the move tree below every legal move, and in total.",
+        flags: &[],
+    },
+    CommandHelp {
+        name: "analyze",
+        usage: "analyze <fen> [--depth N] [--movetime MS] [--threads N]",
+        description: "Search a position, printing the search info and the best move. Searches to\n\
+                      depth 6 without limits.",
+        flags: &["depth", "movetime", "threads"],
+    },
+    CommandHelp {
+        name: "bench",
+        usage: "bench [depth]",
+        description: "Search the built-in positions and print the node count, a signature of the\n\
+                      search.",
+        flags: &[],
+    },
+    CommandHelp {
+        name: "play",
+        usage: "play [--color white|black] [--depth N | --movetime MS | --clock MIN+INC]\n       \
+                [--pgn FILE] [--threads N]",
+        description: "Play a game against the engine in the terminal. Enter moves as Nf3 or\n\
+                      g1f3, or resign or quit. The engine thinks for a second a move unless\n\
+                      told otherwise; --clock 5+3 plays five minutes each with three seconds\n\
+                      added per move. With --pgn, the game is saved to FILE when it ends.",
+        flags: &["color", "depth", "movetime", "clock", "pgn", "threads"],
+    },
+    CommandHelp {
+        name: "epd",
+        usage: "epd <file> [--depth N] [--threads N]",
+        description: "Check the perft counts (;D1 20 ;D2 400 ...) of every position in an EPD\n\
+                      file, up to depth N if given.",
+        flags: &["depth", "threads"],
+    },
+    CommandHelp {
+        name: "suite",
+        usage: "suite <file> [--movetime MS] [--depth N] [--nodes N]",
+        description: "Search every position of an EPD test suite and check the moves against its\n\
+                      bm and am moves. Each position gets a second unless told otherwise.\n\
+                      Fails only on records that can't be read.",
+        flags: &["movetime", "depth", "nodes"],
+    },
+    CommandHelp {
+        name: "book",
+        usage: "book <out.bin> <pgn>... [--ply N] [--min-games N] [--min-score PERCENT]",
+        description: "Build a Polyglot opening book from the games of PGN files. Moves in the\n\
+                      first 16 plies that were played in at least 3 games go in, weighted by\n\
+                      two points a win and one a draw; --min-score leaves out moves that scored\n\
+                      less for the side playing them. Fails if any game can't be read.",
+        flags: &["ply", "min-games", "min-score"],
+    },
+    CommandHelp {
+        name: "tbgen",
+        usage: "tbgen <material>... [--out DIR]",
+        description: "Generate distance-to-mate tables for up to four pieces, written like KQK\n\
+                      or KBNK, and for the material they can turn into. The files go in the\n\
+                      current directory unless told otherwise, and the engine reads them from\n\
+                      its DtmPath.",
+        flags: &["out"],
+    },
+    CommandHelp {
+        name: "help",
+        usage: "help [command]",
+        description: "Show this help, or the help of one command.",
+        flags: &[],
+    },
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Uci,
+    Perft {
+        fen: String,
+        depth: u64,
+    },
+    Analyze {
+        fen: String,
+        depth: Option<u64>,
+        movetime: Option<u64>,
+        threads: usize,
+    },
+    Bench {
+        depth: u64,
+    },
+    Play {
+        human: Side,
+        time_control: TimeControl,
+        pgn: Option<String>,
+        threads: usize,
+    },
+    Epd {
+        path: String,
+        max_depth: u64,
+        threads: usize,
+    },
+    Suite {
+        path: String,
+        budget: Budget,
+    },
+    Book {
+        out: String,
+        pgns: Vec<String>,
+        options: BuildOptions,
+    },
+    Tbgen {
+        signatures: Vec<Signature>,
+        out: String,
+    },
+    Help(Option<String>),
+}
+
+fn help(command: Option<&str>) -> String {
+    match command.and_then(|name| COMMANDS.iter().find(|c| c.name == name)) {
+        Some(c) => format!("usage: chess {}\n\n{}\n", c.usage, c.description),
+        None => {
+            let mut text = String::from("usage: chess [command] [args]\n\ncommands:\n");
+            for c in COMMANDS {
+                text.push_str(&format!("  {}\n", c.name));
+            }
+            text.push_str(
+                "\n`chess help <command>` describes a command.\n\
+                 Exits with 0 on success, 1 when a check fails and 2 on bad usage or input.\n",
+            );
+            text
+        }
+    }
+}
+
+fn default_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// `--name value` pairs.
+type Flags<'a> = Vec<(&'a str, &'a str)>;
+
+/// Splits arguments into positional ones and flags.
+fn split_flags(args: &[String]) -> Result<(Vec<&str>, Flags<'_>), ()> {
+    let mut positional = vec![];
+    let mut flags = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.strip_prefix("--") {
+            Some(name) => flags.push((name, args.next().ok_or(())?.as_str())),
+            None => positional.push(arg.as_str()),
+        }
+    }
+    Ok((positional, flags))
+}
+
+fn number<T: std::str::FromStr>(arg: Option<&str>, default: T) -> Result<T, ()> {
+    arg.map_or(Ok(default), |arg| arg.parse().map_err(|_| ()))
+}
+
+/// A FEN given as several arguments, because it wasn't quoted, is put back together.
+fn fen(parts: &[&str]) -> Result<String, ()> {
+    let fen = parts.join(" ");
+    Board::from_fen(&fen).map_err(|_| ())?;
+    Ok(fen)
+}
+
+fn parse_command<'a>(
+    name: &str,
+    positional: &[&str],
+    flag: impl Fn(&str) -> Option<&'a str>,
+) -> Result<Command, ()> {
+    let optional = |flag: Option<&str>| flag.map(str::parse).transpose().map_err(|_| ());
+    Ok(match name {
+        "uci" if positional.is_empty() => Command::Uci,
+        "perft" if positional.len() >= 2 => {
+            let (depth, fen_parts) = positional.split_last().unwrap();
+            Command::Perft {
+                fen: fen(fen_parts)?,
+                depth: depth.parse().map_err(|_| ())?,
+            }
+        }
+        "analyze" if !positional.is_empty() => Command::Analyze {
+            fen: fen(positional)?,
+            depth: optional(flag("depth"))?,
+            movetime: optional(flag("movetime"))?,
+            threads: number(flag("threads"), default_threads())?,
+        },
+        "bench" if positional.len() <= 1 => Command::Bench {
+            depth: number(positional.first().copied(), bench::DEFAULT_DEPTH)?,
+        },
+        "play" if positional.is_empty() => Command::Play {
+            human: match flag("color") {
+                None | Some("white") => Side::White,
+                Some("black") => Side::Black,
+                Some(_) => return Err(()),
+            },
+            time_control: match (flag("depth"), flag("movetime"), flag("clock")) {
+                (None, None, None) => TimeControl::MoveTime(Duration::from_secs(1)),
+                (Some(depth), None, None) => TimeControl::Depth(depth.parse().map_err(|_| ())?),
+                (None, Some(ms), None) => {
+                    TimeControl::MoveTime(Duration::from_millis(ms.parse().map_err(|_| ())?))
+                }
+                (None, None, Some(clock)) => {
+                    let (minutes, increment) = clock.split_once('+').unwrap_or((clock, "0"));
+                    TimeControl::Clock {
+                        time: Duration::from_secs_f64(
+                            minutes.parse::<f64>().map_err(|_| ())? * 60.,
+                        ),
+                        increment: Duration::from_secs(increment.parse().map_err(|_| ())?),
+                    }
+                }
+                _ => return Err(()),
+            },
+            pgn: flag("pgn").map(str::to_owned),
+            threads: number(flag("threads"), 1)?,
+        },
+        "epd" if positional.len() == 1 => Command::Epd {
+            path: positional[0].to_owned(),
+            max_depth: number(flag("depth"), u64::MAX)?,
+            threads: number(flag("threads"), default_threads())?,
+        },
+        "suite" if positional.len() == 1 => {
+            let budget = Budget {
+                time: optional(flag("movetime"))?.map(Duration::from_millis),
+                depth: optional(flag("depth"))?,
+                nodes: optional(flag("nodes"))?,
+            };
+            Command::Suite {
+                path: positional[0].to_owned(),
+                budget,
+            }
+        }
+        "book" if positional.len() >= 2 => {
+            let defaults = BuildOptions::default();
+            Command::Book {
+                out: positional[0].to_owned(),
+                pgns: positional[1..].iter().map(|&p| p.to_owned()).collect(),
+                options: BuildOptions {
+                    max_ply: number(flag("ply"), defaults.max_ply)?,
+                    min_games: number(flag("min-games"), defaults.min_games)?,
+                    min_score: number(flag("min-score"), defaults.min_score)?,
+                },
+            }
+        }
+        "tbgen" if !positional.is_empty() => Command::Tbgen {
+            signatures: positional
+                .iter()
+                .map(|s| s.parse())
+                .collect::<Result<_, _>>()
+                .map_err(|_| ())?,
+            out: flag("out").unwrap_or(".").to_owned(),
+        },
+        "help" if positional.len() <= 1 => Command::Help(positional.first().map(|s| s.to_string())),
+        _ => return Err(()),
+    })
+}
+
+/// Parses the arguments after the program name. On error, returns what to tell the user.
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    let Some(name) = args.first() else {
+        return Ok(Command::Uci);
+    };
+    if name == "--help" || name == "-h" {
+        return Ok(Command::Help(None));
+    }
+    let Some(known) = COMMANDS.iter().find(|c| c.name == name) else {
+        return Err(format!("unknown command {name}\n\n{}", help(None)));
+    };
+    let rest = &args[1..];
+    if rest.iter().any(|arg| arg == "--help" || arg == "-h") {
+        return Ok(Command::Help(Some(name.clone())));
+    }
+    let usage = help(Some(name));
+    let (positional, flags) = split_flags(rest).map_err(|_| usage.clone())?;
+    if let Some((unknown, _)) = flags.iter().find(|(n, _)| !known.flags.contains(n)) {
+        return Err(format!("unknown option --{unknown}\n\n{usage}"));
+    }
+    // Material the generator can't handle is worth saying more about than the usage.
+    if name == "tbgen" {
+        if let Some(err) = positional.iter().find_map(|s| s.parse::<Signature>().err()) {
+            return Err(format!("{err}\n\n{usage}"));
+        }
+    }
+    let flag = |name: &str| flags.iter().find(|(n, _)| *n == name).map(|(_, v)| *v);
+    parse_command(name, &positional, flag).map_err(|_| usage)
+}
+
+/// Runs the command line and returns the exit code.
+pub fn run(args: &[String]) -> i32 {
+    match parse(args) {
+        Ok(command) => run_command(command),
+        Err(usage) => {
+            eprint!("{usage}");
+            USAGE
+        }
+    }
+}
+
+pub fn run_command(command: Command) -> i32 {
+    match command {
+        Command::Uci => uci(),
+        Command::Perft { fen, depth } => {
+            let board = Board::from_fen(&fen).unwrap();
+            print!(
+                "{}",
+                debug::run_command(&board, &format!("divide {depth}")).unwrap()
+            );
+            SUCCESS
+        }
+        Command::Analyze {
+            fen,
+            depth,
+            movetime,
+            threads,
+        } => {
+            let go = match (depth, movetime) {
+                (None, None) => "go depth 6".to_owned(),
+                (Some(depth), None) => format!("go depth {depth}"),
+                (None, Some(movetime)) => format!("go movetime {movetime}"),
+                (Some(depth), Some(movetime)) => format!("go depth {depth} movetime {movetime}"),
+            };
+            if Analysis::new(true, threads)
+                .search(&fen, &[], &go)
+                .is_none()
+            {
+                println!("no legal moves");
+            }
+            SUCCESS
+        }
+        Command::Bench { depth } => {
+            print!("{}", bench::bench(depth));
+            SUCCESS
+        }
+        Command::Play {
+            human,
+            time_control,
+            pgn,
+            threads,
+        } => {
+            let options = PlayOptions {
+                human,
+                time_control,
+                threads,
+            };
+            let game = match play::play(stdin().lock(), &mut std::io::stdout(), &options) {
+                Ok(game) => game,
+                Err(err) => {
+                    eprintln!("{err}");
+                    return FAILURE;
+                }
+            };
+            match pgn.map(|path| (std::fs::write(&path, game.pgn()), path)) {
+                Some((Err(err), path)) => {
+                    eprintln!("{path}: {err}");
+                    FAILURE
+                }
+                Some((Ok(()), path)) => {
+                    println!("saved the game to {path}");
+                    SUCCESS
+                }
+                None => SUCCESS,
+            }
+        }
+        Command::Epd {
+            path,
+            max_depth,
+            threads,
+        } => match perft::run_epd_file(&path, max_depth, threads) {
+            Ok(true) => SUCCESS,
+            Ok(false) => FAILURE,
+            Err(err) => {
+                eprintln!("{path}: {err}");
+                USAGE
+            }
+        },
+        Command::Suite { path, budget } => match suite::run_suite_file(&path, &budget) {
+            Ok(summary) if summary.errors == 0 => SUCCESS,
+            Ok(_) => FAILURE,
+            Err(err) => {
+                eprintln!("{path}: {err}");
+                USAGE
+            }
+        },
+        Command::Book { out, pgns, options } => {
+            match bookbuild::build_book_file(&out, &pgns, options) {
+                Ok(summary) => {
+                    println!("{summary}, written to {out}");
+                    match summary.errors {
+                        0 => SUCCESS,
+                        _ => FAILURE,
+                    }
+                }
+                Err(err) => {
+                    eprintln!("{err}");
+                    USAGE
+                }
+            }
+        }
+        Command::Tbgen { signatures, out } => {
+            match retro::generate_table_files(&signatures, &out) {
+                Ok(summaries) => {
+                    for summary in summaries {
+                        println!("{summary}");
+                    }
+                    SUCCESS
+                }
+                Err(err) => {
+                    eprintln!("{out}: {err}");
+                    USAGE
+                }
+            }
+        }
+        Command::Help(command) => {
+            print!("{}", help(command.as_deref()));
+            SUCCESS
+        }
+    }
+}
+
+fn uci() -> i32 {
+    let engine = Engine::default();
+    for line in stdin().lines() {
+        let Ok(line) = line else {
+            return FAILURE;
+        };
+        if let Some(out) = debug::run_command(&engine.board(), &line) {
+            print!("{out}");
+            continue;
+        }
+        engine.handle_uci_line(&line);
+        if line.trim() == "quit" {
+            return SUCCESS;
+        }
+    }
+    // Input ran out, e.g. because the GUI closed the pipe. Nobody is left to send `stop`, and an
+    // infinite or ponder search would never end without it, so stop the search and let it
+    // report its move.
+    engine.handle_uci_line("stop");
+    while engine.search_state() != SearchState::Idle {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    SUCCESS
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::chess::{bookbuild::BuildOptions, play::TimeControl, side::Side, suite::Budget};
+
+    use super::{parse, Command};
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(&[]), Ok(Command::Uci));
+        assert_eq!(parse(&args("uci")), Ok(Command::Uci));
+        assert_eq!(parse(&args("--help")), Ok(Command::Help(None)));
+        assert_eq!(
+            parse(&args("perft startpos 5")),
+            Ok(Command::Perft {
+                fen: "startpos".into(),
+                depth: 5
+            })
+        );
+        // An unquoted FEN arrives in pieces.
+        assert_eq!(
+            parse(&args("perft 4k3/8/8/8/8/8/8/4K2R w K - 0 1 3")),
+            Ok(Command::Perft {
+                fen: "4k3/8/8/8/8/8/8/4K2R w K - 0 1".into(),
+                depth: 3
+            })
+        );
+        assert_eq!(
+            parse(&args("analyze startpos --movetime 500 --threads 2")),
+            Ok(Command::Analyze {
+                fen: "startpos".into(),
+                depth: None,
+                movetime: Some(500),
+                threads: 2
+            })
+        );
+        assert_eq!(parse(&args("bench 3")), Ok(Command::Bench { depth: 3 }));
+        assert_eq!(
+            parse(&args("play --depth 2")),
+            Ok(Command::Play {
+                human: Side::White,
+                time_control: TimeControl::Depth(2),
+                pgn: None,
+                threads: 1
+            })
+        );
+        assert_eq!(
+            parse(&args("play --color black --clock 5+3 --pgn game.pgn")),
+            Ok(Command::Play {
+                human: Side::Black,
+                time_control: TimeControl::Clock {
+                    time: Duration::from_secs(300),
+                    increment: Duration::from_secs(3)
+                },
+                pgn: Some("game.pgn".into()),
+                threads: 1
+            })
+        );
+        assert_eq!(
+            parse(&args("epd testcases/depths.epd --depth 3 --threads 2")),
+            Ok(Command::Epd {
+                path: "testcases/depths.epd".into(),
+                max_depth: 3,
+                threads: 2
+            })
+        );
+        assert!(matches!(
+            parse(&args("epd testcases/depths.epd")),
+            Ok(Command::Epd {
+                max_depth: u64::MAX,
+                ..
+            })
+        ));
+        assert_eq!(
+            parse(&args("suite wac.epd --depth 8 --nodes 100000")),
+            Ok(Command::Suite {
+                path: "wac.epd".into(),
+                budget: Budget {
+                    time: None,
+                    depth: Some(8),
+                    nodes: Some(100000)
+                }
+            })
+        );
+        assert_eq!(
+            parse(&args("suite wac.epd")),
+            Ok(Command::Suite {
+                path: "wac.epd".into(),
+                budget: Budget::default()
+            })
+        );
+        assert_eq!(
+            parse(&args("book out.bin a.pgn b.pgn --ply 20 --min-score 50")),
+            Ok(Command::Book {
+                out: "out.bin".into(),
+                pgns: vec!["a.pgn".into(), "b.pgn".into()],
+                options: BuildOptions {
+                    max_ply: 20,
+                    min_score: 50,
+                    ..BuildOptions::default()
+                }
+            })
+        );
+        assert_eq!(
+            parse(&args("tbgen KQK KNBK --out tables")),
+            Ok(Command::Tbgen {
+                signatures: vec!["KQK".parse().unwrap(), "KBNK".parse().unwrap()],
+                out: "tables".into(),
+            })
+        );
+        assert!(matches!(
+            parse(&args("tbgen KRK")),
+            Ok(Command::Tbgen { out, .. }) if out == "."
+        ));
+        assert_eq!(
+            parse(&args("perft --help")),
+            Ok(Command::Help(Some("perft".into())))
+        );
+        assert_eq!(
+            parse(&args("help bench")),
+            Ok(Command::Help(Some("bench".into())))
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let usage = parse(&args("perft startpos")).unwrap_err();
+        assert!(usage.starts_with("usage: chess perft <fen> <depth>\n"));
+        assert!(parse(&args("perft nonsense 3")).is_err());
+        assert!(parse(&args("bench x")).is_err());
+        assert!(parse(&args("epd testcases/depths.epd 3")).is_err());
+        assert!(parse(&args("book out.bin")).is_err());
+        assert!(parse(&args("book out.bin a.pgn --min-games many")).is_err());
+        assert!(parse(&args("tbgen")).is_err());
+        assert!(parse(&args("tbgen KQRBK"))
+            .unwrap_err()
+            .starts_with("KQRBK has more than "));
+        assert!(parse(&args("tbgen KPKP"))
+            .unwrap_err()
+            .starts_with("KPKP has pawns on both sides\n\nusage: chess tbgen "));
+        assert!(parse(&args("play --color green")).is_err());
+        assert!(parse(&args("play --depth 3 --movetime 100")).is_err());
+        assert!(parse(&args("bench --depth 3"))
+            .unwrap_err()
+            .starts_with("unknown option --depth\n"));
+        assert!(parse(&args("analyze startpos --depth")).is_err());
+        assert!(parse(&args("analyze startpos --colour white"))
+            .unwrap_err()
+            .starts_with("unknown option --colour\n"));
+        assert!(parse(&args("frobnicate"))
+            .unwrap_err()
+            .starts_with("unknown command frobnicate\n"));
+    }
+}
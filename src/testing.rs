@@ -1,13 +1,5 @@
-use std::sync::{
-    atomic::{AtomicUsize, Ordering},
-    Arc,
-};
-
-use scoped_threadpool::Scope;
 use serde::Deserialize;
 
-use crate::{ab::SearchSettings, chess::board::Board};
-
 #[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -95,33 +87,67 @@ mod test {
     use std::{
         fs::File,
         io::{BufRead, BufReader},
-        sync::Arc,
-        time::Duration,
     };
 
-    use crate::{ab::SearchSettings, chess::board::Board};
+    use vampirc_uci::UciMove;
+
+    use crate::chess::{
+        board::Board,
+        moves::Move,
+        perft::{divide, perft_stats, PerftCase, PerftStats, PerftTable},
+    };
 
-    use super::{test_with_epd, Monitor, TestSuite};
+    use super::TestSuite;
 
+    /// Plays every legal move of every case and checks the FEN it leads to against the suite,
+    /// reporting every missing, extra and mismatching move before failing. The suites' move
+    /// names are matched with the lenient SAN parser, as they leave out some `x`s and mark
+    /// mates with `+`.
     fn run_test(file: &str) {
         let suite = TestSuite::from_json(&std::fs::read_to_string(file).unwrap()).unwrap();
 
         eprintln!("starting test suite {}", suite.description());
+        let mut failed = 0;
         for case in suite.test_cases() {
             eprintln!("running test case {}", case.start().description());
             let b = Board::from_fen(case.start().fen()).unwrap();
             assert_eq!(case.start().fen(), b.to_fen());
-            eprintln!("starting pos {}:\n{}", case.start().fen(), b);
 
-            let num_calc_moves = b.legal_moves().count();
-            let num_expected_moves = case.moves().len();
-            if num_calc_moves != num_expected_moves {
-                for m in b.legal_moves() {
-                    eprintln!(" ==> {}", m);
+            let mut errors = vec![];
+            let mut calculated: Vec<Move> = b.legal_moves().collect();
+            for expected in case.moves() {
+                let found = b
+                    .parse_san(expected.mv())
+                    .ok()
+                    .and_then(|m| calculated.iter().position(|&c| c == m));
+                let Some(i) = found else {
+                    errors.push(format!("missing {} ({})", expected.mv(), expected.fen()));
+                    continue;
+                };
+                let m = calculated.swap_remove(i);
+                let fen = b.clone().apply_move(&m).unwrap().to_fen();
+                if fen != expected.fen() {
+                    errors.push(format!(
+                        "mismatch {}: got {fen}, expected {}",
+                        b.san(&m),
+                        expected.fen()
+                    ));
+                }
+            }
+            for m in calculated {
+                let fen = b.clone().apply_move(&m).unwrap().to_fen();
+                errors.push(format!("extra {} ({fen})", b.san(&m)));
+            }
+
+            if !errors.is_empty() {
+                failed += 1;
+                eprintln!("starting pos {}:\n{}", case.start().fen(), b);
+                for error in errors {
+                    eprintln!(" ==> {error}");
                 }
             }
-            assert_eq!(num_calc_moves, num_expected_moves);
         }
+        assert_eq!(failed, 0, "{failed} failing cases in {file}");
     }
 
     #[test]
@@ -160,130 +186,35 @@ mod test {
         run_test(path);
     }
 
-    extern crate test;
-    use test::Bencher;
-
-    #[allow(soft_unstable)]
-    #[bench]
-    fn bench_movegen(b: &mut Bencher) {
-        let settings = SearchSettings::divide(4);
-        let board =
-            Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
-        b.iter(|| {
-            let res = board.alphabeta(&settings, true);
-        });
-        let res = board.alphabeta(&settings, true);
-        eprintln!("total: {}", res.count);
-    }
-
-    const MAX_DEPTH: u32 = 5;
+    const MAX_DEPTH: u64 = 5;
     #[test]
     fn test_depth_many_up_to() {
         let file = "testcases/depths.epd";
         let lines = BufReader::new(File::open(file).unwrap()).lines();
-
-        //let file2 = "testcases/fischer.epd";
-        //let _lines2 = BufReader::new(File::open(file2).unwrap()).lines();
-        let mut tp = scoped_threadpool::Pool::new(
-            std::thread::available_parallelism()
-                .unwrap()
-                .try_into()
-                .map(|x: usize| (x * 20).try_into().unwrap())
-                .unwrap(),
-        );
-
-        let monitor = Arc::new(Monitor::default());
-        tp.scoped(|scope| {
-            for line in lines {
-                test_with_epd(&monitor, scope, &line.unwrap(), MAX_DEPTH);
-            }
-            while !monitor.finished() {
-                std::thread::sleep(Duration::from_millis(1000));
-                eprint!(
-                    "{} completed, {} remaining\r",
-                    monitor.completed(),
-                    monitor.remaining()
-                );
-            }
-        });
-    }
-}
-
-#[derive(Debug, Default)]
-struct Monitor {
-    spawned: AtomicUsize,
-    done: AtomicUsize,
-}
-
-impl Monitor {
-    pub fn done(&self) {
-        self.done.fetch_add(1, Ordering::SeqCst);
-    }
-
-    pub fn spawn(&self) {
-        self.spawned.fetch_add(1, Ordering::SeqCst);
-    }
-
-    #[allow(dead_code)]
-    pub fn remaining(&self) -> usize {
-        self.spawned.load(Ordering::SeqCst) - self.done.load(Ordering::SeqCst)
-    }
-
-    #[allow(dead_code)]
-    pub fn completed(&self) -> usize {
-        self.done.load(Ordering::SeqCst)
-    }
-
-    #[allow(dead_code)]
-    pub fn finished(&self) -> bool {
-        self.spawned.load(Ordering::SeqCst) == self.done.load(Ordering::SeqCst)
-    }
-}
-
-#[allow(dead_code)]
-fn test_with_epd(mon: &Arc<Monitor>, scope: &Scope, epd: &str, max: u32) {
-    let mut splits = epd.split(';');
-    let fen = splits.next().unwrap().trim().to_owned();
-
-    //eprintln!("initial position: {}", fen);
-    let board = Board::from_fen(&fen).unwrap();
-    assert_eq!(fen, board.to_fen());
-    for check in splits {
-        let label = check.split(' ').next().unwrap().trim();
-        if label.starts_with('D') {
-            let depth: u32 = label
-                .chars()
-                .skip(1)
-                .collect::<String>()
-                .trim()
-                .parse()
-                .unwrap();
-            let nodes: u64 = check.split(' ').nth(1).unwrap().trim().parse().unwrap();
-
-            if depth > max {
-                break;
-            }
-
-            let fen = fen.clone();
-            let board = board.clone();
-            mon.spawn();
-            let mon = mon.clone();
-            scope.execute(move || {
-                let settings = SearchSettings::divide(depth.into());
-                let res = board.alphabeta(&settings, true);
-                eprintln!("{fen} depth {depth} expected {nodes} got {}", res.count);
-                if res.count != nodes {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let table = PerftTable::new(64);
+
+        let mut failed = 0;
+        for line in lines {
+            let case = PerftCase::parse(&line.unwrap()).unwrap();
+            let board = Board::from_fen(&case.fen).unwrap();
+            assert_eq!(case.fen, board.to_fen());
+            for outcome in case.run(MAX_DEPTH, threads, Some(&table)).unwrap() {
+                eprintln!("{} {outcome}", case.fen);
+                if !outcome.passed() {
+                    failed += 1;
                     eprintln!("fail, here is some info:");
                     eprintln!("{board}");
-                    for m in board.legal_moves() {
-                        let board = board.clone().apply_move(&m).unwrap();
-                        let nres = board.alphabeta(&settings, true);
-                        eprintln!("{m} count: {}", nres.count);
+                    eprintln!("{}", PerftStats::header());
+                    for depth in 1..=outcome.depth {
+                        eprintln!("{}", perft_stats(&board, depth).row(depth));
+                    }
+                    for (m, count) in divide(&board, outcome.depth) {
+                        eprintln!("{} count: {count}", UciMove::from(m));
                     }
                 }
-                assert_eq!(res.count, nodes);
-                mon.done();
-            });
+            }
         }
+        assert_eq!(failed, 0);
     }
 }
@@ -0,0 +1,161 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// How a stored score relates to the true score of the position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    /// The search failed high; the true score is at least this.
+    Lower,
+    /// The search failed low; the true score is at most this.
+    Upper,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Entry {
+    pub score: f32,
+    pub depth: u8,
+    pub bound: Bound,
+    /// Index of the best child in the order `AlphaBeta::children` produces them.
+    pub best: Option<u16>,
+}
+
+impl Entry {
+    fn pack(&self) -> u64 {
+        let bound = match self.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        };
+        self.score.to_bits() as u64
+            | (self.depth as u64) << 32
+            | bound << 40
+            | (self.best.unwrap_or(u16::MAX) as u64) << 48
+    }
+
+    fn unpack(data: u64) -> Self {
+        let best = (data >> 48) as u16;
+        Self {
+            score: f32::from_bits(data as u32),
+            depth: (data >> 32) as u8,
+            bound: match (data >> 40) & 3 {
+                0 => Bound::Exact,
+                1 => Bound::Lower,
+                _ => Bound::Upper,
+            },
+            best: (best != u16::MAX).then_some(best),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Slot {
+    /// The position's key xor'ed with the data, so a slot torn by two threads writing at once
+    /// reads as a miss instead of as another position's entry.
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+/// A hash table of search results, shared by every thread searching a position. It is lock
+/// free; concurrent writes to one slot can lose an entry but never corrupt one.
+pub struct TranspositionTable {
+    slots: Box<[Slot]>,
+}
+
+impl TranspositionTable {
+    pub fn new(megabytes: usize) -> Self {
+        let len = (megabytes.max(1) << 20) / std::mem::size_of::<Slot>();
+        Self {
+            slots: (0..len).map(|_| Slot::default()).collect(),
+        }
+    }
+
+    pub fn megabytes(&self) -> usize {
+        (self.slots.len() * std::mem::size_of::<Slot>()) >> 20
+    }
+
+    fn slot(&self, key: u64) -> &Slot {
+        &self.slots[(key % self.slots.len() as u64) as usize]
+    }
+
+    pub fn probe(&self, key: u64) -> Option<Entry> {
+        let slot = self.slot(key);
+        let data = slot.data.load(Ordering::Relaxed);
+        if slot.key.load(Ordering::Relaxed) ^ data != key || data == 0 {
+            return None;
+        }
+        Some(Entry::unpack(data))
+    }
+
+    /// Stores an entry, unless the slot holds a deeper result for the same position.
+    pub fn store(&self, key: u64, entry: Entry) {
+        let slot = self.slot(key);
+        if let Some(old) = self.probe(key) {
+            if old.depth > entry.depth {
+                return;
+            }
+        }
+        let data = entry.pack();
+        slot.data.store(data, Ordering::Relaxed);
+        slot.key.store(key ^ data, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self) {
+        for slot in self.slots.iter() {
+            slot.key.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// How full the table is in permille, estimated from the first thousand slots.
+    pub fn hashfull(&self) -> u16 {
+        let sample = &self.slots[..self.slots.len().min(1000)];
+        let used = sample
+            .iter()
+            .filter(|slot| slot.data.load(Ordering::Relaxed) != 0)
+            .count();
+        (used * 1000 / sample.len()) as u16
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Bound, Entry, TranspositionTable};
+
+    #[test]
+    fn test_store_probe() {
+        let tt = TranspositionTable::new(1);
+        assert_eq!(tt.megabytes(), 1);
+        let entry = Entry {
+            score: -1.25,
+            depth: 7,
+            bound: Bound::Lower,
+            best: Some(3),
+        };
+        tt.store(42, entry);
+        assert_eq!(tt.probe(42), Some(entry));
+        assert_eq!(tt.probe(43), None);
+
+        // Shallower results don't replace deeper ones, but deeper and equal ones do.
+        tt.store(42, Entry { depth: 3, ..entry });
+        assert_eq!(tt.probe(42), Some(entry));
+        let deeper = Entry {
+            depth: 9,
+            bound: Bound::Exact,
+            best: None,
+            ..entry
+        };
+        tt.store(42, deeper);
+        assert_eq!(tt.probe(42), Some(deeper));
+
+        // Another position in the same slot pushes it out.
+        let other = 42 + tt.slots.len() as u64;
+        tt.store(other, Entry { depth: 1, ..entry });
+        assert_eq!(tt.probe(42), None);
+        assert_eq!(tt.probe(other).unwrap().depth, 1);
+
+        assert!(tt.hashfull() > 0);
+        tt.clear();
+        assert_eq!(tt.probe(other), None);
+        assert_eq!(tt.hashfull(), 0);
+    }
+}